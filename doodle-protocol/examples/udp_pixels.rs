@@ -0,0 +1,112 @@
+// file: udp_pixels.rs
+// desc: streams pixels to the Pico over UDP, one per line of stdin:
+//
+//   "x y" sets a pixel, "x y state" gives it a state byte (see PixelState),
+//   "clear" clears the canvas
+//
+// e.g. `cargo run --example udp_pixels -- 192.168.4.1:4444 < drawing.txt`
+
+use std::io::BufRead;
+use std::net::UdpSocket;
+use std::time::{Duration, Instant};
+
+use doodle_protocol::{AckWatcher, Capabilities, DrawMessage, Message, Packet, PixelState, MAX_MESSAGE_LEN};
+
+// How long to wait on the Pico before asking again, while it has no credit left
+const CREDIT_WAIT: Duration = Duration::from_millis(50);
+
+struct Link {
+    socket: UdpSocket,
+    acks: AckWatcher,
+    started: Instant,
+}
+
+impl Link {
+    fn now_ms(&self) -> u64 {
+        self.started.elapsed().as_millis() as u64
+    }
+
+    fn send(&mut self, message: Message, pixels: u8) -> std::io::Result<()> {
+        let seq = self.acks.send(self.now_ms(), false, pixels);
+        let packet = Packet { seq, message };
+        let mut bytes = [0u8; MAX_MESSAGE_LEN];
+        let len = packet.encode(&mut bytes).expect("messages fit MAX_MESSAGE_LEN");
+        self.socket.send(&bytes[..len])?;
+        Ok(())
+    }
+
+    // Handle whatever the Pico sent, waiting up to `timeout` for the first of it
+    fn receive(&mut self, timeout: Duration) -> std::io::Result<()> {
+        if timeout.is_zero() {
+            self.socket.set_nonblocking(true)?;
+        } else {
+            self.socket.set_read_timeout(Some(timeout))?;
+        }
+        let mut bytes = [0u8; MAX_MESSAGE_LEN];
+        while let Ok(len) = self.socket.recv(&mut bytes) {
+            match Packet::decode(&bytes[..len]).map(|packet| packet.message) {
+                Ok(Message::Hello { client_id, width, height, .. }) => {
+                    eprintln!("Drawing on a {}x{} canvas as #{}", width, height, client_id);
+                }
+                Ok(Message::Ack { acked, latest, credit }) => {
+                    if self.acks.acked(self.now_ms(), acked, latest, credit) {
+                        eprintln!("The Pico missed messages {}..{}", acked.wrapping_add(1), latest);
+                    }
+                }
+                Ok(_) => {}
+                Err(e) => eprintln!("Ignoring a datagram from the Pico: {:?}", e),
+            }
+            self.socket.set_nonblocking(true)?;
+        }
+        self.socket.set_nonblocking(false)
+    }
+
+    // Hold off until the Pico has room, asking with a Ping it answers with an Ack
+    fn wait_for_credit(&mut self, pixels: u16) -> std::io::Result<()> {
+        while self.acks.credit().is_some_and(|credit| credit < pixels) {
+            self.send(Message::Ping(self.now_ms() as u32), 0)?;
+            self.receive(CREDIT_WAIT)?;
+        }
+        Ok(())
+    }
+}
+
+fn parse(line: &str) -> Option<DrawMessage> {
+    if line == "clear" {
+        return Some(DrawMessage::Clear);
+    }
+    let mut numbers = line.split_whitespace().map(str::parse::<u8>);
+    let (x, y) = (numbers.next()?.ok()?, numbers.next()?.ok()?);
+    let state = match numbers.next() {
+        Some(state) => PixelState::from_byte(state.ok()?)?,
+        None => PixelState::Set,
+    };
+    Some(DrawMessage::Pixel { x, y, state })
+}
+
+fn main() -> std::io::Result<()> {
+    let Some(pico) = std::env::args().nth(1) else {
+        eprintln!("usage: udp_pixels <pico address:port> < pixels");
+        std::process::exit(2);
+    };
+
+    let socket = UdpSocket::bind("0.0.0.0:0")?;
+    socket.connect(&pico)?;
+    let mut link = Link { socket, acks: AckWatcher::new(), started: Instant::now() };
+
+    let hello = Message::Hello { client_id: 0, width: 0, height: 0, capabilities: Capabilities::GRAYSCALE };
+    link.send(hello, 0)?;
+    link.receive(Duration::from_millis(500))?;
+
+    for line in std::io::stdin().lock().lines() {
+        let line = line?;
+        let Some(message) = parse(line.trim()) else {
+            eprintln!("Skipping \"{}\"", line);
+            continue;
+        };
+        link.wait_for_credit(1)?;
+        link.send(Message::Draw { author: 0, message }, 1)?;
+        link.receive(Duration::ZERO)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,272 @@
+// file: cbor.rs
+// desc: packets as CBOR, for tools in other languages that would rather use a
+// CBOR library than parse the binary layout
+
+use core::convert::Infallible;
+
+use minicbor::encode::write::{Cursor, Write};
+use minicbor::{Decoder, Encoder};
+
+use crate::{
+    check_stroke, Capabilities, DecodeError, DrawMessage, EncodeError, Message, MessageType, Packet, PixelState, Role,
+    Status, VERSION,
+};
+
+// Counts what an encoding would take, to report how much room it needs
+struct Counter(usize);
+
+impl Write for Counter {
+    type Error = Infallible;
+
+    fn write_all(&mut self, buf: &[u8]) -> Result<(), Self::Error> {
+        self.0 += buf.len();
+        Ok(())
+    }
+}
+
+impl From<minicbor::decode::Error> for DecodeError {
+    fn from(_: minicbor::decode::Error) -> Self {
+        DecodeError::Cbor
+    }
+}
+
+/// True if `bytes` start like a CBOR packet. A binary packet starts with its
+/// version, which is never the start of a CBOR array
+pub fn is_cbor(bytes: &[u8]) -> bool {
+    bytes.first().is_some_and(|byte| byte & 0xe0 == 0x80)
+}
+
+/// Write `packet` to the start of `out` as the CBOR array `[version, type, seq,
+/// fields...]`, returning its length. The fields are those of the binary payload,
+/// in the same order, with Frame runs and Stroke pixels as byte strings. There
+/// is no checksum, CBOR is only spoken over WebSocket, which has its own
+pub fn encode_cbor(packet: &Packet, out: &mut [u8]) -> Result<usize, EncodeError> {
+    let mut encoder = Encoder::new(Cursor::new(out));
+    if write_packet(&mut encoder, packet).is_err() {
+        let mut counter = Encoder::new(Counter(0));
+        let _ = write_packet(&mut counter, packet);
+        return Err(EncodeError::BufferTooSmall { needed: counter.writer().0 });
+    }
+    Ok(encoder.writer().position())
+}
+
+fn write_packet<W: Write>(e: &mut Encoder<W>, packet: &Packet) -> Result<(), minicbor::encode::Error<W::Error>> {
+    let message = packet.message;
+    e.array(3 + fields(&message))?
+        .u8(VERSION)?
+        .u8(message.message_type() as u8)?
+        .u16(packet.seq)?;
+    match message {
+        Message::Hello { client_id, width, height, capabilities } => {
+            e.u8(client_id)?.u8(width)?.u8(height)?.u8(capabilities.0)?
+        }
+        Message::Draw { author, message: DrawMessage::Pixel { x, y, state } } => {
+            e.u8(author)?.u8(x)?.u8(y)?.u8(state.to_byte())?
+        }
+        Message::Draw { author, message: DrawMessage::Clear } => e.u8(author)?,
+        Message::Draw { author, message: DrawMessage::ClearRect { x, y, width, height } } => {
+            e.u8(author)?.u8(x)?.u8(y)?.u8(width)?.u8(height)?
+        }
+        Message::Frame { width, height, data } => e.u8(width)?.u8(height)?.bytes(data)?,
+        Message::Prediction { class, confidence } => e.u8(class)?.u8(confidence)?,
+        Message::Ping(token) => e.u32(token)?,
+        Message::Ack { acked, latest, credit } => e.u16(acked)?.u16(latest)?.u8(credit)?,
+        Message::Stroke { author, pixels } => e.u8(author)?.bytes(pixels)?,
+        Message::Status(status) => e
+            .u16(status.pixels_on)?
+            .u32(status.uptime_secs)?
+            .i8(status.rssi)?
+            .u8(status.clients)?
+            .u32(status.corrupt_messages)?
+            .u8(status.channel)?
+            .u16(status.joins)?
+            .u32(status.socket_errors)?
+            .u32(status.dropped_events)?,
+        Message::Register { id, role } => e.u32(id)?.u8(role.to_byte())?,
+        Message::GetFrame => e,
+        Message::Echo { sent_ms, pico_us } => e.u32(sent_ms)?.u16(pico_us)?,
+    };
+    Ok(())
+}
+
+// How many fields follow the header
+fn fields(message: &Message) -> u64 {
+    match message {
+        Message::Hello { .. } => 4,
+        Message::Draw { message: DrawMessage::Pixel { .. }, .. } => 4,
+        Message::Draw { message: DrawMessage::Clear, .. } => 1,
+        Message::Draw { message: DrawMessage::ClearRect { .. }, .. } => 5,
+        Message::Frame { .. } => 3,
+        Message::Prediction { .. } => 2,
+        Message::Ping(_) => 1,
+        Message::Ack { .. } => 3,
+        Message::Stroke { .. } => 2,
+        Message::Status(_) => 9,
+        Message::Register { .. } => 2,
+        Message::GetFrame => 0,
+        Message::Echo { .. } => 2,
+    }
+}
+
+/// Read a packet written by `encode_cbor`, checked like a binary one
+pub fn decode_cbor(bytes: &[u8]) -> Result<Packet<'_>, DecodeError> {
+    let mut d = Decoder::new(bytes);
+    // An indefinite length array isn't worth the trouble for a few numbers
+    let len = d.array()?.ok_or(DecodeError::Cbor)?;
+    let version = d.u8()?;
+    if version != VERSION {
+        return Err(DecodeError::Version(version));
+    }
+    let kind = d.u8()?;
+    let kind = MessageType::from_byte(kind).ok_or(DecodeError::UnknownType(kind))?;
+    let seq = d.u16()?;
+
+    let message = read_message(&mut d, kind)?;
+    let expected = 3 + fields(&message);
+    if len != expected {
+        return Err(DecodeError::Length { expected: expected as usize, found: len as usize });
+    }
+    if d.position() != bytes.len() {
+        return Err(DecodeError::Cbor);
+    }
+    Ok(Packet { seq, message })
+}
+
+fn read_message<'a>(d: &mut Decoder<'a>, kind: MessageType) -> Result<Message<'a>, DecodeError> {
+    Ok(match kind {
+        MessageType::Hello => Message::Hello {
+            client_id: d.u8()?,
+            width: d.u8()?,
+            height: d.u8()?,
+            capabilities: Capabilities(d.u8()?),
+        },
+        MessageType::Pixel => {
+            let (author, x, y, state) = (d.u8()?, d.u8()?, d.u8()?, d.u8()?);
+            let state = PixelState::from_byte(state).ok_or(DecodeError::State(state))?;
+            Message::Draw { author, message: DrawMessage::Pixel { x, y, state } }
+        }
+        MessageType::Clear => Message::Draw { author: d.u8()?, message: DrawMessage::Clear },
+        MessageType::ClearRect => Message::Draw {
+            author: d.u8()?,
+            message: DrawMessage::ClearRect { x: d.u8()?, y: d.u8()?, width: d.u8()?, height: d.u8()? },
+        },
+        MessageType::Frame => Message::Frame { width: d.u8()?, height: d.u8()?, data: d.bytes()? },
+        MessageType::Prediction => Message::Prediction { class: d.u8()?, confidence: d.u8()? },
+        MessageType::Ping => Message::Ping(d.u32()?),
+        MessageType::Ack => Message::Ack { acked: d.u16()?, latest: d.u16()?, credit: d.u8()? },
+        MessageType::Stroke => {
+            let author = d.u8()?;
+            let pixels = d.bytes()?;
+            check_stroke(pixels)?;
+            Message::Stroke { author, pixels }
+        }
+        MessageType::Status => Message::Status(Status {
+            pixels_on: d.u16()?,
+            uptime_secs: d.u32()?,
+            rssi: d.i8()?,
+            clients: d.u8()?,
+            corrupt_messages: d.u32()?,
+            channel: d.u8()?,
+            joins: d.u16()?,
+            socket_errors: d.u32()?,
+            dropped_events: d.u32()?,
+        }),
+        MessageType::Register => {
+            let id = d.u32()?;
+            let role = d.u8()?;
+            Message::Register { id, role: Role::from_byte(role).ok_or(DecodeError::Role(role))? }
+        }
+        MessageType::GetFrame => Message::GetFrame,
+        MessageType::Echo => Message::Echo { sent_ms: d.u32()?, pico_us: d.u16()? },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MAX_CONTROL_LEN;
+
+    fn round_trip(message: Message) {
+        let packet = Packet { seq: 0x1234, message };
+        let mut buffer = [0u8; 64];
+        let len = encode_cbor(&packet, &mut buffer).unwrap();
+        assert!(is_cbor(&buffer[..len]));
+        assert_eq!(decode_cbor(&buffer[..len]), Ok(packet));
+    }
+
+    #[test]
+    fn messages_round_trip() {
+        round_trip(Message::Hello { client_id: 3, width: 128, height: 48, capabilities: Capabilities::CBOR });
+        round_trip(Message::Draw { author: 2, message: DrawMessage::Pixel { x: 10, y: 20, state: PixelState::Gray(7) } });
+        round_trip(Message::Draw { author: 2, message: DrawMessage::Clear });
+        round_trip(Message::Draw { author: 2, message: DrawMessage::ClearRect { x: 1, y: 2, width: 3, height: 4 } });
+        round_trip(Message::Frame { width: 4, height: 2, data: &[0, 3, 5] });
+        round_trip(Message::Prediction { class: 7, confidence: 200 });
+        round_trip(Message::Ping(0xdead_beef));
+        round_trip(Message::Ack { acked: 7, latest: 9, credit: 21 });
+        round_trip(Message::Stroke { author: 1, pixels: &[1, 2, 1, 3, 4, 0] });
+        round_trip(Message::Status(Status {
+            pixels_on: 6144,
+            uptime_secs: 86_400,
+            rssi: -67,
+            clients: 3,
+            corrupt_messages: 70_000,
+            channel: 6,
+            joins: 1,
+            socket_errors: 0,
+            dropped_events: 12,
+        }));
+        round_trip(Message::Register { id: 0x1234_5678, role: Role::Spectator });
+        round_trip(Message::GetFrame);
+        round_trip(Message::Echo { sent_ms: 0xdead_beef, pico_us: 850 });
+    }
+
+    #[test]
+    fn largest_status_fits_max_control_len() {
+        let status = Status {
+            pixels_on: u16::MAX,
+            uptime_secs: u32::MAX,
+            rssi: i8::MIN,
+            clients: u8::MAX,
+            corrupt_messages: u32::MAX,
+            channel: u8::MAX,
+            joins: u16::MAX,
+            socket_errors: u32::MAX,
+            dropped_events: u32::MAX,
+        };
+        let packet = Packet { seq: u16::MAX, message: Message::Status(status) };
+        let mut buffer = [0u8; MAX_CONTROL_LEN];
+        assert!(encode_cbor(&packet, &mut buffer).is_ok());
+        assert!(packet.encode(&mut buffer).is_ok());
+    }
+
+    #[test]
+    fn pixels_are_plain_cbor() {
+        let pixel = Packet {
+            seq: 1,
+            message: Message::Draw { author: 0, message: DrawMessage::Pixel { x: 10, y: 20, state: PixelState::Set } },
+        };
+        let mut buffer = [0u8; 16];
+        let len = encode_cbor(&pixel, &mut buffer).unwrap();
+        // [7, 2, 1, 0, 10, 20, 1]
+        assert_eq!(buffer[..len], [0x87, VERSION, 2, 1, 0, 10, 20, 1]);
+        assert_eq!(encode_cbor(&pixel, &mut buffer[..4]), Err(EncodeError::BufferTooSmall { needed: 8 }));
+    }
+
+    #[test]
+    fn binary_packets_are_not_cbor() {
+        let mut buffer = [0u8; 16];
+        let len = Packet { seq: 0, message: Message::GetFrame }.encode(&mut buffer).unwrap();
+        assert!(!is_cbor(&buffer[..len]));
+    }
+
+    #[test]
+    fn rejects_bad_packets() {
+        // A field too many, something after the array, and a field missing
+        assert_eq!(decode_cbor(&[0x86, VERSION, 3, 0, 1, 2]), Err(DecodeError::Length { expected: 4, found: 6 }));
+        assert_eq!(decode_cbor(&[0x84, VERSION, 3, 0, 1, 0]), Err(DecodeError::Cbor));
+        assert_eq!(decode_cbor(&[0x84, VERSION, 2, 0, 0]), Err(DecodeError::Cbor));
+        assert_eq!(decode_cbor(&[0x84, VERSION + 1, 3, 0, 1]), Err(DecodeError::Version(VERSION + 1)));
+        assert_eq!(decode_cbor(&[0x85, VERSION, 8, 0, 0, 0x43, 255, 255, 2]), Err(DecodeError::State(2)));
+    }
+}
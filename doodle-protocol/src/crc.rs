@@ -0,0 +1,29 @@
+// file: crc.rs
+// desc: CRC8 guarding every message against corruption on the way
+
+// CRC-8/SMBUS, x^8 + x^2 + x + 1. Bitwise, messages are short enough that a
+// lookup table isn't worth its flash
+const POLYNOMIAL: u8 = 0x07;
+
+pub fn crc8(bytes: &[u8]) -> u8 {
+    bytes.iter().fold(0, |crc, byte| {
+        (0..8).fold(crc ^ byte, |crc, _| {
+            if crc & 0x80 != 0 {
+                (crc << 1) ^ POLYNOMIAL
+            } else {
+                crc << 1
+            }
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_the_standard_check_value() {
+        assert_eq!(crc8(b"123456789"), 0xf4);
+        assert_eq!(crc8(&[]), 0);
+    }
+}
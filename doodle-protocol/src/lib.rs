@@ -0,0 +1,1204 @@
+// file: lib.rs
+// desc: binary messages shared by the webapp and the Pico firmware
+
+#![no_std]
+
+#[cfg(feature = "cbor")]
+mod cbor;
+mod crc;
+mod lz;
+mod sequence;
+mod stream;
+
+#[cfg(feature = "cbor")]
+pub use cbor::{decode_cbor, encode_cbor, is_cbor};
+pub use crc::crc8;
+pub use sequence::{AckWatcher, SequenceTracker};
+pub use stream::DrawStream;
+
+use core::ops::Deref;
+
+/// Every binary message starts with `[VERSION, message type, sequence number]`
+/// and ends with a CRC8 of everything before it. Bump the version whenever an
+/// existing message changes shape, so old peers reject it instead of misreading it
+pub const VERSION: u8 = 8;
+pub const HEADER_LEN: usize = 4;
+pub const CHECKSUM_LEN: usize = 1;
+/// Longest message either side accepts. Frames that would be longer are sent as
+/// a clear and one Pixel per inked cell instead
+pub const MAX_MESSAGE_LEN: usize = 1024;
+/// Longest any message but Frame and Stroke gets, binary or CBOR: a Status with
+/// every counter near its limit in CBOR
+pub const MAX_CONTROL_LEN: usize = 40;
+
+/// Most pixels one Stroke carries, a brush stamp fits in one
+pub const MAX_STROKE_PIXELS: usize = 32;
+
+/// Author id of drawing the Pico makes itself, e.g. an idle clear. Browsers get ids from 1
+pub const DEVICE_AUTHOR: u8 = 0;
+
+/// Optional features a side supports, exchanged in Hello so each side only
+/// sends what the other understands
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Capabilities(pub u8);
+
+impl Capabilities {
+    pub const NONE: Capabilities = Capabilities(0);
+    /// Pixels with an intensity rather than just on and off, see `PixelState::Gray`
+    pub const GRAYSCALE: Capabilities = Capabilities(1 << 0);
+    /// Several pixels in one message
+    pub const BATCHING: Capabilities = Capabilities(1 << 1);
+    /// Recognizes the digit itself instead of waiting for a Prediction message
+    pub const INFERENCE: Capabilities = Capabilities(1 << 2);
+    /// Takes packets compressed with `compress_packet`
+    pub const COMPRESSION: Capabilities = Capabilities(1 << 3);
+    /// Takes packets encoded with `encode_cbor`, and would rather get them too
+    pub const CBOR: Capabilities = Capabilities(1 << 4);
+
+    pub const fn union(self, other: Capabilities) -> Capabilities {
+        Capabilities(self.0 | other.0)
+    }
+
+    /// What both sides support, given the other side's capabilities
+    pub const fn shared(self, other: Capabilities) -> Capabilities {
+        Capabilities(self.0 & other.0)
+    }
+
+    pub const fn contains(self, other: Capabilities) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+// Coordinates no canvas reaches, marking a clear in the compact form
+const CLEAR_BYTES: [u8; 3] = [255, 255, 2];
+// State byte of a compact ClearRect, its width and height follow
+const CLEAR_RECT_STATE: u8 = 3;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DecodeError {
+    // Shorter than the header or the message type's payload
+    Truncated,
+    Version(u8),
+    UnknownType(u8),
+    Length { expected: usize, found: usize },
+    // Not a PixelState, or a compact form that doesn't belong
+    State(u8),
+    // The message was damaged on the way
+    Checksum,
+    // Not a Role
+    Role(u8),
+    // A compressed payload that doesn't decompress, or decompresses to too much
+    Compression,
+    // Not CBOR, or CBOR not shaped like a packet
+    Cbor,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EncodeError {
+    BufferTooSmall { needed: usize },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum MessageType {
+    Hello = 1,
+    Pixel = 2,
+    Clear = 3,
+    Frame = 4,
+    Prediction = 5,
+    Ping = 6,
+    Ack = 7,
+    Stroke = 8,
+    Status = 9,
+    ClearRect = 10,
+    Register = 11,
+    GetFrame = 12,
+    Echo = 13,
+}
+
+impl MessageType {
+    const ALL: [MessageType; 13] = [
+        MessageType::Hello,
+        MessageType::Pixel,
+        MessageType::Clear,
+        MessageType::Frame,
+        MessageType::Prediction,
+        MessageType::Ping,
+        MessageType::Ack,
+        MessageType::Stroke,
+        MessageType::Status,
+        MessageType::ClearRect,
+        MessageType::Register,
+        MessageType::GetFrame,
+        MessageType::Echo,
+    ];
+
+    pub fn from_byte(byte: u8) -> Option<Self> {
+        Self::ALL.into_iter().find(|kind| *kind as u8 == byte)
+    }
+}
+
+// State bytes from here on are gray, the low 4 bits hold the level
+const GRAY_STATE: u8 = 0x10;
+
+/// Levels a gray pixel can have, from 0 (off) to GRAY_LEVELS - 1 (fully on)
+pub const GRAY_LEVELS: u8 = 16;
+
+// 4x4 ordered dither thresholds, spread out so any level looks even
+const BAYER: [[u8; 4]; 4] = [[0, 8, 2, 10], [12, 4, 14, 6], [3, 11, 1, 9], [15, 7, 13, 5]];
+
+/// What a pixel message does to its pixel
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PixelState {
+    Clear,
+    Set,
+    Toggle,
+    // An intensity below GRAY_LEVELS, only sent to peers with GRAYSCALE.
+    // Monochrome displays dither it
+    Gray(u8),
+}
+
+impl PixelState {
+    pub fn from_byte(byte: u8) -> Option<Self> {
+        match byte {
+            0 => Some(PixelState::Clear),
+            1 => Some(PixelState::Set),
+            2 => Some(PixelState::Toggle),
+            GRAY_STATE.. if byte < GRAY_STATE + GRAY_LEVELS => Some(PixelState::Gray(byte - GRAY_STATE)),
+            _ => None,
+        }
+    }
+
+    pub fn to_byte(self) -> u8 {
+        match self {
+            PixelState::Clear => 0,
+            PixelState::Set => 1,
+            PixelState::Toggle => 2,
+            PixelState::Gray(level) => GRAY_STATE + level.min(GRAY_LEVELS - 1),
+        }
+    }
+
+    /// The pixel at (x, y) after this state is applied to it, where `on` is how it was
+    pub fn apply(self, on: bool, x: usize, y: usize) -> bool {
+        match self {
+            PixelState::Clear => false,
+            PixelState::Set => true,
+            PixelState::Toggle => !on,
+            PixelState::Gray(level) => dithered(level, x, y),
+        }
+    }
+}
+
+/// Whether a pixel of gray `level` at (x, y) is on, once dithered to black and white.
+/// About level / 15 of any 4x4 block is on, none at level 0 and all at level 15
+pub fn dithered(level: u8, x: usize, y: usize) -> bool {
+    let level = level.min(GRAY_LEVELS - 1) as u16;
+    // Both on a 0 to 255 scale, thresholds in the middle of their step
+    level * 17 > BAYER[y % 4][x % 4] as u16 * 16 + 8
+}
+
+impl From<bool> for PixelState {
+    fn from(on: bool) -> Self {
+        if on { PixelState::Set } else { PixelState::Clear }
+    }
+}
+
+/// A drawing change. Its compact `[x, y, state]` form is what the firmware
+/// queues for the display, a ClearRect adds its size: `[x, y, 3, width, height]`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DrawMessage {
+    // (255, 255) is never on a canvas, a toggle there would read as a Clear
+    Pixel { x: u8, y: u8, state: PixelState },
+    Clear,
+    // Turns off every pixel from (x, y) to (x + width - 1, y + height - 1)
+    ClearRect { x: u8, y: u8, width: u8, height: u8 },
+}
+
+impl DrawMessage {
+    /// Length of a compact pixel or clear
+    pub const LEN: usize = 3;
+    /// Length of the longest compact form, a ClearRect's
+    pub const MAX_LEN: usize = 5;
+
+    /// Length of the compact form with this state byte
+    pub fn compact_len(state: u8) -> usize {
+        if state == CLEAR_RECT_STATE { Self::MAX_LEN } else { Self::LEN }
+    }
+
+    pub fn encode(&self) -> Compact {
+        match *self {
+            DrawMessage::Pixel { x, y, state } => Compact::new(&[x, y, state.to_byte()]),
+            DrawMessage::Clear => Compact::new(&CLEAR_BYTES),
+            DrawMessage::ClearRect { x, y, width, height } => {
+                Compact::new(&[x, y, CLEAR_RECT_STATE, width, height])
+            }
+        }
+    }
+
+    /// The same change without gray, for peers that only know on and off
+    pub fn monochrome(self) -> Self {
+        match self {
+            DrawMessage::Pixel { x, y, state: PixelState::Gray(level) } => DrawMessage::Pixel {
+                x,
+                y,
+                state: PixelState::from(dithered(level, x as usize, y as usize)),
+            },
+            message => message,
+        }
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self, DecodeError> {
+        let expected = match bytes {
+            [_, _, state, ..] => Self::compact_len(*state),
+            _ => Self::LEN,
+        };
+        if bytes.len() != expected {
+            return Err(DecodeError::Length { expected, found: bytes.len() });
+        }
+        match *bytes {
+            [255, 255, 2] => Ok(DrawMessage::Clear),
+            [x, y, CLEAR_RECT_STATE, width, height] => Ok(DrawMessage::ClearRect { x, y, width, height }),
+            [x, y, state] => match PixelState::from_byte(state) {
+                Some(state) => Ok(DrawMessage::Pixel { x, y, state }),
+                None => Err(DecodeError::State(state)),
+            },
+            _ => Err(DecodeError::Truncated),
+        }
+    }
+}
+
+/// The compact form of a DrawMessage, 3 bytes or 5 for a ClearRect
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Compact {
+    bytes: [u8; DrawMessage::MAX_LEN],
+    len: usize,
+}
+
+impl Compact {
+    fn new(bytes: &[u8]) -> Self {
+        let mut compact = Compact { bytes: [0; DrawMessage::MAX_LEN], len: bytes.len() };
+        compact.bytes[..bytes.len()].copy_from_slice(bytes);
+        compact
+    }
+}
+
+impl Deref for Compact {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.bytes[..self.len]
+    }
+}
+
+impl IntoIterator for Compact {
+    type Item = u8;
+    type IntoIter = core::iter::Take<core::array::IntoIter<u8, { DrawMessage::MAX_LEN }>>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.bytes.into_iter().take(self.len)
+    }
+}
+
+/// What a client registers as. The Pico only takes drawing from drawers and admins
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Role {
+    Drawer,
+    // Watches, anything it draws is ignored
+    Spectator,
+    // A drawer that may manage the device
+    Admin,
+}
+
+impl Role {
+    pub fn from_byte(byte: u8) -> Option<Self> {
+        match byte {
+            0 => Some(Role::Drawer),
+            1 => Some(Role::Spectator),
+            2 => Some(Role::Admin),
+            _ => None,
+        }
+    }
+
+    pub fn to_byte(self) -> u8 {
+        match self {
+            Role::Drawer => 0,
+            Role::Spectator => 1,
+            Role::Admin => 2,
+        }
+    }
+
+    pub fn can_draw(self) -> bool {
+        matches!(self, Role::Drawer | Role::Admin)
+    }
+}
+
+/// What the Pico reports about itself every few seconds
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Status {
+    pub pixels_on: u16,
+    pub uptime_secs: u32,
+    // Signal of the joined network in dBm, 0 if unknown
+    pub rssi: i8,
+    pub clients: u8,
+    // Messages from any client that failed their checksum since boot
+    pub corrupt_messages: u32,
+    // WiFi channel of the joined network, 0 if unknown
+    pub channel: u8,
+    // Times the Pico joined its network since boot, more than one means it lost it
+    pub joins: u16,
+    // Client connections that failed to read or write since boot
+    pub socket_errors: u32,
+    // Drawing the Pico had to drop rather than relay, since boot
+    pub dropped_events: u32,
+}
+
+impl Status {
+    const LEN: usize = 23;
+
+    fn encode(&self) -> [u8; Self::LEN] {
+        let mut bytes = [0u8; Self::LEN];
+        bytes[..2].copy_from_slice(&self.pixels_on.to_le_bytes());
+        bytes[2..6].copy_from_slice(&self.uptime_secs.to_le_bytes());
+        bytes[6] = self.rssi as u8;
+        bytes[7] = self.clients;
+        bytes[8..12].copy_from_slice(&self.corrupt_messages.to_le_bytes());
+        bytes[12] = self.channel;
+        bytes[13..15].copy_from_slice(&self.joins.to_le_bytes());
+        bytes[15..19].copy_from_slice(&self.socket_errors.to_le_bytes());
+        bytes[19..].copy_from_slice(&self.dropped_events.to_le_bytes());
+        bytes
+    }
+
+    fn decode(bytes: &[u8; Self::LEN]) -> Self {
+        Status {
+            pixels_on: u16::from_le_bytes([bytes[0], bytes[1]]),
+            uptime_secs: u32::from_le_bytes([bytes[2], bytes[3], bytes[4], bytes[5]]),
+            rssi: bytes[6] as i8,
+            clients: bytes[7],
+            corrupt_messages: u32::from_le_bytes([bytes[8], bytes[9], bytes[10], bytes[11]]),
+            channel: bytes[12],
+            joins: u16::from_le_bytes([bytes[13], bytes[14]]),
+            socket_errors: u32::from_le_bytes([bytes[15], bytes[16], bytes[17], bytes[18]]),
+            dropped_events: u32::from_le_bytes([bytes[19], bytes[20], bytes[21], bytes[22]]),
+        }
+    }
+}
+
+/// The pixels of a Stroke, which has been checked to hold only compact pixels
+pub fn stroke_pixels(pixels: &[u8]) -> impl Iterator<Item = DrawMessage> + '_ {
+    pixels
+        .chunks_exact(DrawMessage::LEN)
+        .filter_map(|pixel| DrawMessage::decode(pixel).ok())
+}
+
+/// A binary WebSocket message, after the header. Multi-byte numbers are little endian:
+///
+/// | type       | payload                                                                                                                 | sent by                       |
+/// |------------|-------------------------------------------------------------------------------------------------------------------------|-------------------------------|
+/// | Hello      | `[client id, width, height, capabilities]`                                                                              | both, on connect              |
+/// | Pixel      | `[author, x, y, state]`                                                                                                 | both                          |
+/// | Clear      | `[author]`                                                                                                              | both                          |
+/// | Frame      | `[width, height, runs...]`                                                                                              | both, see `encode_runs`       |
+/// | Prediction | `[class, confidence]`                                                                                                   | webapp                        |
+/// | Ping       | `[token: u32]`                                                                                                          | webapp, Pico echoes it        |
+/// | Ack        | `[acked: u16, latest: u16, credit]`                                                                                     | Pico                          |
+/// | Stroke     | `[author, (x, y, state)...]`                                                                                            | both, with batching           |
+/// | Status     | `[pixels on: u16, uptime: u32, rssi: i8, clients, corrupt: u32, channel, joins: u16, socket errors: u32, dropped: u32]` | Pico, every few seconds       |
+/// | ClearRect  | `[author, x, y, width, height]`                                                                                         | both                          |
+/// | Register   | `[id: u32, role]`                                                                                                       | webapp, after Hello           |
+/// | GetFrame   | `[]`                                                                                                                    | webapp, answered with a Frame |
+/// | Echo       | `[sent: u32, pico us: u16]`                                                                                             | webapp, Pico reflects it      |
+///
+/// A pixel's state is a `PixelState`: 0 clears it, 1 sets it, 2 toggles it and
+/// 0x10 to 0x1f give it a gray level from 0 to 15.
+///
+/// Browsers send drawing with author 0, the Pico stamps the sender's client id
+/// on it before relaying it to the others.
+///
+/// Between peers that both have COMPRESSION, a packet may carry its payload
+/// compressed, with the high bit of its type set. See `compress_packet`.
+///
+/// A client that offers CBOR in its Hello may send packets as CBOR instead, and
+/// gets them as CBOR from then on. See `encode_cbor`, with the `cbor` feature.
+///
+/// Acks carry credit, how many pixels the Pico can queue for its display right
+/// now. A sender keeps drawing within it, holding back the rest until the next
+/// Ack rather than have the Pico stall on a full queue.
+///
+/// A client may Register after Hello, with an id of its own that stays the same
+/// across reconnects and its role. Until it does it counts as a drawer.
+///
+/// A client that connects without a canvas of its own, or that spectates, sends
+/// GetFrame to start from what the Pico shows.
+///
+/// Hello opens every connection from both sides. The Pico's carries the client
+/// id and the size of its display, the browser's has client id 0 and the size
+/// of its grid. A side that gets a message of another version closes the
+/// connection, neither guesses at a format it doesn't know
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Message<'a> {
+    Hello { client_id: u8, width: u8, height: u8, capabilities: Capabilities },
+    Draw { author: u8, message: DrawMessage },
+    Frame { width: u8, height: u8, data: &'a [u8] },
+    // What the drawing was recognized as, with a confidence from 0 to 255 (certain)
+    Prediction { class: u8, confidence: u8 },
+    Ping(u32),
+    // Every message up to `acked` arrived, `latest` is the newest one seen.
+    // They differ once something went missing. `credit` is how many more pixels
+    // the receiver has room for right now, see `AckWatcher::credit`
+    Ack { acked: u16, latest: u16, credit: u8 },
+    // Up to MAX_STROKE_PIXELS pixels in their compact form, see `stroke_pixels`
+    Stroke { author: u8, pixels: &'a [u8] },
+    Status(Status),
+    Register { id: u32, role: Role },
+    // Asks for the whole canvas, answered with a Frame
+    GetFrame,
+    // A client's clock when it sent this, in ms. The Pico sends it straight
+    // back with how long it spent on it, in µs, for telling network from firmware
+    Echo { sent_ms: u32, pico_us: u16 },
+}
+
+impl Message<'_> {
+    pub fn message_type(&self) -> MessageType {
+        match self {
+            Message::Hello { .. } => MessageType::Hello,
+            Message::Draw { message: DrawMessage::Pixel { .. }, .. } => MessageType::Pixel,
+            Message::Draw { message: DrawMessage::Clear, .. } => MessageType::Clear,
+            Message::Draw { message: DrawMessage::ClearRect { .. }, .. } => MessageType::ClearRect,
+            Message::Frame { .. } => MessageType::Frame,
+            Message::Prediction { .. } => MessageType::Prediction,
+            Message::Ping(_) => MessageType::Ping,
+            Message::Ack { .. } => MessageType::Ack,
+            Message::Stroke { .. } => MessageType::Stroke,
+            Message::Status(_) => MessageType::Status,
+            Message::Register { .. } => MessageType::Register,
+            Message::GetFrame => MessageType::GetFrame,
+            Message::Echo { .. } => MessageType::Echo,
+        }
+    }
+
+    fn payload_len(&self) -> usize {
+        match self {
+            Message::Prediction { .. } => 2,
+            Message::Draw { message: DrawMessage::Pixel { .. }, .. } => 4,
+            Message::Draw { message: DrawMessage::Clear, .. } => 1,
+            Message::Draw { message: DrawMessage::ClearRect { .. }, .. } => 5,
+            Message::Frame { data, .. } => 2 + data.len(),
+            Message::Stroke { pixels, .. } => 1 + pixels.len(),
+            Message::Status(_) => Status::LEN,
+            Message::Register { .. } | Message::Ack { .. } => 5,
+            Message::Hello { .. } | Message::Ping(_) => 4,
+            Message::GetFrame => 0,
+            Message::Echo { .. } => 6,
+        }
+    }
+}
+
+// Set in the type byte of a packet whose payload is compressed
+const COMPRESSED: u8 = 0x80;
+
+/// Compress the payload of an encoded packet into `out`, for peers with
+/// COMPRESSION. Worth it for Frames and Strokes. Returns None if that doesn't
+/// make the packet shorter
+pub fn compress_packet(packet: &[u8], out: &mut [u8]) -> Option<usize> {
+    let payload = packet.get(HEADER_LEN..packet.len().checked_sub(CHECKSUM_LEN)?)?;
+    let room = out
+        .len()
+        .checked_sub(HEADER_LEN + CHECKSUM_LEN)?
+        .min(payload.len().checked_sub(1)?);
+    let compressed = lz::compress(payload, &mut out[HEADER_LEN..HEADER_LEN + room])?;
+
+    out[..HEADER_LEN].copy_from_slice(&packet[..HEADER_LEN]);
+    out[1] |= COMPRESSED;
+    let len = HEADER_LEN + compressed + CHECKSUM_LEN;
+    out[len - CHECKSUM_LEN] = crc8(&out[..len - CHECKSUM_LEN]);
+    Some(len)
+}
+
+/// The packet with its payload decompressed into `scratch`, or `bytes` as they
+/// are if it isn't compressed. What arrives goes through here before `Packet::decode`
+pub fn decompress_packet<'a>(bytes: &'a [u8], scratch: &'a mut [u8]) -> Result<&'a [u8], DecodeError> {
+    // Other versions are left for decode to report
+    let [VERSION, kind, ..] = bytes else {
+        return Ok(bytes);
+    };
+    if kind & COMPRESSED == 0 {
+        return Ok(bytes);
+    }
+    let [rest @ .., checksum] = bytes else {
+        return Err(DecodeError::Truncated);
+    };
+    if crc8(rest) != *checksum {
+        return Err(DecodeError::Checksum);
+    }
+    let payload = rest.get(HEADER_LEN..).ok_or(DecodeError::Truncated)?;
+    // Too short for even the header and checksum of the plain packet
+    if scratch.len() < HEADER_LEN + CHECKSUM_LEN {
+        return Err(DecodeError::Truncated);
+    }
+    let room = scratch.len() - HEADER_LEN - CHECKSUM_LEN;
+    let len = lz::decompress(payload, &mut scratch[HEADER_LEN..HEADER_LEN + room])?;
+
+    scratch[..HEADER_LEN].copy_from_slice(&rest[..HEADER_LEN]);
+    scratch[1] &= !COMPRESSED;
+    let end = HEADER_LEN + len;
+    scratch[end] = crc8(&scratch[..end]);
+    Ok(&scratch[..end + CHECKSUM_LEN])
+}
+
+/// A message with the sequence number its sender gave it. Each side numbers
+/// what it sends on a connection from any start, wrapping at u16::MAX
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Packet<'a> {
+    pub seq: u16,
+    pub message: Message<'a>,
+}
+
+impl<'a> Packet<'a> {
+    pub fn encoded_len(&self) -> usize {
+        HEADER_LEN + self.message.payload_len() + CHECKSUM_LEN
+    }
+
+    /// Write the packet to the start of `out`, returning its length
+    pub fn encode(&self, out: &mut [u8]) -> Result<usize, EncodeError> {
+        let len = self.encoded_len();
+        let out = out
+            .get_mut(..len)
+            .ok_or(EncodeError::BufferTooSmall { needed: len })?;
+
+        let (header, rest) = out.split_at_mut(HEADER_LEN);
+        let payload = &mut rest[..len - HEADER_LEN - CHECKSUM_LEN];
+        let [seq_low, seq_high] = self.seq.to_le_bytes();
+        header.copy_from_slice(&[VERSION, self.message.message_type() as u8, seq_low, seq_high]);
+        match self.message {
+            Message::Hello { client_id, width, height, capabilities } => {
+                payload.copy_from_slice(&[client_id, width, height, capabilities.0]);
+            }
+            Message::Draw { author, message: DrawMessage::Pixel { x, y, state } } => {
+                payload.copy_from_slice(&[author, x, y, state.to_byte()]);
+            }
+            Message::Draw { author, message: DrawMessage::Clear } => payload[0] = author,
+            Message::Draw { author, message: DrawMessage::ClearRect { x, y, width, height } } => {
+                payload.copy_from_slice(&[author, x, y, width, height]);
+            }
+            Message::Status(status) => payload.copy_from_slice(&status.encode()),
+            Message::Register { id, role } => {
+                payload[..4].copy_from_slice(&id.to_le_bytes());
+                payload[4] = role.to_byte();
+            }
+            Message::Stroke { author, pixels } => {
+                payload[0] = author;
+                payload[1..].copy_from_slice(pixels);
+            }
+            Message::Frame { width, height, data } => {
+                payload[0] = width;
+                payload[1] = height;
+                payload[2..].copy_from_slice(data);
+            }
+            Message::Prediction { class, confidence } => payload.copy_from_slice(&[class, confidence]),
+            Message::GetFrame => {}
+            Message::Echo { sent_ms, pico_us } => {
+                payload[..4].copy_from_slice(&sent_ms.to_le_bytes());
+                payload[4..].copy_from_slice(&pico_us.to_le_bytes());
+            }
+            Message::Ping(token) => payload.copy_from_slice(&token.to_le_bytes()),
+            Message::Ack { acked, latest, credit } => {
+                payload[..2].copy_from_slice(&acked.to_le_bytes());
+                payload[2..4].copy_from_slice(&latest.to_le_bytes());
+                payload[4] = credit;
+            }
+        }
+        out[len - CHECKSUM_LEN] = crc8(&out[..len - CHECKSUM_LEN]);
+        Ok(len)
+    }
+
+    pub fn decode(bytes: &'a [u8]) -> Result<Self, DecodeError> {
+        // The version goes first on its own, so a peer can report a mismatch
+        // whatever the rest of the header looks like in other versions
+        let [version, rest @ ..] = bytes else {
+            return Err(DecodeError::Truncated);
+        };
+        if *version != VERSION {
+            return Err(DecodeError::Version(*version));
+        }
+        let [rest @ .., checksum] = rest else {
+            return Err(DecodeError::Truncated);
+        };
+        if crc8(&bytes[..bytes.len() - CHECKSUM_LEN]) != *checksum {
+            return Err(DecodeError::Checksum);
+        }
+        let [kind, seq_low, seq_high, payload @ ..] = rest else {
+            return Err(DecodeError::Truncated);
+        };
+        let seq = u16::from_le_bytes([*seq_low, *seq_high]);
+        let kind = MessageType::from_byte(*kind).ok_or(DecodeError::UnknownType(*kind))?;
+        let message = Self::decode_payload(kind, payload)?;
+        Ok(Packet { seq, message })
+    }
+
+    fn decode_payload(kind: MessageType, payload: &'a [u8]) -> Result<Message<'a>, DecodeError> {
+        let exact = |expected: usize| match payload.len() {
+            found if found == expected => Ok(()),
+            found if found < expected => Err(DecodeError::Truncated),
+            found => Err(DecodeError::Length { expected, found }),
+        };
+
+        match kind {
+            MessageType::Hello => {
+                exact(4)?;
+                Ok(Message::Hello {
+                    client_id: payload[0],
+                    width: payload[1],
+                    height: payload[2],
+                    // Bits this version doesn't know are kept, `shared` drops them
+                    capabilities: Capabilities(payload[3]),
+                })
+            }
+            MessageType::Pixel => {
+                exact(4)?;
+                let state = PixelState::from_byte(payload[3]).ok_or(DecodeError::State(payload[3]))?;
+                Ok(Message::Draw {
+                    author: payload[0],
+                    message: DrawMessage::Pixel { x: payload[1], y: payload[2], state },
+                })
+            }
+            MessageType::Clear => {
+                exact(1)?;
+                Ok(Message::Draw {
+                    author: payload[0],
+                    message: DrawMessage::Clear,
+                })
+            }
+            MessageType::ClearRect => {
+                exact(5)?;
+                Ok(Message::Draw {
+                    author: payload[0],
+                    message: DrawMessage::ClearRect {
+                        x: payload[1],
+                        y: payload[2],
+                        width: payload[3],
+                        height: payload[4],
+                    },
+                })
+            }
+            MessageType::Register => {
+                exact(5)?;
+                Ok(Message::Register {
+                    id: u32::from_le_bytes([payload[0], payload[1], payload[2], payload[3]]),
+                    role: Role::from_byte(payload[4]).ok_or(DecodeError::Role(payload[4]))?,
+                })
+            }
+            MessageType::GetFrame => {
+                exact(0)?;
+                Ok(Message::GetFrame)
+            }
+            MessageType::Echo => {
+                exact(6)?;
+                Ok(Message::Echo {
+                    sent_ms: u32::from_le_bytes([payload[0], payload[1], payload[2], payload[3]]),
+                    pico_us: u16::from_le_bytes([payload[4], payload[5]]),
+                })
+            }
+            MessageType::Status => {
+                exact(Status::LEN)?;
+                let bytes = payload.try_into().map_err(|_| DecodeError::Truncated)?;
+                Ok(Message::Status(Status::decode(bytes)))
+            }
+            MessageType::Stroke => {
+                let [author, pixels @ ..] = payload else {
+                    return Err(DecodeError::Truncated);
+                };
+                check_stroke(pixels)?;
+                Ok(Message::Stroke { author: *author, pixels })
+            }
+            MessageType::Frame => match payload {
+                [width, height, data @ ..] => Ok(Message::Frame {
+                    width: *width,
+                    height: *height,
+                    data,
+                }),
+                _ => Err(DecodeError::Truncated),
+            },
+            MessageType::Prediction => {
+                exact(2)?;
+                Ok(Message::Prediction { class: payload[0], confidence: payload[1] })
+            }
+            MessageType::Ping => {
+                exact(4)?;
+                Ok(Message::Ping(u32::from_le_bytes([payload[0], payload[1], payload[2], payload[3]])))
+            }
+            MessageType::Ack => {
+                exact(5)?;
+                Ok(Message::Ack {
+                    acked: u16::from_le_bytes([payload[0], payload[1]]),
+                    latest: u16::from_le_bytes([payload[2], payload[3]]),
+                    credit: payload[4],
+                })
+            }
+        }
+    }
+}
+
+// A Stroke's pixels have to be whole compact pixels, and only pixels
+fn check_stroke(pixels: &[u8]) -> Result<(), DecodeError> {
+    let whole = pixels.len() - pixels.len() % DrawMessage::LEN;
+    if whole != pixels.len() || whole > MAX_STROKE_PIXELS * DrawMessage::LEN {
+        return Err(DecodeError::Length {
+            expected: 1 + whole.min(MAX_STROKE_PIXELS * DrawMessage::LEN),
+            found: 1 + pixels.len(),
+        });
+    }
+    for pixel in pixels.chunks_exact(DrawMessage::LEN) {
+        // A stroke only draws, clearing has its own messages
+        match DrawMessage::decode(pixel) {
+            Ok(DrawMessage::Pixel { .. }) => {}
+            Ok(_) | Err(DecodeError::Length { .. }) => return Err(DecodeError::State(pixel[2])),
+            Err(error) => return Err(error),
+        }
+    }
+    Ok(())
+}
+
+// LEB128: 7 bits per byte, high bit set while more bytes follow
+fn push_varint(mut value: usize, push: &mut impl FnMut(u8)) {
+    while value >= 0x80 {
+        push((value as u8 & 0x7f) | 0x80);
+        value >>= 7;
+    }
+    push(value as u8);
+}
+
+fn read_varint(bytes: &mut impl Iterator<Item = u8>) -> Result<usize, DecodeError> {
+    let mut value = 0usize;
+    for shift in (0..usize::BITS).step_by(7) {
+        let byte = bytes.next().ok_or(DecodeError::Truncated)?;
+        value |= ((byte & 0x7f) as usize) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(DecodeError::Truncated)
+}
+
+/// Run-length encode pixels in row-major order for a Frame: alternating runs
+/// of off and on pixels, starting with off, each a LEB128 varint. A final off
+/// run is left out, the receiver knows the frame size
+pub fn encode_runs(pixels: impl IntoIterator<Item = bool>, mut push: impl FnMut(u8)) {
+    let mut current = false;
+    let mut run = 0;
+    for pixel in pixels {
+        if pixel != current {
+            push_varint(run, &mut push);
+            current = pixel;
+            run = 0;
+        }
+        run += 1;
+    }
+    if current {
+        push_varint(run, &mut push);
+    }
+}
+
+/// Decode the runs of a `len` pixel frame, calling `ink` with the index of every
+/// pixel that is on
+pub fn decode_runs(runs: &[u8], len: usize, mut ink: impl FnMut(usize)) -> Result<(), DecodeError> {
+    let mut bytes = runs.iter().copied().peekable();
+    let mut index = 0usize;
+    let mut on = false;
+    while bytes.peek().is_some() {
+        let run = read_varint(&mut bytes)?;
+        let end = match index.checked_add(run) {
+            Some(end) if end <= len => end,
+            // Past the frame, or so far past it the index overflows
+            end => return Err(DecodeError::Length { expected: len, found: end.unwrap_or(usize::MAX) }),
+        };
+        if on {
+            (index..end).for_each(&mut ink);
+        }
+        index = end;
+        on = !on;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(message: Message) {
+        let packet = Packet { seq: 0xbeef, message };
+        let mut buffer = [0u8; 64];
+        let len = packet.encode(&mut buffer).unwrap();
+        assert_eq!(len, packet.encoded_len());
+        assert_eq!(Packet::decode(&buffer[..len]), Ok(packet));
+    }
+
+    #[test]
+    fn pixel_round_trips() {
+        let states = [PixelState::Clear, PixelState::Set, PixelState::Toggle, PixelState::Gray(0), PixelState::Gray(15)];
+        for state in states {
+            let pixel = DrawMessage::Pixel { x: 127, y: 47, state };
+            assert_eq!(DrawMessage::decode(&pixel.encode()), Ok(pixel));
+            round_trip(Message::Draw { author: 1, message: pixel });
+            assert_eq!(PixelState::from_byte(state.to_byte()), Some(state));
+        }
+        assert_eq!(PixelState::from_byte(3), None);
+        assert_eq!(PixelState::from_byte(0x20), None);
+        // Levels past the top are sent as the top level
+        assert_eq!(PixelState::Gray(200).to_byte(), 0x1f);
+    }
+
+    #[test]
+    fn gray_dithers_evenly() {
+        let on_in_block = |level| (0..16).filter(|i| dithered(level, i % 4, i / 4)).count();
+        assert_eq!(on_in_block(0), 0);
+        assert_eq!(on_in_block(8), 8);
+        assert_eq!(on_in_block(15), 16);
+        // The pattern repeats every 4 pixels
+        assert_eq!(dithered(5, 1, 2), dithered(5, 9, 6));
+        assert!((1..GRAY_LEVELS).all(|level| on_in_block(level) >= on_in_block(level - 1)));
+
+        let gray = DrawMessage::Pixel { x: 0, y: 0, state: PixelState::Gray(15) };
+        assert_eq!(gray.monochrome(), DrawMessage::Pixel { x: 0, y: 0, state: PixelState::Set });
+        assert_eq!(DrawMessage::Clear.monochrome(), DrawMessage::Clear);
+    }
+
+    #[test]
+    fn pixel_states_apply() {
+        assert!(!PixelState::Clear.apply(true, 0, 0));
+        assert!(PixelState::Set.apply(false, 0, 0));
+        assert!(PixelState::Toggle.apply(false, 0, 0));
+        assert!(!PixelState::Toggle.apply(true, 0, 0));
+        assert!(!PixelState::Gray(0).apply(true, 0, 0));
+        assert_eq!(PixelState::from(true), PixelState::Set);
+        assert_eq!(PixelState::from(false), PixelState::Clear);
+    }
+
+    #[test]
+    fn clear_keeps_its_compact_form() {
+        assert_eq!(*DrawMessage::Clear.encode(), [255, 255, 2]);
+        assert_eq!(DrawMessage::decode(&[255, 255, 2]), Ok(DrawMessage::Clear));
+    }
+
+    #[test]
+    fn compact_form_rejects_bad_input() {
+        assert_eq!(
+            DrawMessage::decode(&[1, 2, 1, 7]),
+            Err(DecodeError::Length { expected: 3, found: 4 })
+        );
+        assert_eq!(DrawMessage::decode(&[3, 4, 4]), Err(DecodeError::State(4)));
+        assert_eq!(DrawMessage::decode(&[255, 255, 4]), Err(DecodeError::State(4)));
+        assert_eq!(
+            DrawMessage::decode(&[1, 2, 3]),
+            Err(DecodeError::Length { expected: 5, found: 3 })
+        );
+    }
+
+    #[test]
+    fn clear_rect_carries_its_size() {
+        let rect = DrawMessage::ClearRect { x: 10, y: 4, width: 20, height: 8 };
+        assert_eq!(*rect.encode(), [10, 4, 3, 20, 8]);
+        assert_eq!(DrawMessage::decode(&rect.encode()), Ok(rect));
+        assert_eq!(DrawMessage::compact_len(3), DrawMessage::MAX_LEN);
+    }
+
+    #[test]
+    fn every_message_round_trips() {
+        round_trip(Message::Hello {
+            client_id: 4,
+            width: 128,
+            height: 48,
+            capabilities: Capabilities::GRAYSCALE.union(Capabilities::BATCHING),
+        });
+        round_trip(Message::Draw {
+            author: 3,
+            message: DrawMessage::Pixel { x: 5, y: 6, state: PixelState::Set },
+        });
+        round_trip(Message::Draw {
+            author: DEVICE_AUTHOR,
+            message: DrawMessage::Clear,
+        });
+        round_trip(Message::Draw {
+            author: 5,
+            message: DrawMessage::ClearRect { x: 0, y: 1, width: 128, height: 47 },
+        });
+        round_trip(Message::Frame {
+            width: 128,
+            height: 48,
+            data: &[0x80, 0x01],
+        });
+        round_trip(Message::Frame { width: 0, height: 0, data: &[] });
+        round_trip(Message::Prediction { class: 7, confidence: 230 });
+        round_trip(Message::Ping(0xdead_beef));
+        round_trip(Message::Ack { acked: 7, latest: 0x1234, credit: 21 });
+        round_trip(Message::Stroke { author: 2, pixels: &[1, 2, 1, 3, 4, 0] });
+        round_trip(Message::Status(Status {
+            pixels_on: 6144,
+            uptime_secs: 86_400,
+            rssi: -67,
+            clients: 3,
+            corrupt_messages: 70_000,
+            channel: 11,
+            joins: 2,
+            socket_errors: 5,
+            dropped_events: 100_000,
+        }));
+        for role in [Role::Drawer, Role::Spectator, Role::Admin] {
+            round_trip(Message::Register { id: 0x1234_5678, role });
+        }
+        round_trip(Message::GetFrame);
+        round_trip(Message::Echo { sent_ms: 0xdead_beef, pico_us: 850 });
+    }
+
+    #[test]
+    fn header_comes_first() {
+        let pixel = Packet {
+            seq: 0x0102,
+            message: Message::Draw {
+                author: 2,
+                message: DrawMessage::Pixel { x: 10, y: 20, state: PixelState::Set },
+            },
+        };
+        let mut buffer = [0u8; 9];
+        assert_eq!(pixel.encode(&mut buffer), Ok(9));
+        assert_eq!(buffer[..8], [VERSION, MessageType::Pixel as u8, 2, 1, 2, 10, 20, 1]);
+        assert_eq!(buffer[8], crc8(&buffer[..8]));
+    }
+
+    // Decode `bytes` with a valid checksum appended
+    fn decode_sealed(bytes: &[u8]) -> Result<(), DecodeError> {
+        let mut buffer = [0u8; 16];
+        buffer[..bytes.len()].copy_from_slice(bytes);
+        buffer[bytes.len()] = crc8(bytes);
+        Packet::decode(&buffer[..=bytes.len()]).map(|_| ())
+    }
+
+    #[test]
+    fn strokes_hold_whole_pixels() {
+        let mut drawn = stroke_pixels(&[1, 2, 1, 3, 4, 0, 5, 6, 2]);
+        assert_eq!(drawn.next(), Some(DrawMessage::Pixel { x: 1, y: 2, state: PixelState::Set }));
+        assert_eq!(drawn.next(), Some(DrawMessage::Pixel { x: 3, y: 4, state: PixelState::Clear }));
+        assert_eq!(drawn.next(), Some(DrawMessage::Pixel { x: 5, y: 6, state: PixelState::Toggle }));
+        assert_eq!(drawn.next(), None);
+
+        let stroke = MessageType::Stroke as u8;
+        assert_eq!(
+            decode_sealed(&[VERSION, stroke, 0, 0, 0, 1, 2]),
+            Err(DecodeError::Length { expected: 1, found: 3 })
+        );
+        // A clear smuggled into a stroke
+        assert_eq!(decode_sealed(&[VERSION, stroke, 0, 0, 0, 255, 255, 2]), Err(DecodeError::State(2)));
+        assert_eq!(decode_sealed(&[VERSION, stroke, 0, 0, 0, 1, 2, 3]), Err(DecodeError::State(3)));
+        assert_eq!(decode_sealed(&[VERSION, stroke, 0, 0, 0, 1, 2, 4]), Err(DecodeError::State(4)));
+    }
+
+    #[test]
+    fn strokes_have_a_limit() {
+        let pixels = [0u8; (MAX_STROKE_PIXELS + 1) * DrawMessage::LEN];
+        let mut buffer = [0u8; 128];
+        let stroke = Packet { seq: 0, message: Message::Stroke { author: 0, pixels: &pixels } };
+        let len = stroke.encode(&mut buffer).unwrap();
+        assert_eq!(
+            Packet::decode(&buffer[..len]),
+            Err(DecodeError::Length {
+                expected: 1 + MAX_STROKE_PIXELS * DrawMessage::LEN,
+                found: 1 + pixels.len()
+            })
+        );
+    }
+
+    #[test]
+    fn only_drawers_and_admins_draw() {
+        assert!(Role::Drawer.can_draw());
+        assert!(Role::Admin.can_draw());
+        assert!(!Role::Spectator.can_draw());
+        assert_eq!(
+            decode_sealed(&[VERSION, MessageType::Register as u8, 0, 0, 1, 2, 3, 4, 3]),
+            Err(DecodeError::Role(3))
+        );
+    }
+
+    #[test]
+    fn compressed_packets_decode() {
+        let pixels = [5, 6, 1].repeat(MAX_STROKE_PIXELS);
+        let stroke = Packet { seq: 3, message: Message::Stroke { author: 0, pixels: &pixels } };
+        let mut encoded = [0u8; 128];
+        let len = stroke.encode(&mut encoded).unwrap();
+
+        let mut compressed = [0u8; 128];
+        let compressed_len = compress_packet(&encoded[..len], &mut compressed).unwrap();
+        assert!(compressed_len < len / 4);
+        // Compressed packets must not pass for plain ones
+        assert!(Packet::decode(&compressed[..compressed_len]).is_err());
+
+        let mut scratch = [0u8; MAX_MESSAGE_LEN];
+        let decompressed = decompress_packet(&compressed[..compressed_len], &mut scratch).unwrap();
+        assert_eq!(Packet::decode(decompressed), Ok(stroke));
+
+        // Plain packets pass through, short ones aren't worth compressing
+        let ping = Packet { seq: 4, message: Message::Ping(9) };
+        let len = ping.encode(&mut encoded).unwrap();
+        assert_eq!(decompress_packet(&encoded[..len], &mut scratch), Ok(&encoded[..len]));
+        assert_eq!(compress_packet(&encoded[..len], &mut compressed), None);
+    }
+
+    #[test]
+    fn damaged_compressed_packets_are_refused() {
+        let frame = Packet { seq: 0, message: Message::Frame { width: 8, height: 8, data: &[0; 40] } };
+        let mut encoded = [0u8; 64];
+        let len = frame.encode(&mut encoded).unwrap();
+        let mut compressed = [0u8; 64];
+        let compressed_len = compress_packet(&encoded[..len], &mut compressed).unwrap();
+
+        let mut scratch = [0u8; 64];
+        let mut damaged = compressed;
+        damaged[HEADER_LEN] ^= 0x01;
+        assert_eq!(
+            decompress_packet(&damaged[..compressed_len], &mut scratch),
+            Err(DecodeError::Checksum)
+        );
+        // Decompressing to more than the scratch holds
+        assert_eq!(
+            decompress_packet(&compressed[..compressed_len], &mut scratch[..16]),
+            Err(DecodeError::Compression)
+        );
+        // A scratch without room for the header and checksum
+        for short in 0..HEADER_LEN + CHECKSUM_LEN {
+            assert_eq!(
+                decompress_packet(&compressed[..compressed_len], &mut scratch[..short]),
+                Err(DecodeError::Truncated)
+            );
+        }
+    }
+
+    #[test]
+    fn capabilities_combine() {
+        let pico = Capabilities::BATCHING.union(Capabilities::INFERENCE);
+        let browser = Capabilities::BATCHING.union(Capabilities::GRAYSCALE);
+        let shared = pico.shared(browser);
+        assert!(shared.contains(Capabilities::BATCHING));
+        assert!(!shared.contains(Capabilities::GRAYSCALE));
+        assert!(!shared.contains(Capabilities::INFERENCE));
+        assert!(shared.contains(Capabilities::NONE));
+        // A future capability is ignored rather than rejected
+        assert_eq!(Capabilities(0x80).shared(browser), Capabilities::NONE);
+    }
+
+    #[test]
+    fn rejects_damaged_messages() {
+        let mut buffer = [0u8; 16];
+        let ping = Packet { seq: 9, message: Message::Ping(1234) };
+        let len = ping.encode(&mut buffer).unwrap();
+        for i in 1..len {
+            let mut damaged = buffer;
+            damaged[i] ^= 0x10;
+            assert_eq!(Packet::decode(&damaged[..len]), Err(DecodeError::Checksum));
+        }
+    }
+
+    #[test]
+    fn rejects_other_versions() {
+        assert_eq!(Packet::decode(&[VERSION + 1, 2]), Err(DecodeError::Version(VERSION + 1)));
+        // Version 1 had no sequence number or checksum
+        assert_eq!(Packet::decode(&[1, 2, 0, 1, 1, 1]), Err(DecodeError::Version(1)));
+        // The pre-header [x, y, state] format must not pass for a message
+        assert!(Packet::decode(&[10, 20, 1]).is_err());
+    }
+
+    #[test]
+    fn rejects_unknown_types_and_bad_lengths() {
+        assert_eq!(decode_sealed(&[VERSION, 99, 0, 0]), Err(DecodeError::UnknownType(99)));
+        assert_eq!(Packet::decode(&[VERSION]), Err(DecodeError::Truncated));
+        assert_eq!(decode_sealed(&[VERSION, MessageType::Ack as u8, 0]), Err(DecodeError::Truncated));
+        assert_eq!(
+            decode_sealed(&[VERSION, MessageType::Pixel as u8, 0, 0, 1, 2]),
+            Err(DecodeError::Truncated)
+        );
+        assert_eq!(
+            decode_sealed(&[VERSION, MessageType::Clear as u8, 0, 0, 1, 2]),
+            Err(DecodeError::Length { expected: 1, found: 2 })
+        );
+        assert_eq!(
+            decode_sealed(&[VERSION, MessageType::GetFrame as u8, 0, 0, 1]),
+            Err(DecodeError::Length { expected: 0, found: 1 })
+        );
+        assert_eq!(
+            decode_sealed(&[VERSION, MessageType::Pixel as u8, 0, 0, 1, 2, 3, 5]),
+            Err(DecodeError::State(5))
+        );
+        assert_eq!(
+            decode_sealed(&[VERSION, MessageType::Pixel as u8, 0, 0, 1, 2, 3, 3]),
+            Err(DecodeError::State(3))
+        );
+    }
+
+    fn runs(pixels: &[bool]) -> ([u8; 32], usize) {
+        let mut out = [0u8; 32];
+        let mut len = 0;
+        encode_runs(pixels.iter().copied(), |byte| {
+            out[len] = byte;
+            len += 1;
+        });
+        (out, len)
+    }
+
+    fn inked(runs: &[u8], len: usize) -> Result<[bool; 16], DecodeError> {
+        let mut pixels = [false; 16];
+        decode_runs(runs, len, |i| pixels[i] = true)?;
+        Ok(pixels)
+    }
+
+    #[test]
+    fn runs_round_trip() {
+        let mut pixels = [false; 16];
+        for i in [0, 1, 2, 7, 15] {
+            pixels[i] = true;
+        }
+        let (out, len) = runs(&pixels);
+        assert_eq!(&out[..len], &[0, 3, 4, 1, 7, 1]);
+        assert_eq!(inked(&out[..len], 16), Ok(pixels));
+    }
+
+    #[test]
+    fn blank_frame_has_no_runs() {
+        assert_eq!(runs(&[false; 16]).1, 0);
+        assert_eq!(inked(&[], 16), Ok([false; 16]));
+    }
+
+    #[test]
+    fn long_runs_use_varints() {
+        let mut out = [0u8; 8];
+        let mut len = 0;
+        let pixels = core::iter::repeat_n(false, 300).chain(core::iter::once(true));
+        encode_runs(pixels, |byte| {
+            out[len] = byte;
+            len += 1;
+        });
+        // 300 = 0b10_0101100
+        assert_eq!(&out[..len], &[0xac, 0x02, 1]);
+
+        let mut last = None;
+        assert_eq!(decode_runs(&out[..len], 301, |i| last = Some(i)), Ok(()));
+        assert_eq!(last, Some(300));
+    }
+
+    #[test]
+    fn runs_past_the_frame_are_rejected() {
+        assert_eq!(inked(&[10, 10], 16), Err(DecodeError::Length { expected: 16, found: 20 }));
+        assert_eq!(inked(&[0x80], 16), Err(DecodeError::Truncated));
+    }
+
+    #[test]
+    fn huge_runs_are_rejected() {
+        // Three pixels, then a run of a 64-bit usize::MAX the index can't be moved by
+        let runs = [3, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x01];
+        assert_eq!(inked(&runs, 16), Err(DecodeError::Length { expected: 16, found: usize::MAX }));
+    }
+
+    #[test]
+    fn encode_needs_room() {
+        let mut buffer = [0u8; 3];
+        let ping = Packet { seq: 0, message: Message::Ping(1) };
+        assert_eq!(ping.encode(&mut buffer), Err(EncodeError::BufferTooSmall { needed: 9 }));
+    }
+}
@@ -0,0 +1,136 @@
+// file: lz.rs
+// desc: a small LZ77 style compressor for Frame and Stroke payloads, light enough
+// to decompress on the Pico without a buffer beyond the output
+
+use crate::DecodeError;
+
+// Tokens start with a byte below 0x80 for (byte + 1) literal bytes that follow,
+// or 0x80 | (length - MIN_MATCH) for a copy of earlier output, followed by how
+// far back it starts
+const MATCH: u8 = 0x80;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = MIN_MATCH + 0x7f;
+const MAX_LITERALS: usize = 0x80;
+// How far back a copy may start, its distance is one byte
+const WINDOW: usize = 255;
+
+/// Compress `input` into `out`, returning the compressed length, or None if it doesn't fit
+pub fn compress(input: &[u8], out: &mut [u8]) -> Option<usize> {
+    let mut len = 0;
+    let mut literals_from = 0;
+    let mut pos = 0;
+    while pos < input.len() {
+        let (distance, length) = longest_match(input, pos);
+        if length < MIN_MATCH {
+            pos += 1;
+            continue;
+        }
+        len = push_literals(&input[literals_from..pos], out, len)?;
+        out.get_mut(len..len + 2)?
+            .copy_from_slice(&[MATCH | (length - MIN_MATCH) as u8, distance as u8]);
+        len += 2;
+        pos += length;
+        literals_from = pos;
+    }
+    push_literals(&input[literals_from..], out, len)
+}
+
+fn push_literals(literals: &[u8], out: &mut [u8], mut len: usize) -> Option<usize> {
+    for chunk in literals.chunks(MAX_LITERALS) {
+        let token = out.get_mut(len..len + 1 + chunk.len())?;
+        token[0] = (chunk.len() - 1) as u8;
+        token[1..].copy_from_slice(chunk);
+        len += token.len();
+    }
+    Some(len)
+}
+
+// (distance, length) of the longest earlier match for the bytes at `pos`. A
+// match may run into the bytes it repeats, the decoder copies byte by byte
+fn longest_match(input: &[u8], pos: usize) -> (usize, usize) {
+    let max = (input.len() - pos).min(MAX_MATCH);
+    let mut best = (0, 0);
+    for start in pos.saturating_sub(WINDOW)..pos {
+        let length = (0..max).take_while(|&i| input[start + i] == input[pos + i]).count();
+        if length > best.1 {
+            best = (pos - start, length);
+        }
+    }
+    best
+}
+
+/// Decompress `input` into `out`, returning the decompressed length
+pub fn decompress(input: &[u8], out: &mut [u8]) -> Result<usize, DecodeError> {
+    let mut bytes = input.iter().copied();
+    let mut len = 0;
+    while let Some(token) = bytes.next() {
+        if token < MATCH {
+            for _ in 0..=token {
+                let byte = bytes.next().ok_or(DecodeError::Truncated)?;
+                *out.get_mut(len).ok_or(DecodeError::Compression)? = byte;
+                len += 1;
+            }
+        } else {
+            let length = (token & !MATCH) as usize + MIN_MATCH;
+            let distance = bytes.next().ok_or(DecodeError::Truncated)? as usize;
+            if distance == 0 || distance > len {
+                return Err(DecodeError::Compression);
+            }
+            for _ in 0..length {
+                let byte = out[len - distance];
+                *out.get_mut(len).ok_or(DecodeError::Compression)? = byte;
+                len += 1;
+            }
+        }
+    }
+    Ok(len)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(input: &[u8]) -> usize {
+        let mut compressed = [0u8; 512];
+        let len = compress(input, &mut compressed).unwrap();
+        let mut out = [0u8; 512];
+        let out_len = decompress(&compressed[..len], &mut out).unwrap();
+        assert_eq!(&out[..out_len], input);
+        len
+    }
+
+    #[test]
+    fn repeats_shrink() {
+        let mut stroke = [0u8; 96];
+        for (i, pixel) in stroke.chunks_exact_mut(3).enumerate() {
+            pixel.copy_from_slice(&[10 + i as u8 % 4, 20, 1]);
+        }
+        assert!(round_trip(&stroke) < stroke.len() / 4);
+        assert!(round_trip(&[0; 300]) < 10);
+    }
+
+    #[test]
+    fn anything_round_trips() {
+        round_trip(&[]);
+        round_trip(&[7]);
+        let mut noise = [0u8; 200];
+        let mut state = 1u32;
+        for byte in noise.iter_mut() {
+            state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+            *byte = (state >> 16) as u8;
+        }
+        // Literal runs are split every 128 bytes
+        assert_eq!(round_trip(&noise), noise.len() + 2);
+    }
+
+    #[test]
+    fn rejects_bad_input() {
+        let mut out = [0u8; 8];
+        // A copy from before the start
+        assert_eq!(decompress(&[0, 1, MATCH, 2], &mut out), Err(DecodeError::Compression));
+        assert_eq!(decompress(&[2, 1], &mut out), Err(DecodeError::Truncated));
+        // More than fits
+        assert_eq!(decompress(&[0, 1, MATCH | 9, 1], &mut out), Err(DecodeError::Compression));
+        assert_eq!(compress(&[1, 2, 3], &mut out[..3]), None);
+    }
+}
@@ -0,0 +1,218 @@
+// file: sequence.rs
+// desc: sequence number bookkeeping, so either side can tell when messages went missing
+
+use crate::Message;
+
+// True if `seq` is `since` or came after it, allowing for wrap around
+fn reached(seq: u16, since: u16) -> bool {
+    (seq.wrapping_sub(since) as i16) >= 0
+}
+
+/// The receiving side. Counts what arrived in order and builds the cumulative
+/// Ack the sender watches for gaps
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SequenceTracker {
+    // (acked, latest), None until the first packet
+    seen: Option<(u16, u16)>,
+    unacked: u16,
+}
+
+impl SequenceTracker {
+    pub const fn new() -> Self {
+        Self { seen: None, unacked: 0 }
+    }
+
+    /// Note an arriving packet. A `resync` packet, i.e. a full frame, replaces
+    /// everything before it, so gaps before it no longer matter
+    pub fn receive(&mut self, seq: u16, resync: bool) {
+        self.seen = Some(match self.seen {
+            Some((acked, latest)) if !resync && (acked != latest || seq != latest.wrapping_add(1)) => (acked, seq),
+            _ => (seq, seq),
+        });
+        self.unacked = self.unacked.saturating_add(1);
+    }
+
+    /// Packets received since the last Ack
+    pub fn unacked(&self) -> u16 {
+        self.unacked
+    }
+
+    /// The Ack for everything received so far, with the `credit` the receiver
+    /// has left, or None if nothing has arrived
+    pub fn ack(&mut self, credit: u8) -> Option<Message<'static>> {
+        let (acked, latest) = self.seen?;
+        self.unacked = 0;
+        Some(Message::Ack { acked, latest, credit })
+    }
+}
+
+// How many of the latest packets' pixel counts are kept, for taking what is
+// still on its way off the credit in an Ack
+const COST_WINDOW: usize = 32;
+
+/// The sending side. Numbers outgoing packets, checks the Acks that come back
+/// and counts down the credit they grant
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct AckWatcher {
+    next: u16,
+    // A full frame sent to make up for a gap, later gaps only count once it is acked
+    resync: Option<u16>,
+    // When the oldest packet the peer hasn't acked was sent
+    waiting_since: Option<u64>,
+    // Pixels in each of the latest packets, by sequence number
+    costs: [u8; COST_WINDOW],
+    // None until the first Ack, or from a peer that never grants any
+    credit: Option<u16>,
+}
+
+impl AckWatcher {
+    pub const fn new() -> Self {
+        Self { next: 0, resync: None, waiting_since: None, costs: [0; COST_WINDOW], credit: None }
+    }
+
+    /// Sequence number for the next packet, which carries `pixels` pixels for
+    /// the peer's display. Mark full frames as `resync`
+    pub fn send(&mut self, now_ms: u64, resync: bool, pixels: u8) -> u16 {
+        let seq = self.next;
+        self.next = seq.wrapping_add(1);
+        self.costs[seq as usize % COST_WINDOW] = pixels;
+        self.credit = self.credit.map(|credit| credit.saturating_sub(pixels as u16));
+        if resync {
+            self.resync = Some(seq);
+            self.waiting_since = Some(now_ms);
+        } else {
+            self.waiting_since.get_or_insert(now_ms);
+        }
+        seq
+    }
+
+    /// Handle an Ack, returning true if the peer missed something and needs a full frame
+    pub fn acked(&mut self, now_ms: u64, acked: u16, latest: u16, credit: u8) -> bool {
+        let last_sent = self.next.wrapping_sub(1);
+        self.waiting_since = if latest == last_sent { None } else { Some(now_ms) };
+
+        // The credit was counted before the packets sent after `latest` arrived.
+        // Past the window too much is on its way to trust it at all
+        let in_flight = last_sent.wrapping_sub(latest) as usize;
+        self.credit = Some(if in_flight < COST_WINDOW {
+            let on_the_way = (1..=in_flight)
+                .map(|back| self.costs[latest.wrapping_add(back as u16) as usize % COST_WINDOW] as u16)
+                .sum();
+            (credit as u16).saturating_sub(on_the_way)
+        } else {
+            0
+        });
+
+        match self.resync {
+            // The gap is already being made up for
+            Some(resync) if !reached(latest, resync) => false,
+            _ => {
+                self.resync = None;
+                acked != latest
+            }
+        }
+    }
+
+    /// Pixels the peer has room for beyond what is already on its way, or None
+    /// if it hasn't said. Heartbeats keep the Acks coming while a sender holds back
+    pub fn credit(&self) -> Option<u16> {
+        self.credit
+    }
+
+    /// True once packets have gone unacknowledged for longer than `timeout_ms`
+    pub fn overdue(&self, now_ms: u64, timeout_ms: u64) -> bool {
+        self.waiting_since.is_some_and(|since| now_ms.saturating_sub(since) > timeout_ms)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ack(tracker: &mut SequenceTracker) -> (u16, u16) {
+        match tracker.ack(0) {
+            Some(Message::Ack { acked, latest, .. }) => (acked, latest),
+            other => panic!("expected an ack, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn nothing_to_ack_before_the_first_packet() {
+        assert_eq!(SequenceTracker::new().ack(0), None);
+    }
+
+    #[test]
+    fn in_order_packets_are_acked() {
+        let mut tracker = SequenceTracker::new();
+        for seq in [u16::MAX - 1, u16::MAX, 0, 1] {
+            tracker.receive(seq, false);
+        }
+        assert_eq!(tracker.unacked(), 4);
+        assert_eq!(ack(&mut tracker), (1, 1));
+        assert_eq!(tracker.unacked(), 0);
+    }
+
+    #[test]
+    fn gaps_hold_the_ack_back_until_a_resync() {
+        let mut tracker = SequenceTracker::new();
+        tracker.receive(1, false);
+        tracker.receive(3, false);
+        tracker.receive(4, false);
+        assert_eq!(ack(&mut tracker), (1, 4));
+
+        tracker.receive(5, true);
+        tracker.receive(6, false);
+        assert_eq!(ack(&mut tracker), (6, 6));
+    }
+
+    #[test]
+    fn watcher_asks_for_a_resync_once_per_gap() {
+        let mut watcher = AckWatcher::new();
+        for _ in 0..5 {
+            watcher.send(0, false, 1);
+        }
+        assert!(watcher.acked(10, 1, 4, 0));
+
+        let frame = watcher.send(20, true, 0);
+        // Acks already on their way don't cover the frame yet
+        assert!(!watcher.acked(30, 1, 4, 0));
+        assert!(!watcher.acked(40, frame, frame, 0));
+        assert!(!watcher.overdue(40, 0));
+    }
+
+    #[test]
+    fn watcher_notices_missing_acks() {
+        let mut watcher = AckWatcher::new();
+        assert!(!watcher.overdue(10_000, 2_000));
+
+        watcher.send(1_000, false, 1);
+        watcher.send(1_500, false, 1);
+        assert!(!watcher.overdue(3_000, 2_000));
+        assert!(watcher.overdue(3_001, 2_000));
+
+        assert!(!watcher.acked(3_100, 1, 1, 0));
+        assert!(!watcher.overdue(10_000, 2_000));
+    }
+
+    #[test]
+    fn credit_counts_what_is_still_on_its_way() {
+        let mut watcher = AckWatcher::new();
+        assert_eq!(watcher.credit(), None);
+
+        let first = watcher.send(0, false, 5);
+        watcher.send(0, false, 3);
+        watcher.send(0, false, 1);
+        // Granted before the last two arrived
+        watcher.acked(10, first, first, 20);
+        assert_eq!(watcher.credit(), Some(16));
+
+        watcher.send(20, false, 10);
+        assert_eq!(watcher.credit(), Some(6));
+        watcher.send(20, false, 10);
+        assert_eq!(watcher.credit(), Some(0));
+
+        let last = watcher.send(30, false, 0);
+        watcher.acked(40, last, last, 21);
+        assert_eq!(watcher.credit(), Some(21));
+    }
+}
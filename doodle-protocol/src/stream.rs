@@ -0,0 +1,151 @@
+// file: stream.rs
+// desc: reassemble compact drawing messages from a byte stream that may split them
+
+use crate::{DecodeError, DrawMessage};
+
+/// Reads compact DrawMessages out of a byte stream like the firmware's display
+/// pipe, where a read can stop partway through a message because the pipe's
+/// ring buffer wrapped or a write hasn't finished yet
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DrawStream {
+    partial: [u8; DrawMessage::MAX_LEN],
+    filled: usize,
+}
+
+impl DrawStream {
+    pub const fn new() -> Self {
+        Self { partial: [0; DrawMessage::MAX_LEN], filled: 0 }
+    }
+
+    /// Bytes the next message still needs. Read no more than this, so whatever
+    /// follows stays in the stream
+    pub fn missing(&self) -> usize {
+        self.len() - self.filled
+    }
+
+    // Length of the message being read, known once its state byte is in
+    fn len(&self) -> usize {
+        if self.filled < DrawMessage::LEN {
+            DrawMessage::LEN
+        } else {
+            DrawMessage::compact_len(self.partial[2])
+        }
+    }
+
+    /// Add bytes read from the stream, at most `missing()` of them. Returns the
+    /// message they complete, if any
+    pub fn push(&mut self, bytes: &[u8]) -> Option<Result<DrawMessage, DecodeError>> {
+        let take = bytes.len().min(self.missing());
+        self.partial[self.filled..self.filled + take].copy_from_slice(&bytes[..take]);
+        self.filled += take;
+
+        let len = self.len();
+        if self.filled < len {
+            return None;
+        }
+        self.filled = 0;
+        Some(DrawMessage::decode(&self.partial[..len]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::PixelState;
+
+    // A small ring buffer that, like embassy's Pipe, only reads and writes up to
+    // where the buffer wraps
+    struct Ring {
+        buffer: [u8; 8],
+        start: usize,
+        len: usize,
+    }
+
+    impl Ring {
+        fn write(&mut self, bytes: &[u8]) -> usize {
+            let end = (self.start + self.len) % self.buffer.len();
+            let room = (self.buffer.len() - self.len).min(self.buffer.len() - end);
+            let n = bytes.len().min(room);
+            self.buffer[end..end + n].copy_from_slice(&bytes[..n]);
+            self.len += n;
+            n
+        }
+
+        fn read(&mut self, out: &mut [u8]) -> usize {
+            let n = out.len().min(self.len).min(self.buffer.len() - self.start);
+            out[..n].copy_from_slice(&self.buffer[self.start..self.start + n]);
+            self.start = (self.start + n) % self.buffer.len();
+            self.len -= n;
+            n
+        }
+    }
+
+    #[test]
+    fn waits_for_the_whole_message() {
+        let mut stream = DrawStream::new();
+        assert_eq!(stream.push(&[4]), None);
+        assert_eq!(stream.missing(), 2);
+        assert_eq!(stream.push(&[5]), None);
+        assert_eq!(stream.push(&[1]), Some(Ok(DrawMessage::Pixel { x: 4, y: 5, state: PixelState::Set })));
+        assert_eq!(stream.missing(), DrawMessage::LEN);
+        assert_eq!(stream.push(&[255, 255, 2]), Some(Ok(DrawMessage::Clear)));
+    }
+
+    #[test]
+    fn clear_rects_read_their_size_too() {
+        let mut stream = DrawStream::new();
+        assert_eq!(stream.push(&[1, 2, 3]), None);
+        assert_eq!(stream.missing(), 2);
+        assert_eq!(
+            stream.push(&[4, 5]),
+            Some(Ok(DrawMessage::ClearRect { x: 1, y: 2, width: 4, height: 5 }))
+        );
+    }
+
+    #[test]
+    fn bad_messages_still_move_on() {
+        let mut stream = DrawStream::new();
+        assert_eq!(stream.push(&[1, 2, 9]), Some(Err(DecodeError::State(9))));
+        assert_eq!(
+            stream.push(&[1, 2, 0]),
+            Some(Ok(DrawMessage::Pixel { x: 1, y: 2, state: PixelState::Clear }))
+        );
+    }
+
+    #[test]
+    fn survives_partial_pipe_writes() {
+        let sent: [DrawMessage; 6] = core::array::from_fn(|i| match i {
+            3 => DrawMessage::ClearRect { x: 1, y: 2, width: 3, height: 4 },
+            i => DrawMessage::Pixel { x: i as u8, y: 10 + i as u8, state: PixelState::from(i % 2 == 0) },
+        });
+        let mut bytes = [0u8; 20];
+        let mut len = 0;
+        for message in sent.iter() {
+            let compact = message.encode();
+            bytes[len..len + compact.len()].copy_from_slice(&compact);
+            len += compact.len();
+        }
+        assert_eq!(len, bytes.len());
+
+        // Writes land in whatever room the ring has, reads stop at its end
+        let mut ring = Ring { buffer: [0; 8], start: 0, len: 0 };
+        let mut stream = DrawStream::new();
+        let mut written = 0;
+        let mut received = 0;
+        for write_size in [5, 1, 7, 2, 8, 4].into_iter().cycle() {
+            let end = (written + write_size).min(bytes.len());
+            written += ring.write(&bytes[written..end]);
+
+            let mut buffer = [0u8; DrawMessage::MAX_LEN];
+            let n = ring.read(&mut buffer[..stream.missing()]);
+            if let Some(message) = stream.push(&buffer[..n]) {
+                assert_eq!(message, Ok(sent[received]));
+                received += 1;
+            }
+            if received == sent.len() {
+                break;
+            }
+        }
+        assert_eq!(ring.len, 0);
+    }
+}
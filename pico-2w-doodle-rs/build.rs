@@ -10,9 +10,12 @@
 // https://doc.rust-lang.org/cargo/reference/build-scripts.html
 
 use std::env;
-use std::fs::File;
+use std::fs::{self, File};
 use std::io::Write;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+
+use flate2::write::GzEncoder;
+use flate2::Compression;
 
 fn main() {
     // Put `memory.x` in our output directory and ensure it's
@@ -33,4 +36,57 @@ fn main() {
     println!("cargo:rustc-link-arg-bins=--nmagic");
     println!("cargo:rustc-link-arg-bins=-Tlink.x");
     println!("cargo:rustc-link-arg-bins=-Tdefmt.x");
+
+    bundle_webapp(out);
+}
+
+// Gzip the files of a `trunk build --release` from the WEBAPP_DIST directory
+// into a table the firmware serves over HTTP. Without it the table is empty
+fn bundle_webapp(out: &Path) {
+    println!("cargo:rerun-if-env-changed=WEBAPP_DIST");
+    let mut assets = String::new();
+    let mut total = 0;
+
+    if let Some(dist) = env::var_os("WEBAPP_DIST") {
+        println!("cargo:rerun-if-changed={}", PathBuf::from(&dist).display());
+        for entry in fs::read_dir(&dist).expect("WEBAPP_DIST is not a directory") {
+            let path = entry.unwrap().path();
+            if !path.is_file() {
+                continue;
+            }
+            let name = path.file_name().unwrap().to_str().expect("webapp file names are UTF-8");
+
+            let mut gzip = GzEncoder::new(Vec::new(), Compression::best());
+            gzip.write_all(&fs::read(&path).unwrap()).unwrap();
+            let compressed = gzip.finish().unwrap();
+            total += compressed.len();
+
+            let bundled = out.join(format!("{}.gz", name));
+            fs::write(&bundled, compressed).unwrap();
+            assets += &format!(
+                "    Asset {{ path: \"/{}\", content_type: \"{}\", body: include_bytes!({:?}) }},\n",
+                name,
+                content_type(name),
+                bundled
+            );
+        }
+        // The firmware and the bundle share 2 MiB of flash
+        if total > 1024 * 1024 {
+            println!("cargo:warning=The webapp is {} KiB gzipped, is WEBAPP_DIST a release build?", total / 1024);
+        }
+    }
+
+    fs::write(out.join("webapp_assets.rs"), format!("static ASSETS: &[Asset] = &[\n{}];\n", assets)).unwrap();
+}
+
+fn content_type(name: &str) -> &'static str {
+    match name.rsplit('.').next() {
+        Some("html") => "text/html; charset=utf-8",
+        Some("js") => "text/javascript",
+        Some("wasm") => "application/wasm",
+        Some("css") => "text/css",
+        Some("svg") => "image/svg+xml",
+        Some("webmanifest") => "application/manifest+json",
+        _ => "application/octet-stream",
+    }
 }
\ No newline at end of file
@@ -6,72 +6,407 @@ use embedded_graphics::{
     mono_font::{ascii::FONT_6X10, MonoTextStyle},
     pixelcolor::BinaryColor,
     prelude::*,
-    text::Text,
+    primitives::{PrimitiveStyle, Rectangle},
+    text::{Alignment, Text},
     Pixel,
 };
+use qrcodegen_no_heap::{QrCode, QrCodeEcc, Version};
 
 use defmt::{info, error, warn};
-use embassy_sync::pipe::{Reader};
+use embassy_sync::pipe::{Pipe};
 use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
-use embassy_time::Timer;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::signal::Signal;
+use core::cell::RefCell;
+use core::sync::atomic::{AtomicU32, Ordering};
+use embassy_time::{with_timeout, Duration, Instant, Timer};
+use core::fmt::Write as _;
+use heapless::String;
 
 // Import from crate root
 use crate::setup_devices::Display;
+use crate::wifi_scan::{scan_results, SCAN_DONE};
+use crate::networking_task::{DrawingEvent, DEVICE_ADDRESS, DRAWING_EVENTS};
+use doodle_protocol::{DrawMessage, DrawStream, PixelState, DEVICE_AUTHOR};
 
 // Constants
-const CANVAS_SIZE: usize = 48;
+// The canvas covers the whole 128x48 area below the title, so both square
+// (48x48) and full-width (128x48) webapp grids map one-to-one onto the panel
+pub const CANVAS_WIDTH: usize = 128;
+pub const CANVAS_HEIGHT: usize = 48;
 const DISPLAY_OFFSET_Y: i32 = 16; 
 
+type Canvas = [[bool; CANVAS_WIDTH]; CANVAS_HEIGHT];
+
+// Packed copy of the canvas, row-major with the first pixel in the top bit, so
+// clients can ask for what the OLED shows
+pub const FRAME_BYTES: usize = CANVAS_WIDTH * CANVAS_HEIGHT / 8;
+pub static FRAME: Mutex<CriticalSectionRawMutex, RefCell<[u8; FRAME_BYTES]>> =
+    Mutex::new(RefCell::new([0; FRAME_BYTES]));
+
+fn publish_frame(drawing_canvas: &Canvas) {
+    let mut frame = [0u8; FRAME_BYTES];
+    for (i, pixel) in drawing_canvas.iter().flatten().enumerate() {
+        if *pixel {
+            frame[i / 8] |= 0x80 >> (i % 8);
+        }
+    }
+    FRAME.lock(|shared| *shared.borrow_mut() = frame);
+}
+
+// What a client recognized the drawing as, shown in the title bar until the canvas changes
+#[derive(Clone, Copy)]
+pub struct Prediction {
+    pub class: u8,
+    // 0 to 255, certain
+    pub confidence: u8,
+}
+
+pub static PREDICTION: Signal<CriticalSectionRawMutex, Prediction> = Signal::new();
+
+// A whole canvas sent by a client to resync the OLED, packed like FRAME, with
+// the slot of the client that sent it
+pub static CANVAS_SYNC: Signal<CriticalSectionRawMutex, (usize, [u8; FRAME_BYTES])> = Signal::new();
+
+fn apply_frame(drawing_canvas: &mut Canvas, frame: &[u8; FRAME_BYTES]) {
+    for (i, pixel) in drawing_canvas.iter_mut().flatten().enumerate() {
+        *pixel = frame[i / 8] & (0x80 >> (i % 8)) != 0;
+    }
+}
+
+// Carries compact DrawMessages from every client connection to the display, each
+// after the id of its author. Room for 21 pixels
+pub type DrawingPipe = Pipe<CriticalSectionRawMutex, { 21 * (1 + DrawMessage::LEN) }>;
+
+// How often a connection checks for room in a full drawing pipe
+const PIPE_RETRY: Duration = Duration::from_millis(2);
+
+// Minutes without input before the canvas clears itself, for public installations.
+// 0 disables it. Set IDLE_CLEAR_MINUTES at build time, clients can change it at runtime
+pub static IDLE_CLEAR_MINUTES: AtomicU32 = AtomicU32::new(parse_minutes(option_env!("IDLE_CLEAR_MINUTES")));
+
+const fn parse_minutes(text: Option<&str>) -> u32 {
+    let Some(text) = text else {
+        return 0;
+    };
+    let bytes = text.as_bytes();
+    let mut minutes = 0;
+    let mut i = 0;
+    while i < bytes.len() {
+        assert!(bytes[i].is_ascii_digit(), "IDLE_CLEAR_MINUTES must be a whole number of minutes");
+        minutes = minutes * 10 + (bytes[i] - b'0') as u32;
+        i += 1;
+    }
+    minutes
+}
+
+// Consecutive set pixels from one author arriving within this window are treated
+// as one stroke and joined with a line on the OLED, so fast or batched strokes
+// don't show up as scattered dots. Larger jumps are a new stroke and are left
+// unconnected. The lines are only shown, FRAME and the clients have the pixels
+// as they were drawn
+const STROKE_GAP: Duration = Duration::from_millis(80);
+const MAX_INTERPOLATION_STEP: usize = 8;
+
+// Authors whose strokes are followed at once, one more pushes out the one that
+// drew longest ago
+const STROKE_AUTHORS: usize = 4;
+
+// Last set pixel of an author's stroke in progress
+#[derive(Clone, Copy)]
+struct StrokePoint {
+    author: u8,
+    x: usize,
+    y: usize,
+    at: Instant,
+}
+
+// What reading the drawing pipe carries from one update to the next
+struct PipeReader {
+    stream: DrawStream,
+    // Author of the message being read, once its byte is in
+    author: Option<u8>,
+    strokes: [Option<StrokePoint>; STROKE_AUTHORS],
+}
+
+impl PipeReader {
+    fn new() -> Self {
+        PipeReader { stream: DrawStream::new(), author: None, strokes: [None; STROKE_AUTHORS] }
+    }
+
+    // Where `author`'s stroke is kept, or the slot it can take
+    fn stroke_slot(&self, author: u8) -> usize {
+        let mine = self.strokes.iter().position(|point| point.is_some_and(|point| point.author == author));
+        let free = || self.strokes.iter().position(|point| point.is_none());
+        let oldest = || (0..STROKE_AUTHORS).min_by_key(|&i| self.strokes[i].map(|point| point.at)).unwrap_or(0);
+        mine.or_else(free).unwrap_or_else(oldest)
+    }
+}
+
+// Bresenham line between two canvas points, both ends included
+fn draw_line(drawing_canvas: &mut Canvas, from: (usize, usize), to: (usize, usize)) {
+    let (mut x, mut y) = (from.0 as i32, from.1 as i32);
+    let (x1, y1) = (to.0 as i32, to.1 as i32);
+    let dx = (x1 - x).abs();
+    let dy = -(y1 - y).abs();
+    let step_x = if x < x1 { 1 } else { -1 };
+    let step_y = if y < y1 { 1 } else { -1 };
+    let mut error = dx + dy;
+
+    loop {
+        drawing_canvas[y as usize][x as usize] = true;
+        if x == x1 && y == y1 {
+            break;
+        }
+        let doubled = 2 * error;
+        if doubled >= dy {
+            error += dy;
+            x += step_x;
+        }
+        if doubled <= dx {
+            error += dx;
+            y += step_y;
+        }
+    }
+}
+
+// Optional URL shown as a QR code at boot so phones can join by scanning the display.
+// Versions above 3 (29x29 modules) don't fit the 64 px panel with a quiet zone at 2x scale
+const WEBAPP_URL: Option<&str> = option_env!("WEBAPP_URL");
+const QR_MAX_VERSION: u8 = 3;
+const QR_SCALE: i32 = 2;
+const QR_SPLASH_SECS: u64 = 10;
+
+fn draw_qr_code(display: &mut Display, text: &str) -> bool {
+    let mut out_buffer = [0u8; Version::new(QR_MAX_VERSION).buffer_len()];
+    let mut temp_buffer = [0u8; Version::new(QR_MAX_VERSION).buffer_len()];
+
+    let qr = match QrCode::encode_text(
+        text,
+        &mut temp_buffer,
+        &mut out_buffer,
+        QrCodeEcc::Low,
+        Version::MIN,
+        Version::new(QR_MAX_VERSION),
+        None,
+        true,
+    ) {
+        Ok(qr) => qr,
+        Err(_) => {
+            warn!("URL too long for an OLED QR code: {}", text);
+            return false;
+        }
+    };
+
+    // Lit background with dark modules, centered on the panel
+    let side = (qr.size() + 2) * QR_SCALE;
+    let origin = Point::new((128 - side) / 2, (64 - side) / 2);
+    Rectangle::new(origin, Size::new(side as u32, side as u32))
+        .into_styled(PrimitiveStyle::with_fill(BinaryColor::On))
+        .draw(display)
+        .unwrap();
+
+    for y in 0..qr.size() {
+        for x in 0..qr.size() {
+            if qr.get_module(x, y) {
+                let module_origin = origin + Point::new((x + 1) * QR_SCALE, (y + 1) * QR_SCALE);
+                Rectangle::new(module_origin, Size::new(QR_SCALE as u32, QR_SCALE as u32))
+                    .into_styled(PrimitiveStyle::with_fill(BinaryColor::Off))
+                    .draw(display)
+                    .unwrap();
+            }
+        }
+    }
+    true
+}
+
+// Networks found at boot, strongest first. The scan runs before joining WiFi, so
+// don't hold up the canvas for long if it never finishes
+const SCAN_WAIT: Duration = Duration::from_secs(10);
+const SCAN_SCREEN_SECS: u64 = 8;
+const SCAN_SCREEN_LINES: usize = 5;
+
+fn draw_scan_results(display: &mut Display, text_style: MonoTextStyle<'_, BinaryColor>) {
+    let results = scan_results();
+
+    let mut title: String<21> = String::new();
+    let _ = write!(title, "WiFi: {} found", results.len());
+    Text::new(&title, Point::new(0, 10), text_style)
+        .draw(display)
+        .unwrap();
+
+    for (i, entry) in results.iter().take(SCAN_SCREEN_LINES).enumerate() {
+        // 21 columns at 6 px: "-67 c11 " then as much of the SSID as fits
+        let mut line: String<21> = String::new();
+        let _ = write!(line, "{} c{} ", entry.rssi, entry.channel);
+        for c in entry.ssid.chars() {
+            if line.push(c).is_err() {
+                break;
+            }
+        }
+        Text::new(&line, Point::new(0, 21 + 10 * i as i32), text_style)
+            .draw(display)
+            .unwrap();
+    }
+}
+
+// Apply everything queued in the drawing pipe, so a batched stroke costs one
+// display flush instead of one per pixel. `lines` gets the pixels joining up
+// strokes, for the OLED only. Returns true if the canvas changed
 async fn update_canvas(
-    drawing_canvas: &mut [[bool; CANVAS_SIZE]; CANVAS_SIZE],
-    pipe_reader: &mut Reader<'static, CriticalSectionRawMutex, 64>
+    drawing_canvas: &mut Canvas,
+    lines: &mut Canvas,
+    reader: &mut PipeReader,
+    drawing_pipe: &'static DrawingPipe,
 ) -> bool {
-    // Try to read 3 bytes (non-blocking)
-    let mut buffer = [0u8; 3];
-    match pipe_reader.try_read(&mut buffer) {
-        Ok(bytes_read) if bytes_read == 3 => {
-            let x = buffer[0];
-            let y = buffer[1];
-            let state = buffer[2];
-            
-            // Check for clear command
-            if x == 255 && y == 255 && state == 2 {
+    let mut updated = false;
+    loop {
+        // Never read past the current message, the pipe may hold only part of it
+        // where its buffer wraps. Each message follows its author's byte
+        let mut buffer = [0u8; DrawMessage::MAX_LEN];
+        let wanted = if reader.author.is_some() { reader.stream.missing() } else { 1 };
+        let Ok(bytes_read) = drawing_pipe.try_read(&mut buffer[..wanted]) else {
+            // Nothing more queued
+            return updated;
+        };
+        let Some(author) = reader.author else {
+            reader.author = Some(buffer[0]);
+            continue;
+        };
+        
+        let Some(message) = reader.stream.push(&buffer[..bytes_read]) else {
+            continue;
+        };
+        reader.author = None;
+        let (x, y, state) = match message {
+            Ok(DrawMessage::Clear) => {
                 info!("Clearing canvas");
-                for row in drawing_canvas.iter_mut() {
-                    for pixel in row.iter_mut() {
-                        *pixel = false;
-                    }
-                }
-                return true;
+                reader.strokes = [None; STROKE_AUTHORS];
+                clear_canvas(drawing_canvas);
+                clear_canvas(lines);
+                updated = true;
+                continue;
             }
+            Ok(DrawMessage::ClearRect { x, y, width, height }) => {
+                info!("Clearing {}x{} at x={}, y={}", width, height, x, y);
+                reader.strokes = [None; STROKE_AUTHORS];
+                clear_rect(drawing_canvas, x as usize, y as usize, width as usize, height as usize);
+                clear_rect(lines, x as usize, y as usize, width as usize, height as usize);
+                updated = true;
+                continue;
+            }
+            Ok(DrawMessage::Pixel { x, y, state }) => (x, y, state),
+            Err(_) => {
+                warn!("Malformed message in drawing pipe");
+                continue;
+            }
+        };
+        
+        // Update pixel if coordinates are valid
+        if (x as usize) < CANVAS_WIDTH && (y as usize) < CANVAS_HEIGHT {
+            let (x, y) = (x as usize, y as usize);
+            let now = Instant::now();
             
-            // Update pixel if coordinates are valid
-            if (x as usize) < CANVAS_SIZE && (y as usize) < CANVAS_SIZE {
-                drawing_canvas[y as usize][x as usize] = state == 1;
-                info!("Updated pixel: x={}, y={}, state={}", x, y, state == 1);
-                return true;
-            } else {
-                warn!("Invalid coordinates: x={}, y={}", x, y);
+            // The pixel itself is exact, gray dithered as the display only has on
+            // and off. Whatever was drawn over it wins over a joining line
+            drawing_canvas[y][x] = state.apply(drawing_canvas[y][x], x, y);
+            lines[y][x] = false;
+            
+            // Only drawing is joined up into lines, with the same author's last pixel
+            let slot = reader.stroke_slot(author);
+            match reader.strokes[slot].take() {
+                Some(last) if last.author == author
+                    && state == PixelState::Set
+                    && now - last.at <= STROKE_GAP
+                    && last.x.abs_diff(x).max(last.y.abs_diff(y)) <= MAX_INTERPOLATION_STEP =>
+                {
+                    draw_line(lines, (last.x, last.y), (x, y));
+                }
+                _ => {}
             }
-        },
-        Ok(_) => {
-            // Partial read, ignore for now
-        },
-        Err(_) => {
-            // No data available or error, this is normal
+            if state == PixelState::Set {
+                reader.strokes[slot] = Some(StrokePoint { author, x, y, at: now });
+            }
+            
+            info!("Updated pixel: x={}, y={}, on={}", x, y, drawing_canvas[y][x]);
+            updated = true;
+        } else {
+            warn!("Invalid coordinates: x={}, y={}", x, y);
+        }
+    }
+}
+
+/// How many more pixels the display's queue has room for, what clients are told
+/// they may send before the next Ack
+pub fn drawing_credit(drawing_pipe: &DrawingPipe) -> u8 {
+    (drawing_pipe.free_capacity() / (1 + DrawMessage::LEN)) as u8
+}
+
+// Queue a message from `author` for the display. It goes in whole once there is
+// room, so messages from different connections never interleave partway through
+pub async fn queue_drawing(drawing_pipe: &DrawingPipe, author: u8, message: DrawMessage) {
+    let compact = message.encode();
+    let mut bytes = [0u8; 1 + DrawMessage::MAX_LEN];
+    bytes[0] = author;
+    bytes[1..1 + compact.len()].copy_from_slice(&compact);
+    let bytes = &bytes[..1 + compact.len()];
+    while drawing_pipe.free_capacity() < bytes.len() {
+        Timer::after(PIPE_RETRY).await;
+    }
+    
+    // No await from here on. Only where the pipe's buffer wraps does it take two writes
+    let mut written = 0;
+    while written < bytes.len() {
+        match drawing_pipe.try_write(&bytes[written..]) {
+            Ok(n) => written += n,
+            Err(_) => break,
         }
     }
-    false
+}
+
+fn draw_prediction(display: &mut Display, prediction: Prediction, text_style: MonoTextStyle<'_, BinaryColor>) {
+    let mut text: String<16> = String::new();
+    let percent = prediction.confidence as u32 * 100 / 255;
+    let _ = write!(text, "{} {}%", prediction.class, percent);
+    Text::with_alignment(&text, Point::new(CANVAS_WIDTH as i32 - 1, 10), text_style, Alignment::Right)
+        .draw(display)
+        .unwrap();
+}
+
+fn clear_canvas(drawing_canvas: &mut Canvas) {
+    for row in drawing_canvas.iter_mut() {
+        for pixel in row.iter_mut() {
+            *pixel = false;
+        }
+    }
+}
+
+// Turn off the part of a rectangle that lies on the canvas
+fn clear_rect(drawing_canvas: &mut Canvas, x: usize, y: usize, width: usize, height: usize) {
+    for row in drawing_canvas.iter_mut().skip(y).take(height) {
+        for pixel in row.iter_mut().skip(x).take(width) {
+            *pixel = false;
+        }
+    }
+}
+
+// True once the canvas has had ink and no input for the configured idle time
+fn idle_timed_out(drawing_canvas: &Canvas, last_input: Instant) -> bool {
+    let minutes = IDLE_CLEAR_MINUTES.load(Ordering::Relaxed);
+    minutes > 0
+        && last_input.elapsed() >= Duration::from_secs(minutes as u64 * 60)
+        && drawing_canvas.iter().flatten().any(|pixel| *pixel)
 }
 
 fn draw_canvas_to_display(
     display: &mut Display,
-    drawing_canvas: &[[bool; CANVAS_SIZE]; CANVAS_SIZE]
+    drawing_canvas: &Canvas,
+    lines: &Canvas,
 ) {
-    // Draw each pixel from the canvas
-    for (y, row) in drawing_canvas.iter().enumerate() {
-        for (x, &pixel_state) in row.iter().enumerate() {
-            if pixel_state {
+    // Draw each pixel from the canvas, and the lines joining up strokes
+    for (y, (row, line_row)) in drawing_canvas.iter().zip(lines.iter()).enumerate() {
+        for (x, (&pixel_state, &on_line)) in row.iter().zip(line_row.iter()).enumerate() {
+            if pixel_state || on_line {
                 // Calculate display position
                 let display_x = x as i32;
                 let display_y = (y as i32) + DISPLAY_OFFSET_Y;
@@ -90,15 +425,41 @@ fn draw_canvas_to_display(
 #[embassy_executor::task]
 pub async fn display_task(
     mut display: Display,
-    mut pipe_reader: Reader<'static, CriticalSectionRawMutex, 64>,
+    drawing_pipe: &'static DrawingPipe,
 ) {
     info!("Display task started");
 
     // Create text style
     let text_style = MonoTextStyle::new(&FONT_6X10, BinaryColor::On);
 
-    // Initialize drawing canvas (48x48 grid)
-    let mut drawing_canvas: [[bool; CANVAS_SIZE]; CANVAS_SIZE] = [[false; CANVAS_SIZE]; CANVAS_SIZE];
+    // Initialize drawing canvas (128x48 grid)
+    let mut drawing_canvas: Canvas = [[false; CANVAS_WIDTH]; CANVAS_HEIGHT];
+    let mut lines: Canvas = [[false; CANVAS_WIDTH]; CANVAS_HEIGHT];
+    let mut reader = PipeReader::new();
+    let mut prediction: Option<Prediction> = None;
+    // Shown in place of the title while the canvas is blank, so the device can be found
+    let mut address: String<16> = String::new();
+    let mut offline = false;
+    
+    // Show the webapp QR code for a while before switching to the canvas
+    if let Some(url) = WEBAPP_URL {
+        display.clear(BinaryColor::Off).unwrap();
+        if draw_qr_code(&mut display, url) && display.flush().is_ok() {
+            info!("Showing QR code for {}", url);
+            Timer::after_secs(QR_SPLASH_SECS).await;
+        }
+    }
+    
+    // Then the scan results, to help pick a spot with a strong signal
+    if with_timeout(SCAN_WAIT, SCAN_DONE.wait()).await.is_ok() {
+        display.clear(BinaryColor::Off).unwrap();
+        draw_scan_results(&mut display, text_style);
+        if display.flush().is_ok() {
+            Timer::after_secs(SCAN_SCREEN_SECS).await;
+        }
+    } else {
+        warn!("No WiFi scan results to show");
+    }
     
     // Initial display setup
     display.clear(BinaryColor::Off).unwrap();
@@ -111,22 +472,85 @@ pub async fn display_task(
         Err(_) => error!("Initial display flush failed"),
     }
     
+    let mut last_input = Instant::now();
+    
     loop {
-        // Check for pipe updates (non-blocking check)
-        let canvas_updated = update_canvas(&mut drawing_canvas, &mut pipe_reader).await;
+        // Take a resync from a client first, otherwise check for pipe updates (non-blocking check)
+        let mut resynced_by = None;
+        let mut canvas_updated = if let Some((slot, frame)) = CANVAS_SYNC.try_take() {
+            info!("Resyncing canvas from a client frame");
+            apply_frame(&mut drawing_canvas, &frame);
+            clear_canvas(&mut lines);
+            reader.strokes = [None; STROKE_AUTHORS];
+            resynced_by = Some(slot);
+            true
+        } else {
+            update_canvas(&mut drawing_canvas, &mut lines, &mut reader, drawing_pipe).await
+        };
+        if canvas_updated {
+            last_input = Instant::now();
+        } else if idle_timed_out(&drawing_canvas, last_input) {
+            info!("Canvas idle, clearing");
+            clear_canvas(&mut drawing_canvas);
+            clear_canvas(&mut lines);
+            reader.strokes = [None; STROKE_AUTHORS];
+            last_input = Instant::now();
+            // Tell every connected client so browsers stay in sync
+            DRAWING_EVENTS.immediate_publisher().publish_immediate(DrawingEvent::Draw {
+                from: None,
+                author: DEVICE_AUTHOR,
+                message: DrawMessage::Clear,
+            });
+            canvas_updated = true;
+        }
         
-        // Only redraw if canvas was updated
+        // A prediction is for the drawing as it was, a new one follows any change
+        let mut redraw = canvas_updated;
         if canvas_updated {
+            prediction = None;
+        }
+        if let Some(new) = PREDICTION.try_take() {
+            prediction = Some(new);
+            redraw = true;
+        }
+        if let Some(new) = DEVICE_ADDRESS.try_take() {
+            offline = new.is_none();
+            if let Some(new) = new {
+                address.clear();
+                let _ = write!(address, "{}", new);
+            }
+            redraw = true;
+        }
+        
+        // Only redraw if canvas or prediction was updated
+        if redraw {
+            publish_frame(&drawing_canvas);
+            // Other clients get the resynced canvas once FRAME holds it
+            if let Some(from) = resynced_by {
+                DRAWING_EVENTS.immediate_publisher().publish_immediate(DrawingEvent::Canvas { from });
+            }
+            
             // Clear the display
             display.clear(BinaryColor::Off).unwrap();
             
-            // Draw title in the top section
-            Text::new("Doodle rs", Point::new(0, 10), text_style)
+            // Draw title in the top section, with the prediction on the right
+            let blank = drawing_canvas.iter().flatten().all(|pixel| !pixel);
+            let title = if offline {
+                "No WiFi"
+            } else if blank && !address.is_empty() {
+                address.as_str()
+            } else {
+                "Doodle rs"
+            };
+            Text::new(title, Point::new(0, 10), text_style)
                 .draw(&mut display)
                 .unwrap();
+            if let Some(prediction) = prediction {
+                draw_prediction(&mut display, prediction, text_style);
+            }
             
             // Draw the canvas pixels
-            draw_canvas_to_display(&mut display, &drawing_canvas);
+            draw_canvas_to_display(&mut display, &drawing_canvas, &lines);
             
             // Update display
             match display.flush() {
@@ -0,0 +1,92 @@
+// file: json_command.rs
+// desc: small JSON commands sent as WebSocket text, so the Pico can be poked from
+// e.g. websocat without the webapp
+
+use core::fmt::Write as _;
+use heapless::String;
+use serde::Deserialize;
+
+use doodle_protocol::{DrawMessage, PixelState, Status};
+
+// Longest reply, a status report
+pub const REPLY_LEN: usize = 224;
+
+// {"cmd":"clear"}, {"cmd":"status"}, {"cmd":"pixel","x":3,"y":4,"on":false} or
+// {"cmd":"idle","minutes":5}. Fields a command doesn't use are ignored
+#[derive(Deserialize)]
+struct Request<'a> {
+    cmd: &'a str,
+    x: Option<u8>,
+    y: Option<u8>,
+    on: Option<bool>,
+    minutes: Option<u32>,
+}
+
+pub enum JsonCommand {
+    Draw(DrawMessage),
+    Status,
+    // Set the idle clear timeout, or just report it
+    Idle(Option<u32>),
+}
+
+/// True if `text` looks like a JSON command rather than a plain text one
+pub fn is_json(text: &str) -> bool {
+    text.trim_start().starts_with('{')
+}
+
+/// Parse a command, or say what was wrong with it
+pub fn parse(text: &str) -> Result<JsonCommand, &'static str> {
+    let (request, _) = serde_json_core::from_str::<Request>(text).map_err(|_| "malformed json")?;
+    match request.cmd {
+        "clear" => Ok(JsonCommand::Draw(DrawMessage::Clear)),
+        "pixel" => {
+            let (Some(x), Some(y)) = (request.x, request.y) else {
+                return Err("pixel needs x and y");
+            };
+            let state = PixelState::from(request.on.unwrap_or(true));
+            Ok(JsonCommand::Draw(DrawMessage::Pixel { x, y, state }))
+        }
+        "status" => Ok(JsonCommand::Status),
+        "idle" => Ok(JsonCommand::Idle(request.minutes)),
+        _ => Err("unknown cmd"),
+    }
+}
+
+pub fn write_ok(reply: &mut String<REPLY_LEN>) {
+    let _ = reply.push_str(r#"{"ok":true}"#);
+}
+
+pub fn write_error(error: &str, reply: &mut String<REPLY_LEN>) {
+    let _ = write!(reply, r#"{{"error":"{}"}}"#, error);
+}
+
+pub fn write_idle(minutes: u32, reply: &mut String<REPLY_LEN>) {
+    let _ = write!(reply, r#"{{"idle_minutes":{}}}"#, minutes);
+}
+
+/// `text` as a quoted JSON string
+pub fn write_string<W: core::fmt::Write>(out: &mut W, text: &str) -> core::fmt::Result {
+    out.write_char('"')?;
+    for c in text.chars() {
+        match c {
+            '"' => out.write_str("\\\"")?,
+            '\\' => out.write_str("\\\\")?,
+            c if (c as u32) < 0x20 => write!(out, "\\u{:04x}", c as u32)?,
+            c => out.write_char(c)?,
+        }
+    }
+    out.write_char('"')
+}
+
+pub fn write_status(status: &Status, reply: &mut String<REPLY_LEN>) {
+    let _ = write!(
+        reply,
+        r#"{{"pixels_on":{},"uptime_secs":{},"rssi":{},"clients":{},"corrupt_messages":{},"#,
+        status.pixels_on, status.uptime_secs, status.rssi, status.clients, status.corrupt_messages,
+    );
+    let _ = write!(
+        reply,
+        r#""channel":{},"joins":{},"socket_errors":{},"dropped_events":{}}}"#,
+        status.channel, status.joins, status.socket_errors, status.dropped_events,
+    );
+}
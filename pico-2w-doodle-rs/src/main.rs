@@ -6,9 +6,8 @@
 
 use defmt::*;
 use embassy_executor::Spawner;
+use embassy_rp::flash::{Blocking, Flash};
 use embassy_time::Timer;
-use embassy_sync::pipe::{Pipe};
-use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
 use static_cell::StaticCell;
 use {defmt_rtt as _, panic_probe as _};
 
@@ -18,9 +17,15 @@ use setup_devices::{setup_display, setup_wifi};
 
 // Import task mods
 mod display_task;
-use display_task::{display_task};
+use display_task::{display_task, DrawingPipe};
 mod networking_task;
 use networking_task::{networking_task};
+mod wifi_scan;
+mod json_command;
+mod udp_task;
+mod provisioning;
+mod webapp_assets;
+use provisioning::FLASH_SIZE;
 
 // Program metadata for `picotool info`.
 const PROGRAM_NAME: &core::ffi::CStr = c"Pico 2W Doodle rs";
@@ -32,7 +37,7 @@ pub static PICOTOOL_ENTRIES: [embassy_rp::binary_info::EntryAddr; 3] = [
     embassy_rp::binary_info::rp_program_build_attribute!(),
 ];
 
-static DRAWING_PIPE: StaticCell<Pipe<CriticalSectionRawMutex, 64>> = StaticCell::new();
+static DRAWING_PIPE: StaticCell<DrawingPipe> = StaticCell::new();
 
 
 #[embassy_executor::main]
@@ -40,9 +45,8 @@ async fn main(spawner: Spawner) {
     // Initialize peripherals
     let p = embassy_rp::init(Default::default());
 
-    // Initialize the pipe and split it
-    let drawing_pipe = DRAWING_PIPE.init(Pipe::new());
-    let (reader, writer) = drawing_pipe.split();
+    // Initialize the pipe, shared by every client connection and the display
+    let drawing_pipe: &'static DrawingPipe = DRAWING_PIPE.init(DrawingPipe::new());
     
     // Setup individual components
     let display = setup_display(p.I2C0, 
@@ -59,11 +63,14 @@ async fn main(spawner: Spawner) {
         &spawner
     ).await;
     
+    // Holds the WiFi network given through the setup access point
+    let flash = Flash::<_, Blocking, FLASH_SIZE>::new_blocking(p.FLASH);
+    
     info!("System initialization complete!");
 
     // Create tasks
-    spawner.spawn(display_task(display, reader)).unwrap();
-    spawner.spawn(networking_task(wifi_stack, writer)).unwrap();
+    spawner.spawn(display_task(display, drawing_pipe)).unwrap();
+    spawner.spawn(networking_task(wifi_stack, flash, drawing_pipe)).unwrap();
     
     // Main animation loop
     loop {
@@ -1,33 +1,256 @@
 // file: networking_task.rs
 // desc: handle networking with WebSocket support
 
-use defmt::{info, warn};
+use defmt::{error, info, warn};
+use core::fmt::Write as _;
 use core::str::from_utf8;
+use heapless::String;
 
-use embassy_sync::pipe::{Writer};
 use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
-use embassy_net::tcp::TcpSocket;
+use embassy_sync::pubsub::{PubSubChannel, Subscriber, WaitResult};
+use embassy_sync::signal::Signal;
+use embassy_executor::Spawner;
+use embassy_net::{ConfigV4, IpAddress, Ipv4Address, Stack};
+use embassy_net::tcp::{self, TcpSocket};
 use cyw43::JoinOptions;
-use embassy_time::{Duration, Timer};
+use embassy_time::{with_timeout, Duration, Instant, Timer};
+use embassy_futures::select::{select, select3, Either, Either3};
+use core::sync::atomic::{AtomicU32, AtomicU8, Ordering};
+use core::cell::RefCell;
+use embassy_sync::blocking_mutex::Mutex;
 
 use embedded_websocket as ws;
-use embedded_websocket::{WebSocketSendMessageType, WebSocketReceiveMessageType};
+use embedded_websocket::{WebSocketCloseStatusCode, WebSocketSendMessageType, WebSocketReceiveMessageType};
 
-use crate::setup_devices::WifiStack;
+use doodle_protocol::{
+    decode_cbor, decode_runs, decompress_packet, encode_cbor, encode_runs, is_cbor, stroke_pixels, Capabilities, DecodeError,
+    DrawMessage, EncodeError, Message, Packet, PixelState, Role, SequenceTracker,
+    Status, CHECKSUM_LEN, DEVICE_AUTHOR, HEADER_LEN, MAX_CONTROL_LEN, MAX_MESSAGE_LEN, MAX_STROKE_PIXELS, VERSION,
+};
 
-// Source from env variables WIFI_ID, WIFI_PASS
-const WIFI_NETWORK: &str = env!("WIFI_ID");
-const WIFI_PASSWORD: &str = env!("WIFI_PASS");
+use crate::setup_devices::{dhcp_config, static_config, WifiStack, DEVICE_HOSTNAME};
+use crate::display_task::{drawing_credit, queue_drawing, DrawingPipe, Prediction, PREDICTION, CANVAS_WIDTH, CANVAS_HEIGHT, CANVAS_SYNC, FRAME, FRAME_BYTES, IDLE_CLEAR_MINUTES};
+use crate::wifi_scan::{find_network, measure_network, scan_networks, write_status, MAX_SCAN_RESULTS};
+use crate::json_command::{self, JsonCommand};
+use crate::udp_task::udp_task;
+use crate::provisioning::{load_credentials, provision, url_decode, CredentialFlash, Credentials};
+use crate::webapp_assets::find_asset;
+
+// Network to try when none was set up through the setup access point, source
+// from env variables WIFI_ID, WIFI_PASS. Without them the device starts in setup
+const WIFI_NETWORK: Option<&str> = option_env!("WIFI_ID");
+const WIFI_PASSWORD: &str = match option_env!("WIFI_PASS") {
+    Some(password) => password,
+    None => "",
+};
+
+// Clients have to give this to draw, unless it's empty. Set through the setup
+// access point, or with the ACCESS_TOKEN env variable
+const BUILT_IN_TOKEN: Option<&str> = option_env!("ACCESS_TOKEN");
+const _: () = assert!(
+    match BUILT_IN_TOKEN {
+        Some(token) => token.len() <= 32,
+        None => true,
+    },
+    "ACCESS_TOKEN is longer than 32 characters"
+);
+static ACCESS_TOKEN: Mutex<CriticalSectionRawMutex, RefCell<String<32>>> = Mutex::new(RefCell::new(String::new()));
+
+// Tries at each known network before giving up on it
+const JOIN_ATTEMPTS: u32 = 3;
+
+// The network that was joined, for its signal strength in Status
+static NETWORK: Mutex<CriticalSectionRawMutex, RefCell<String<32>>> = Mutex::new(RefCell::new(String::new()));
+
+// Longest idle timeout a client may set, one day
+const MAX_IDLE_CLEAR_MINUTES: u32 = 24 * 60;
+
+// Browsers that can draw at the same time, each gets its own socket and task
+const CLIENT_COUNT: usize = 3;
+
+// A change to pass on to connected clients, each connection has its own queue
+// of them. `from` is the connection it came in on, so it isn't echoed back
+#[derive(Clone)]
+pub enum DrawingEvent {
+    // None is the device itself, e.g. an idle clear
+    Draw { from: Option<usize>, author: u8, message: DrawMessage },
+    // A Stroke as it came in, one event rather than one per pixel so a busy
+    // client doesn't fill the other clients' queues
+    Stroke { from: Option<usize>, author: u8, pixels: StrokePixels },
+    // A client's frame replaced the whole canvas, the others get the new one
+    Canvas { from: usize },
+    // A client connected or left, or the WiFi came back. Everyone gets a Status
+    Clients,
+}
+
+// The compact pixels of one Stroke
+pub type StrokePixels = heapless::Vec<u8, { MAX_STROKE_PIXELS * DrawMessage::LEN }>;
+
+// Binary messages that failed their checksum since boot, reported on /status
+pub static CORRUPT_MESSAGES: AtomicU32 = AtomicU32::new(0);
+
+// Link health since boot, reported in Status and on /status
+static JOINS: AtomicU32 = AtomicU32::new(0);
+static SOCKET_ERRORS: AtomicU32 = AtomicU32::new(0);
+static DROPPED_EVENTS: AtomicU32 = AtomicU32::new(0);
+
+// How often the joined network's signal is read again while connected
+const SIGNAL_INTERVAL: Duration = Duration::from_secs(15);
+
+// What is known about the WebSocket client on a slot, reported in Status and on /status
+#[derive(Clone, Copy)]
+struct ClientInfo {
+    client_id: u8,
+    // The id the client registered with, the same across its reconnects
+    registered_id: Option<u32>,
+    role: Role,
+}
+
+static CLIENTS: Mutex<CriticalSectionRawMutex, RefCell<[Option<ClientInfo>; CLIENT_COUNT]>> =
+    Mutex::new(RefCell::new([None; CLIENT_COUNT]));
+
+// Holds a slot's client info for as long as the client is connected
+struct ConnectedClient {
+    slot: usize,
+}
+
+impl ConnectedClient {
+    // Clients count as drawers until they register, like those from before roles
+    fn new(slot: usize, client_id: u8) -> Self {
+        let info = ClientInfo { client_id, registered_id: None, role: Role::Drawer };
+        CLIENTS.lock(|clients| clients.borrow_mut()[slot] = Some(info));
+        DRAWING_EVENTS.immediate_publisher().publish_immediate(DrawingEvent::Clients);
+        ConnectedClient { slot }
+    }
+
+    fn register(&self, id: u32, role: Role) {
+        CLIENTS.lock(|clients| {
+            if let Some(info) = clients.borrow_mut()[self.slot].as_mut() {
+                info.registered_id = Some(id);
+                info.role = role;
+            }
+        });
+    }
+
+    fn role(&self) -> Role {
+        CLIENTS.lock(|clients| clients.borrow()[self.slot].map_or(Role::Drawer, |info| info.role))
+    }
+}
+
+impl Drop for ConnectedClient {
+    fn drop(&mut self) {
+        CLIENTS.lock(|clients| clients.borrow_mut()[self.slot] = None);
+        DRAWING_EVENTS.immediate_publisher().publish_immediate(DrawingEvent::Clients);
+    }
+}
+
+fn connected_clients() -> u8 {
+    CLIENTS.lock(|clients| clients.borrow().iter().flatten().count() as u8)
+}
+
+fn role_name(role: Role) -> &'static str {
+    match role {
+        Role::Drawer => "drawer",
+        Role::Spectator => "spectator",
+        Role::Admin => "admin",
+    }
+}
+
+// Client ids label who drew what in relayed messages
+static NEXT_CLIENT_ID: AtomicU8 = AtomicU8::new(1);
+
+pub fn next_client_id() -> u8 {
+    loop {
+        let id = NEXT_CLIENT_ID.fetch_add(1, Ordering::Relaxed);
+        if id != DEVICE_AUTHOR {
+            return id;
+        }
+    }
+}
+
+// Optional protocol features this firmware implements, offered in Hello
+const CAPABILITIES: Capabilities = Capabilities::BATCHING
+    .union(Capabilities::GRAYSCALE)
+    .union(Capabilities::COMPRESSION)
+    .union(Capabilities::CBOR);
+
+// How long to wait for a DHCP lease before taking the static address
+const DHCP_TIMEOUT: Duration = Duration::from_secs(15);
+
+// The address the device ended up with, for the OLED to show. None while the
+// WiFi link is down
+pub static DEVICE_ADDRESS: Signal<CriticalSectionRawMutex, Option<Ipv4Address>> = Signal::new();
+
+// Rejoining after the link drops waits longer after each failed try, up to a minute
+const REJOIN_BACKOFF_MIN: Duration = Duration::from_secs(2);
+const REJOIN_BACKOFF_MAX: Duration = Duration::from_secs(60);
+
+// Clients learn which of their messages arrived from an Ack after this many
+// messages, or this long after the first one nobody acked yet
+const ACK_EVERY: u16 = 16;
+const ACK_DELAY: Duration = Duration::from_millis(250);
+
+// Once a client was told it has less credit than this, it hears again after
+// ACK_DELAY when the display has caught up, instead of waiting for a heartbeat
+const LOW_CREDIT: u8 = 8;
+
+// The webapp sends a heartbeat Ping every couple of seconds. A client silent for
+// this long is gone, its socket is dropped so the slot can take a new one
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Each client gets a Status this often, for the webapp's Pico status panel
+const STATUS_INTERVAL: Duration = Duration::from_secs(5);
+
+// Room for a short burst while a client's socket is busy sending
+const DRAWING_EVENT_QUEUE: usize = 32;
+
+pub static DRAWING_EVENTS: PubSubChannel<CriticalSectionRawMutex, DrawingEvent, DRAWING_EVENT_QUEUE, CLIENT_COUNT, 0> =
+    PubSubChannel::new();
+
+type DrawingEventSubscriber = Subscriber<'static, CriticalSectionRawMutex, DrawingEvent, DRAWING_EVENT_QUEUE, CLIENT_COUNT, 0>;
 
 #[embassy_executor::task]
 pub async fn networking_task(
     mut wifi_stack: WifiStack,
-    mut pipe_writer: Writer<'static, CriticalSectionRawMutex, 64>,
+    mut flash: CredentialFlash,
+    drawing_pipe: &'static DrawingPipe,
 ) {
-    info!("Starting networking task...");
+    info!("Starting networking task as {}...", DEVICE_HOSTNAME);
+    
+    // Survey the airwaves first, this is what /status and the boot screen report
+    scan_networks(&mut wifi_stack.wifi_controller).await;
     
     // Connect to WiFi
-    connect_wifi(&mut wifi_stack).await;
+    let credentials = connect_wifi(&mut wifi_stack, &mut flash).await;
+    
+    // One listening socket per client slot, all on port 80, and one for UDP
+    let spawner = Spawner::for_current_executor().await;
+    for slot in 0..CLIENT_COUNT {
+        if spawner.spawn(connection_task(wifi_stack.stack, drawing_pipe, slot)).is_err() {
+            warn!("Failed to spawn connection task {}", slot);
+        }
+    }
+    // Datagrams have no way to carry the access token
+    if ACCESS_TOKEN.lock(|token| !token.borrow().is_empty()) {
+        info!("Access token set, not listening for UDP");
+    } else if spawner.spawn(udp_task(wifi_stack.stack, drawing_pipe)).is_err() {
+        warn!("Failed to spawn UDP task");
+    }
+
+    supervise_wifi(&mut wifi_stack, &credentials).await;
+}
+
+#[embassy_executor::task(pool_size = CLIENT_COUNT)]
+async fn connection_task(
+    stack: &'static Stack<'static>,
+    drawing_pipe: &'static DrawingPipe,
+    slot: usize,
+) {
+    // Subscribed for the life of the task so no slot can be taken by another
+    let Ok(mut events) = DRAWING_EVENTS.subscriber() else {
+        warn!("No drawing event subscriber left for client {}", slot);
+        return;
+    };
     
     // WebSocket server loop
     let mut rx_buffer = [0; 2048];
@@ -35,18 +258,23 @@ pub async fn networking_task(
 
     loop {
         // Create socket
-        let mut socket = TcpSocket::new(*wifi_stack.stack, &mut rx_buffer, &mut tx_buffer);
-        // No timeout - WebSocket connections should stay open
-        socket.set_timeout(None);
+        let mut socket = TcpSocket::new(*stack, &mut rx_buffer, &mut tx_buffer);
+        // Idle WebSocket connections stay open on heartbeats, keep-alives catch a
+        // peer that vanished before the handshake got that far
+        socket.set_timeout(Some(CLIENT_TIMEOUT));
+        socket.set_keep_alive(Some(CLIENT_TIMEOUT / 2));
 
-        info!("Waiting for connection on port 80");
+        info!("Client {} waiting for connection on port 80", slot);
         
         match socket.accept(80).await {
             Ok(_) => {
-                info!("Connection accepted");
+                info!("Connection accepted on client {}", slot);
+                
+                // Drawing from before this client connected is not news to it
+                while events.try_next_message().is_some() {}
                 
                 // Handle this WebSocket connection
-                handle_websocket_connection(&mut socket, &mut pipe_writer).await;
+                handle_websocket_connection(&mut socket, drawing_pipe, slot, &mut events).await;
                 
                 // Close socket cleanly
                 socket.close();
@@ -55,6 +283,7 @@ pub async fn networking_task(
                 Timer::after(Duration::from_millis(10)).await;
             },
             Err(_) => {
+                SOCKET_ERRORS.fetch_add(1, Ordering::Relaxed);
                 Timer::after(Duration::from_millis(100)).await;
             }
         }
@@ -63,7 +292,9 @@ pub async fn networking_task(
 
 async fn handle_websocket_connection(
     socket: &mut TcpSocket<'_>,
-    pipe_writer: &mut Writer<'static, CriticalSectionRawMutex, 64>,
+    drawing_pipe: &'static DrawingPipe,
+    slot: usize,
+    events: &mut DrawingEventSubscriber,
 ) {
     let mut read_buffer = [0u8; 1024];
     let mut read_cursor = 0;
@@ -82,6 +313,32 @@ async fn handle_websocket_connection(
                 
                 match request.parse(&read_buffer[..read_cursor]) {
                     Ok(httparse::Status::Complete(_)) => {
+                        let route = request.path.map(|path| path.split('?').next().unwrap_or(path));
+                        match (request.method, route) {
+                            (Some("GET"), Some("/status")) => {
+                                send_status(socket).await;
+                                return;
+                            }
+                            (Some("POST"), Some("/clear")) if !authorized(&request) => {
+                                send_response(socket, "401 Unauthorized", "text/plain", false, b"").await;
+                                return;
+                            }
+                            (Some("POST"), Some("/clear")) => {
+                                clear_canvas(drawing_pipe).await;
+                                send_response(socket, "200 OK", "application/json", false, br#"{"ok":true}"#).await;
+                                return;
+                            }
+                            (Some("GET"), Some("/frame")) => {
+                                send_frame_pbm(socket).await;
+                                return;
+                            }
+                            (_, Some("/status" | "/clear" | "/frame")) => {
+                                send_response(socket, "405 Method Not Allowed", "text/plain", false, b"").await;
+                                return;
+                            }
+                            _ => {}
+                        }
+                        
                         // Parse WebSocket headers
                         let header_iter = request.headers.iter().map(|h| (h.name, h.value));
                         
@@ -90,12 +347,30 @@ async fn handle_websocket_connection(
                             let mut websocket = ws::WebSocketServer::new_server();
                             
                             if let Ok(len) = websocket.server_accept(&ws_context.sec_websocket_key, None, &mut write_buffer) {
-                                let _ = socket.write(&write_buffer[..len]).await;
+                                let _ = write_all(socket, &write_buffer[..len]).await;
                                 let _ = socket.flush().await;
                                 
+                                // Accepted first so the client sees why in the close frame
+                                if !authorized(&request) {
+                                    warn!("Client {} gave no or the wrong access token", slot);
+                                    if let Ok(len) = websocket.close(
+                                        WebSocketCloseStatusCode::PolicyViolation,
+                                        Some("access token"),
+                                        &mut write_buffer,
+                                    ) {
+                                        let _ = write_all(socket, &write_buffer[..len]).await;
+                                        let _ = socket.flush().await;
+                                    }
+                                    return;
+                                }
+                                
                                 // Enter message loop
-                                websocket_message_loop(socket, &mut websocket, pipe_writer).await;
+                                websocket_message_loop(socket, &mut websocket, drawing_pipe, slot, events).await;
                             }
+                        } else if let Some(asset) = request.path.and_then(find_asset) {
+                            send_response(socket, "200 OK", asset.content_type, true, asset.body).await;
+                        } else {
+                            send_response(socket, "404 Not Found", "text/plain", false, b"").await;
                         }
                         return;
                     }
@@ -113,135 +388,833 @@ async fn handle_websocket_connection(
     }
 }
 
+// The access token as a `token` query parameter, which is all a browser can send
+// with a WebSocket, or as a Bearer token for everything else
+fn authorized(request: &httparse::Request) -> bool {
+    ACCESS_TOKEN.lock(|token| {
+        let token = token.borrow();
+        if token.is_empty() {
+            return true;
+        }
+        let query = request.path.and_then(|path| path.split_once('?')).map_or("", |(_, query)| query);
+        let in_query = query
+            .split('&')
+            .filter_map(|field| field.strip_prefix("token="))
+            .any(|value| url_decode::<32>(value.as_bytes()).is_some_and(|value| value == *token));
+        let in_header = request.headers.iter().any(|header| {
+            header.name.eq_ignore_ascii_case("authorization") && header.value.strip_prefix(b"Bearer ") == Some(token.as_bytes())
+        });
+        in_query || in_header
+    })
+}
+
 async fn websocket_message_loop(
     socket: &mut TcpSocket<'_>,
     websocket: &mut ws::WebSocketServer,
-    pipe_writer: &mut Writer<'static, CriticalSectionRawMutex, 64>,
+    drawing_pipe: &'static DrawingPipe,
+    slot: usize,
+    events: &mut DrawingEventSubscriber,
 ) {
-    let mut read_buffer = [0u8; 512];
-    let mut frame_buffer = [0u8; 256];
+    // Room for the largest message plus its WebSocket header
+    let mut read_buffer = [0u8; MAX_MESSAGE_LEN + 16];
+    // Bytes at the front of read_buffer from an earlier read, part of a frame
+    let mut pending = 0;
+    let mut frame_buffer = [0u8; MAX_MESSAGE_LEN];
+    // Where compressed messages are unpacked, they are at most as long as any other
+    let mut inflate_buffer = [0u8; MAX_MESSAGE_LEN];
     let mut write_buffer = [0u8; 256];
+    // Numbers what we send, and keeps track of what the client sent
+    let mut outgoing = Outgoing { seq: 0, cbor: false };
+    let mut received = SequenceTracker::new();
+    // The credit in the last Ack this client got
+    let mut advertised = u8::MAX;
+    // What the client said it supports, nothing until its Hello
+    let mut shared = Capabilities::NONE;
+    
+    // Tell the client its id, relayed drawing is prefixed with the id of its author,
+    // and what the display can show
+    let client_id = next_client_id();
+    info!("WebSocket connected on client {} as #{}", slot, client_id);
+    
+    let hello = Message::Hello {
+        client_id,
+        width: CANVAS_WIDTH as u8,
+        height: CANVAS_HEIGHT as u8,
+        capabilities: CAPABILITIES,
+    };
+    send_message(socket, websocket, &mut outgoing, &hello, &mut write_buffer).await;
     
-    info!("WebSocket connected");
+    let connected = ConnectedClient::new(slot, client_id);
+    let mut last_heard = Instant::now();
+    let mut status_at = Instant::now();
     
     loop {
-        // Read data from socket
-        match socket.read(&mut read_buffer).await {
+        let silent_at = last_heard + CLIENT_TIMEOUT;
+        let mut wake_at = silent_at.min(status_at);
+        if received.unacked() > 0 || advertised < LOW_CREDIT {
+            wake_at = wake_at.min(Instant::now() + ACK_DELAY);
+        }
+        
+        // Read data from socket, pass on drawing from other clients and the device,
+        // ack what the client sent, report status or give up on a silent client
+        let read_result = match select3(socket.read(&mut read_buffer[pending..]), events.next_message(), Timer::at(wake_at)).await {
+            Either3::First(read_result) => read_result,
+            Either3::Second(WaitResult::Message(DrawingEvent::Draw { from, author, message })) => {
+                if from != Some(slot) {
+                    let message = if shared.contains(Capabilities::GRAYSCALE) {
+                        message
+                    } else {
+                        message.monochrome()
+                    };
+                    let relayed = Message::Draw { author, message };
+                    send_message(socket, websocket, &mut outgoing, &relayed, &mut write_buffer).await;
+                }
+                continue;
+            }
+            Either3::Second(WaitResult::Message(DrawingEvent::Stroke { from, author, pixels })) => {
+                if from != Some(slot) {
+                    send_stroke(socket, websocket, &mut outgoing, shared, author, &pixels, &mut write_buffer).await;
+                }
+                continue;
+            }
+            Either3::Second(WaitResult::Message(DrawingEvent::Canvas { from })) => {
+                if from != slot {
+                    send_canvas_runs(socket, websocket, &mut outgoing).await;
+                }
+                continue;
+            }
+            Either3::Second(WaitResult::Message(DrawingEvent::Clients)) => {
+                send_message(socket, websocket, &mut outgoing, &Message::Status(current_status()), &mut write_buffer).await;
+                status_at = Instant::now() + STATUS_INTERVAL;
+                continue;
+            }
+            // Whatever was missed is in the canvas, so send all of it
+            Either3::Second(WaitResult::Lagged(missed)) => {
+                warn!("Client {} missed {} drawing events, sending the whole canvas", slot, missed);
+                DROPPED_EVENTS.fetch_add(missed as u32, Ordering::Relaxed);
+                while events.try_next_message().is_some() {}
+                send_canvas_runs(socket, websocket, &mut outgoing).await;
+                continue;
+            }
+            Either3::Third(()) => {
+                let now = Instant::now();
+                if now >= silent_at {
+                    warn!("Client {} missed its heartbeats, dropping it", slot);
+                    return;
+                }
+                if now >= status_at {
+                    send_message(socket, websocket, &mut outgoing, &Message::Status(current_status()), &mut write_buffer).await;
+                    status_at = now + STATUS_INTERVAL;
+                }
+                let credit = drawing_credit(drawing_pipe);
+                if received.unacked() > 0 || (advertised < LOW_CREDIT && credit > advertised) {
+                    if let Some(ack) = received.ack(credit) {
+                        send_message(socket, websocket, &mut outgoing, &ack, &mut write_buffer).await;
+                        advertised = credit;
+                    }
+                }
+                continue;
+            }
+        };
+        
+        match read_result {
             Ok(0) => {
                 info!("Connection closed");
                 return;
             }
             Ok(bytes_read) => {
-                // Process WebSocket frame
-                match websocket.read(&read_buffer[..bytes_read], &mut frame_buffer) {
-                    Ok(ws_result) => {
-                        match ws_result.message_type {
-                            WebSocketReceiveMessageType::Binary => {
-                                let payload = &frame_buffer[..ws_result.len_to];
-                                
-                                // Expect 3-byte messages: [x, y, state]
-                                if payload.len() == 3 {
-                                    let x = payload[0];
-                                    let y = payload[1];
-                                    let state = payload[2];
-                                    
-                                    // Check for clear command (255, 255, 2)
-                                    if x == 255 && y == 255 && state == 2 {
-                                        info!("Clear");
-                                    } else {
-                                        info!("Pixel: x={}, y={}, s={}", x, y, state);
+                last_heard = Instant::now();
+                
+                let read_end = pending + bytes_read;
+                let mut read_cursor = 0;
+                
+                // One read can hold several WebSocket frames, or the start of one
+                while read_cursor < read_end {
+                    let ws_result = match websocket.read(&read_buffer[read_cursor..read_end], &mut frame_buffer) {
+                        Ok(ws_result) => ws_result,
+                        Err(ws::Error::ReadFrameIncomplete) => break,
+                        Err(_) => return,
+                    };
+                    read_cursor += ws_result.len_from;
+                    
+                    match ws_result.message_type {
+                        WebSocketReceiveMessageType::Binary => {
+                            let payload = &frame_buffer[..ws_result.len_to];
+                            
+                            let decoded = if is_cbor(payload) {
+                                decode_cbor(payload)
+                            } else {
+                                decompress_packet(payload, &mut inflate_buffer).and_then(Packet::decode)
+                            };
+                            if let Ok(packet) = decoded {
+                                // A frame replaces the whole canvas, so it makes up for anything missed
+                                received.receive(packet.seq, matches!(packet.message, Message::Frame { .. }));
+                            }
+                            
+                            match decoded.map(|packet| packet.message) {
+                                Ok(Message::Draw { .. } | Message::Stroke { .. } | Message::Frame { .. })
+                                    if !connected.role().can_draw() =>
+                                {
+                                    warn!("Ignoring drawing from spectator client {}", slot);
+                                }
+                                Ok(Message::Register { id, role }) => {
+                                    info!("Client {} registered as {=u32:x}, {}", slot, id, role_name(role));
+                                    connected.register(id, role);
+                                }
+                                // The author a client claims is ignored, it is always this connection
+                                Ok(Message::Draw { message, .. }) => {
+                                    match message {
+                                        DrawMessage::Clear => info!("Clear"),
+                                        DrawMessage::Pixel { x, y, state } => {
+                                            info!("Pixel: x={}, y={}, state={}", x, y, state.to_byte())
+                                        }
+                                        DrawMessage::ClearRect { x, y, width, height } => {
+                                            info!("Clear rect: x={}, y={}, {}x{}", x, y, width, height)
+                                        }
                                     }
                                     
-                                    // Write to pipe for display task
-                                    let _ = pipe_writer.write(payload).await;
+                                    // Write to pipe for display task, and share with the other clients
+                                    queue_drawing(drawing_pipe, client_id, message).await;
+                                    DRAWING_EVENTS.immediate_publisher().publish_immediate(DrawingEvent::Draw {
+                                        from: Some(slot),
+                                        author: client_id,
+                                        message,
+                                    });
+                                }
+                                // Unpacked into single pixels for the display, the other
+                                // clients get it whole
+                                Ok(Message::Stroke { pixels, .. }) => {
+                                    info!("Stroke: {} pixels", pixels.len() / DrawMessage::LEN);
+                                    for message in stroke_pixels(pixels) {
+                                        queue_drawing(drawing_pipe, client_id, message).await;
+                                    }
+                                    publish_stroke(Some(slot), client_id, pixels);
+                                }
+                                Ok(Message::Prediction { class, confidence }) => {
+                                    info!("Prediction: {} ({}/255)", class, confidence);
+                                    PREDICTION.signal(Prediction { class, confidence });
+                                }
+                                Ok(Message::Hello { width, height, capabilities, .. }) => {
+                                    shared = CAPABILITIES.shared(capabilities);
+                                    outgoing.cbor = shared.contains(Capabilities::CBOR);
+                                    info!(
+                                        "Client {} draws on {}x{}, shared capabilities {=u8:#x}",
+                                        slot, width, height, shared.0
+                                    );
+                                }
+                                Ok(Message::Frame { width, height, data }) => {
+                                    info!("Frame: {}x{}", width, height);
+                                    match unpack_frame(width as usize, height as usize, data) {
+                                        Ok(frame) => CANVAS_SYNC.signal((slot, frame)),
+                                        Err(_) => warn!("Ignoring malformed frame from client {}", slot),
+                                    }
+                                }
+                                Ok(Message::GetFrame) => {
+                                    info!("Client {} asked for the canvas", slot);
+                                    send_canvas_runs(socket, websocket, &mut outgoing).await;
+                                }
+                                // Reflected before anything else can queue up, with the time spent
+                                // since the read so the webapp can tell it apart from the network's
+                                Ok(Message::Echo { sent_ms, .. }) => {
+                                    let pico_us = last_heard.elapsed().as_micros().min(u16::MAX as u64) as u16;
+                                    let echo = Message::Echo { sent_ms, pico_us };
+                                    send_message(socket, websocket, &mut outgoing, &echo, &mut write_buffer).await;
+                                }
+                                // The webapp's heartbeat, answering it tells the webapp we're still here
+                                Ok(Message::Ping(token)) => {
+                                    send_message(socket, websocket, &mut outgoing, &Message::Ping(token), &mut write_buffer).await;
+                                }
+                                Ok(other) => {
+                                    info!("Ignoring {} message from client {}", other.message_type() as u8, slot);
+                                }
+                                // Dropped, the client's next Ack check sees the gap and resyncs
+                                Err(DecodeError::Checksum) => {
+                                    let count = CORRUPT_MESSAGES.fetch_add(1, Ordering::Relaxed) + 1;
+                                    warn!("Dropping corrupt message from client {}, {} so far", slot, count);
+                                }
+                                // Refuse rather than misread it, the client sees why in the close frame
+                                Err(DecodeError::Version(version)) => {
+                                    warn!("Client {} speaks protocol version {}, expected {}", slot, version, VERSION);
+                                    let mut reason: String<64> = String::new();
+                                    let _ = write!(reason, "protocol version {} expected, got {}", VERSION, version);
+                                    if let Ok(len) = websocket.close(
+                                        WebSocketCloseStatusCode::ProtocolError,
+                                        Some(&reason),
+                                        &mut write_buffer,
+                                    ) {
+                                        let _ = write_all(socket, &write_buffer[..len]).await;
+                                        let _ = socket.flush().await;
+                                    }
+                                    return;
                                 }
+                                Err(_) => warn!("Ignoring malformed message of {} bytes", payload.len()),
                             }
-                            WebSocketReceiveMessageType::Text => {
-                                if let Ok(text) = from_utf8(&frame_buffer[..ws_result.len_to]) {
-                                    info!("Text: {}", text);
+                            
+                            // A client running low on credit hears about it straight away
+                            let credit = drawing_credit(drawing_pipe);
+                            if received.unacked() >= ACK_EVERY || (received.unacked() > 0 && credit < LOW_CREDIT) {
+                                if let Some(ack) = received.ack(credit) {
+                                    send_message(socket, websocket, &mut outgoing, &ack, &mut write_buffer).await;
+                                    advertised = credit;
                                 }
                             }
-                            WebSocketReceiveMessageType::CloseMustReply => {
-                                info!("Close frame");
+                        }
+                        WebSocketReceiveMessageType::Text => {
+                            if let Ok(text) = from_utf8(&frame_buffer[..ws_result.len_to]) {
+                                info!("Text: {}", text);
                                 
-                                // Send close reply
-                                if let Ok(len) = websocket.write(
-                                    WebSocketSendMessageType::CloseReply,
-                                    true,
-                                    &frame_buffer[..ws_result.len_to],
-                                    &mut write_buffer,
-                                ) {
-                                    let _ = socket.write(&write_buffer[..len]).await;
-                                    let _ = socket.flush().await;
+                                if json_command::is_json(text) {
+                                    let mut reply: String<{ json_command::REPLY_LEN }> = String::new();
+                                    match json_command::parse(text) {
+                                        Ok(JsonCommand::Draw(_)) if !connected.role().can_draw() => {
+                                            json_command::write_error("spectators can't draw", &mut reply);
+                                        }
+                                        Ok(JsonCommand::Draw(message)) => {
+                                            queue_drawing(drawing_pipe, client_id, message).await;
+                                            DRAWING_EVENTS.immediate_publisher().publish_immediate(DrawingEvent::Draw {
+                                                from: Some(slot),
+                                                author: client_id,
+                                                message,
+                                            });
+                                            json_command::write_ok(&mut reply);
+                                        }
+                                        Ok(JsonCommand::Status) => json_command::write_status(&current_status(), &mut reply),
+                                        Ok(JsonCommand::Idle(minutes)) => {
+                                            if let Some(minutes) = minutes {
+                                                set_idle_clear(minutes);
+                                            }
+                                            json_command::write_idle(IDLE_CLEAR_MINUTES.load(Ordering::Relaxed), &mut reply);
+                                        }
+                                        Err(error) => {
+                                            warn!("Bad JSON command: {}", error);
+                                            json_command::write_error(error, &mut reply);
+                                        }
+                                    }
+                                    send_text(socket, websocket, &reply, &mut write_buffer).await;
+                                    continue;
                                 }
                                 
-                                return;
-                            }
-                            WebSocketReceiveMessageType::Ping => {
-                                info!("Ping");
-                                
-                                // Respond with pong
-                                if let Ok(len) = websocket.write(
-                                    WebSocketSendMessageType::Pong,
-                                    true,
-                                    &frame_buffer[..ws_result.len_to],
-                                    &mut write_buffer,
-                                ) {
-                                    let _ = socket.write(&write_buffer[..len]).await;
-                                    let _ = socket.flush().await;
+                                let mut reply: String<64> = String::new();
+                                if handle_text_command(text, &mut reply) {
+                                    send_text(socket, websocket, &reply, &mut write_buffer).await;
                                 }
                             }
-                            _ => {
-                                info!("Other message type");
+                        }
+                        WebSocketReceiveMessageType::CloseMustReply => {
+                            info!("Close frame");
+                            
+                            // Send close reply
+                            if let Ok(len) = websocket.write(
+                                WebSocketSendMessageType::CloseReply,
+                                true,
+                                &frame_buffer[..ws_result.len_to],
+                                &mut write_buffer,
+                            ) {
+                                let _ = write_all(socket, &write_buffer[..len]).await;
+                                let _ = socket.flush().await;
                             }
+                            
+                            return;
+                        }
+                        WebSocketReceiveMessageType::Ping => {
+                            info!("Ping");
+                            
+                            // Respond with pong
+                            if let Ok(len) = websocket.write(
+                                WebSocketSendMessageType::Pong,
+                                true,
+                                &frame_buffer[..ws_result.len_to],
+                                &mut write_buffer,
+                            ) {
+                                let _ = write_all(socket, &write_buffer[..len]).await;
+                                let _ = socket.flush().await;
+                            }
+                        }
+                        _ => {
+                            info!("Other message type");
                         }
-                    }
-                    Err(ws::Error::ReadFrameIncomplete) => {
-                        continue;
-                    }
-                    Err(_) => {
-                        return;
                     }
                 }
+                
+                // The start of a frame still arriving waits at the front for the rest
+                read_buffer.copy_within(read_cursor..read_end, 0);
+                pending = read_end - read_cursor;
+                if pending == read_buffer.len() {
+                    warn!("Client {} sent a frame longer than any message, dropping it", slot);
+                    return;
+                }
             }
             Err(_) => {
+                SOCKET_ERRORS.fetch_add(1, Ordering::Relaxed);
                 return;
             }
         }
     }
 }
 
-async fn connect_wifi(wifi_stack: &mut WifiStack) {
-    info!("Connecting to WiFi: {}", WIFI_NETWORK);
-    
-    loop {
-        match wifi_stack.wifi_controller
-            .join(WIFI_NETWORK, JoinOptions::new(WIFI_PASSWORD.as_bytes()))
-            .await
-        {
-            Ok(_) => {
-                info!("WiFi connected!");
-                break;
-            }
-            Err(err) => {
-                warn!("WiFi join failed: {}, retrying...", err.status);
-                Timer::after(Duration::from_secs(5)).await;
+fn current_status() -> Status {
+    let pixels_on: u32 = FRAME.lock(|frame| frame.borrow().iter().map(|byte| byte.count_ones()).sum());
+    // The channel from the boot scan, the signal read again every SIGNAL_INTERVAL
+    let network = NETWORK.lock(|network| find_network(&network.borrow()));
+    Status {
+        pixels_on: pixels_on as u16,
+        uptime_secs: Instant::now().as_secs() as u32,
+        rssi: network.as_ref().map_or(0, |network| network.rssi.clamp(i8::MIN as i16, -1) as i8),
+        clients: connected_clients(),
+        corrupt_messages: CORRUPT_MESSAGES.load(Ordering::Relaxed),
+        channel: network.as_ref().map_or(0, |network| network.channel),
+        joins: JOINS.load(Ordering::Relaxed).min(u16::MAX as u32) as u16,
+        socket_errors: SOCKET_ERRORS.load(Ordering::Relaxed),
+        dropped_events: DROPPED_EVENTS.load(Ordering::Relaxed),
+    }
+}
+
+// Text commands are small "<command> <args>" strings. Returns true if `reply` should be sent back
+fn handle_text_command(command: &str, reply: &mut String<64>) -> bool {
+    match command.split_once(' ').unwrap_or((command, "")) {
+        ("grid", size) => {
+            // The canvas can't grow, so answer with the part of the client's grid the OLED shows
+            let Some((width, height)) = size
+                .split_once('x')
+                .and_then(|(w, h)| Some((w.parse::<usize>().ok()?, h.parse::<usize>().ok()?)))
+            else {
+                warn!("Malformed grid size: {}", size);
+                return false;
+            };
+            
+            let width = width.min(CANVAS_WIDTH);
+            let height = height.min(CANVAS_HEIGHT);
+            info!("Client grid {}, showing {}x{}", size, width, height);
+            write!(reply, "grid {}x{}", width, height).is_ok()
+        }
+        // The client times the round trip, the payload (its send time) comes straight back
+        ("echo", payload) => write!(reply, "echo {}", payload).is_ok(),
+        ("idle", minutes) => {
+            // No argument just asks for the current setting
+            if !minutes.is_empty() {
+                let Ok(minutes) = minutes.parse::<u32>() else {
+                    warn!("Malformed idle timeout: {}", minutes);
+                    return false;
+                };
+                set_idle_clear(minutes);
             }
+            write!(reply, "idle {}", IDLE_CLEAR_MINUTES.load(Ordering::Relaxed)).is_ok()
+        }
+        _ => {
+            warn!("Unknown text command: {}", command);
+            false
+        }
+    }
+}
+
+fn set_idle_clear(minutes: u32) {
+    let minutes = minutes.min(MAX_IDLE_CLEAR_MINUTES);
+    info!("Idle clear set to {} minutes", minutes);
+    IDLE_CLEAR_MINUTES.store(minutes, Ordering::Relaxed);
+}
+
+async fn send_text(
+    socket: &mut TcpSocket<'_>,
+    websocket: &mut ws::WebSocketServer,
+    text: &str,
+    write_buffer: &mut [u8],
+) {
+    send_frame(socket, websocket, WebSocketSendMessageType::Text, text.as_bytes(), write_buffer).await;
+}
+
+// The canvas as a run-length encoded Frame, the answer to GetFrame and how a
+// client catches up. One too busy to fit a message goes pixel by pixel
+async fn send_canvas_runs(socket: &mut TcpSocket<'_>, websocket: &mut ws::WebSocketServer, outgoing: &mut Outgoing) {
+    let frame = FRAME.lock(|shared| *shared.borrow());
+    let mut runs = [0u8; MAX_MESSAGE_LEN - HEADER_LEN - CHECKSUM_LEN - 2];
+    let mut len = 0;
+    let mut fits = true;
+    let pixels = (0..CANVAS_WIDTH * CANVAS_HEIGHT).map(|i| frame[i / 8] & (0x80 >> (i % 8)) != 0);
+    encode_runs(pixels, |byte| match runs.get_mut(len) {
+        Some(run) => {
+            *run = byte;
+            len += 1;
+        }
+        None => fits = false,
+    });
+    if !fits {
+        send_canvas_pixels(socket, websocket, outgoing, &frame).await;
+        return;
+    }
+    
+    let message = Message::Frame {
+        width: CANVAS_WIDTH as u8,
+        height: CANVAS_HEIGHT as u8,
+        data: &runs[..len],
+    };
+    
+    // CBOR takes a few bytes more than binary, so runs that fit a binary frame can
+    // still make a CBOR one too long for the client. Encoded before it's numbered,
+    // so one that doesn't fit leaves no gap in the sequence
+    let mut bytes = [0u8; MAX_MESSAGE_LEN];
+    let mut write_buffer = [0u8; MAX_MESSAGE_LEN + 16];
+    match outgoing.encode(&Packet { seq: outgoing.seq, message }, &mut bytes) {
+        Ok(len) => {
+            outgoing.packet(message);
+            info!("Sending canvas, {} bytes", len);
+            send_frame(socket, websocket, WebSocketSendMessageType::Binary, &bytes[..len], &mut write_buffer).await;
+        }
+        Err(_) => send_canvas_pixels(socket, websocket, outgoing, &frame).await,
+    }
+}
+
+// The canvas the way the webapp sends one too busy for a frame: a clear, then
+// every inked pixel on its own
+async fn send_canvas_pixels(
+    socket: &mut TcpSocket<'_>,
+    websocket: &mut ws::WebSocketServer,
+    outgoing: &mut Outgoing,
+    frame: &[u8; FRAME_BYTES],
+) {
+    warn!("Canvas too busy for a frame, sending its pixels");
+    let mut write_buffer = [0u8; 64];
+    let clear = Message::Draw { author: DEVICE_AUTHOR, message: DrawMessage::Clear };
+    send_message(socket, websocket, outgoing, &clear, &mut write_buffer).await;
+    for i in (0..CANVAS_WIDTH * CANVAS_HEIGHT).filter(|i| frame[i / 8] & (0x80 >> (i % 8)) != 0) {
+        let (x, y) = ((i % CANVAS_WIDTH) as u8, (i / CANVAS_WIDTH) as u8);
+        let pixel = Message::Draw { author: DEVICE_AUTHOR, message: DrawMessage::Pixel { x, y, state: PixelState::Set } };
+        send_message(socket, websocket, outgoing, &pixel, &mut write_buffer).await;
+    }
+}
+
+// Lay a client's frame over the canvas from the top-left corner, cropping what
+// doesn't fit. Anything outside the frame is blank
+fn unpack_frame(width: usize, height: usize, runs: &[u8]) -> Result<[u8; FRAME_BYTES], DecodeError> {
+    let mut frame = [0u8; FRAME_BYTES];
+    decode_runs(runs, width * height, |i| {
+        let (x, y) = (i % width, i / width);
+        if x < CANVAS_WIDTH && y < CANVAS_HEIGHT {
+            let index = y * CANVAS_WIDTH + x;
+            frame[index / 8] |= 0x80 >> (index % 8);
+        }
+    })?;
+    Ok(frame)
+}
+
+// How packets to one client are numbered and encoded
+struct Outgoing {
+    seq: u16,
+    // Once the client's Hello offered CBOR, everything after ours goes as CBOR
+    cbor: bool,
+}
+
+impl Outgoing {
+    // The next packet, numbered after the last one
+    fn packet<'a>(&mut self, message: Message<'a>) -> Packet<'a> {
+        let packet = Packet { seq: self.seq, message };
+        self.seq = self.seq.wrapping_add(1);
+        packet
+    }
+    
+    fn encode(&self, packet: &Packet<'_>, out: &mut [u8]) -> Result<usize, EncodeError> {
+        if self.cbor {
+            encode_cbor(packet, out)
+        } else {
+            packet.encode(out)
+        }
+    }
+}
+
+// Sends `message` as the next packet to the client
+async fn send_message(
+    socket: &mut TcpSocket<'_>,
+    websocket: &mut ws::WebSocketServer,
+    outgoing: &mut Outgoing,
+    message: &Message<'_>,
+    write_buffer: &mut [u8],
+) {
+    let packet = outgoing.packet(*message);
+    
+    // Everything but the canvas, which send_canvas_runs sends itself. A relayed
+    // Stroke is the longest
+    let mut bytes = [0u8; MAX_CONTROL_LEN + MAX_STROKE_PIXELS * DrawMessage::LEN];
+    match outgoing.encode(&packet, &mut bytes) {
+        Ok(len) => send_frame(socket, websocket, WebSocketSendMessageType::Binary, &bytes[..len], write_buffer).await,
+        Err(_) => error!("Message too long to send: {} bytes", packet.encoded_len()),
+    }
+}
+
+/// Share a Stroke from `from`, checked when it was decoded, with the other clients
+pub fn publish_stroke(from: Option<usize>, author: u8, pixels: &[u8]) {
+    if let Ok(pixels) = StrokePixels::from_slice(pixels) {
+        DRAWING_EVENTS.immediate_publisher().publish_immediate(DrawingEvent::Stroke { from, author, pixels });
+    }
+}
+
+// A relayed Stroke goes as one to clients that batch and pixel by pixel to the
+// rest, without gray for clients that only know on and off
+async fn send_stroke(
+    socket: &mut TcpSocket<'_>,
+    websocket: &mut ws::WebSocketServer,
+    outgoing: &mut Outgoing,
+    shared: Capabilities,
+    author: u8,
+    pixels: &[u8],
+    write_buffer: &mut [u8],
+) {
+    let grayscale = shared.contains(Capabilities::GRAYSCALE);
+    let messages = stroke_pixels(pixels).map(|message| if grayscale { message } else { message.monochrome() });
+    if !shared.contains(Capabilities::BATCHING) {
+        for message in messages {
+            send_message(socket, websocket, outgoing, &Message::Draw { author, message }, write_buffer).await;
         }
+        return;
     }
 
+    let mut packed = StrokePixels::new();
+    for message in messages {
+        let _ = packed.extend_from_slice(&message.encode());
+    }
+    send_message(socket, websocket, outgoing, &Message::Stroke { author, pixels: &packed }, write_buffer).await;
+}
+
+async fn send_frame(
+    socket: &mut TcpSocket<'_>,
+    websocket: &mut ws::WebSocketServer,
+    message_type: WebSocketSendMessageType,
+    payload: &[u8],
+    write_buffer: &mut [u8],
+) {
+    if let Ok(len) = websocket.write(
+        message_type,
+        true,
+        payload,
+        write_buffer,
+    ) {
+        let _ = write_all(socket, &write_buffer[..len]).await;
+        let _ = socket.flush().await;
+    }
+}
+
+// Plain HTTP, for anything that wants to check on or poke the device without a
+// WebSocket. Every response closes the connection
+async fn send_response(
+    socket: &mut TcpSocket<'_>,
+    status: &str,
+    content_type: &str,
+    gzip: bool,
+    body: &[u8],
+) {
+    let mut header: String<256> = String::new();
+    let _ = write!(
+        header,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\n{}Content-Length: {}\r\nAccess-Control-Allow-Origin: *\r\nConnection: close\r\n\r\n",
+        status,
+        content_type,
+        if gzip { "Content-Encoding: gzip\r\n" } else { "" },
+        body.len()
+    );
+    // The body can be much more than the socket's buffer holds
+    if write_all(socket, header.as_bytes()).await.is_err() || write_all(socket, body).await.is_err() {
+        warn!("Client left before the response was sent");
+        return;
+    }
+    let _ = socket.flush().await;
+}
+
+/// Write all of `bytes`, waiting for the socket's buffer to drain as often as it
+/// takes. A write only takes what fits, and half a WebSocket frame leaves the
+/// client reading garbage from then on
+pub async fn write_all(socket: &mut TcpSocket<'_>, mut bytes: &[u8]) -> Result<(), tcp::Error> {
+    while !bytes.is_empty() {
+        match socket.write(bytes).await? {
+            0 => return Err(tcp::Error::ConnectionReset),
+            written => bytes = &bytes[written..],
+        }
+    }
+    Ok(())
+}
+
+fn write_clients<W: core::fmt::Write>(out: &mut W) -> core::fmt::Result {
+    let clients = CLIENTS.lock(|clients| *clients.borrow());
+    out.write_char('[')?;
+    for (i, info) in clients.iter().flatten().enumerate() {
+        if i > 0 {
+            out.write_char(',')?;
+        }
+        write!(out, r#"{{"id":{},"role":"{}","registered_id":"#, info.client_id, role_name(info.role))?;
+        match info.registered_id {
+            Some(id) => write!(out, r#""{:08x}"}}"#, id)?,
+            None => out.write_str("null}")?,
+        }
+    }
+    out.write_char(']')
+}
+
+fn write_device<W: core::fmt::Write>(out: &mut W, address: Option<IpAddress>) -> core::fmt::Result {
+    let status = current_status();
+    out.write_str(r#"{"hostname":"#)?;
+    json_command::write_string(out, DEVICE_HOSTNAME)?;
+    match address {
+        Some(address) => write!(out, r#","ip":"{}""#, address)?,
+        None => out.write_str(r#","ip":null"#)?,
+    }
+    write!(
+        out,
+        r#","uptime_secs":{},"rssi":{},"channel":{},"joins":{},"pixels_on":{},"corrupt_messages":{},"#,
+        status.uptime_secs, status.rssi, status.channel, status.joins, status.pixels_on, status.corrupt_messages,
+    )?;
+    write!(
+        out,
+        r#""socket_errors":{},"dropped_events":{},"clients":"#,
+        status.socket_errors, status.dropped_events,
+    )?;
+    write_clients(out)?;
+    out.write_str(r#","networks":"#)?;
+    write_status(out)?;
+    out.write_char('}')
+}
+
+// The longest /status body: every number at its longest, and every string at six
+// bytes a character, what escaping a control character takes
+const ESCAPED_CHAR_LEN: usize = 6;
+const DEVICE_JSON_LEN: usize = r#"{"hostname":"","ip":"255.255.255.255","uptime_secs":4294967295,"rssi":-128,"channel":255,"joins":65535,"pixels_on":65535,"corrupt_messages":4294967295,"socket_errors":4294967295,"dropped_events":4294967295,"clients":[],"networks":[]}"#.len()
+    + 32 * ESCAPED_CHAR_LEN;
+const CLIENT_JSON_LEN: usize = r#"{"id":255,"role":"spectator","registered_id":"ffffffff"},"#.len();
+const NETWORK_JSON_LEN: usize = r#"{"ssid":"","channel":255,"rssi":-32768},"#.len() + 32 * ESCAPED_CHAR_LEN;
+const STATUS_JSON_LEN: usize = DEVICE_JSON_LEN + CLIENT_COUNT * CLIENT_JSON_LEN + MAX_SCAN_RESULTS * NETWORK_JSON_LEN;
+
+async fn send_status(socket: &mut TcpSocket<'_>) {
+    let mut body: String<STATUS_JSON_LEN> = String::new();
+    // The address the request came in on is the device's
+    let address = socket.local_endpoint().map(|endpoint| endpoint.addr);
+    if write_device(&mut body, address).is_err() {
+        // Half a JSON document is no use to anyone, say so instead
+        error!("Status longer than {} bytes", STATUS_JSON_LEN);
+        send_response(socket, "500 Internal Server Error", "text/plain", false, b"status too long").await;
+        return;
+    }
+    send_response(socket, "200 OK", "application/json", false, body.as_bytes()).await;
+}
+
+// The canvas as a binary PBM, lit pixels black on white
+async fn send_frame_pbm(socket: &mut TcpSocket<'_>) {
+    let mut image: heapless::Vec<u8, { FRAME_BYTES + 16 }> = heapless::Vec::new();
+    let mut header: String<16> = String::new();
+    let _ = write!(header, "P4\n{} {}\n", CANVAS_WIDTH, CANVAS_HEIGHT);
+    let _ = image.extend_from_slice(header.as_bytes());
+    // Rows of a P4 are packed like FRAME, most significant bit first
+    FRAME.lock(|frame| {
+        let _ = image.extend_from_slice(&*frame.borrow());
+    });
+    send_response(socket, "200 OK", "image/x-portable-bitmap", false, &image).await;
+}
+
+async fn clear_canvas(drawing_pipe: &'static DrawingPipe) {
+    let message = DrawMessage::Clear;
+    queue_drawing(drawing_pipe, DEVICE_AUTHOR, message).await;
+    DRAWING_EVENTS.immediate_publisher().publish_immediate(DrawingEvent::Draw {
+        from: None,
+        author: DEVICE_AUTHOR,
+        message,
+    });
+}
+
+// The network set up through the setup access point first, then the built in
+// one. If neither works the device becomes the setup access point, and restarts
+// once it has been given a network
+async fn connect_wifi(wifi_stack: &mut WifiStack, flash: &mut CredentialFlash) -> Credentials {
+    let stored = load_credentials(flash);
+    let built_in = WIFI_NETWORK.and_then(|network| Credentials::new(network, WIFI_PASSWORD));
+
+    // A token from the setup access point wins over the built in one
+    let token = stored.as_ref().map(|stored| stored.token.as_str()).filter(|token| !token.is_empty());
+    if let Some(token) = token.or(BUILT_IN_TOKEN) {
+        ACCESS_TOKEN.lock(|access| *access.borrow_mut() = String::try_from(token).unwrap_or_default());
+    }
+
+    let mut joined = None;
+    for credentials in [stored, built_in].into_iter().flatten() {
+        if join_network(wifi_stack, &credentials, JOIN_ATTEMPTS).await {
+            joined = Some(credentials);
+            break;
+        }
+    }
+    let Some(credentials) = joined else {
+        provision(wifi_stack, flash).await;
+    };
+
+    acquire_address(wifi_stack).await;
+    credentials
+}
+
+// Wait for the link and an address, from DHCP or the static one if nobody answers
+async fn acquire_address(wifi_stack: &mut WifiStack) {
     info!("Waiting for link up...");
     wifi_stack.stack.wait_link_up().await;
     
     info!("Waiting for DHCP...");
-    wifi_stack.stack.wait_config_up().await;
+    if with_timeout(DHCP_TIMEOUT, wifi_stack.stack.wait_config_up()).await.is_err() {
+        let config = static_config();
+        warn!("No DHCP lease after {}s, falling back to {}", DHCP_TIMEOUT.as_secs(), config.address);
+        wifi_stack.stack.set_config_v4(ConfigV4::Static(config));
+        wifi_stack.stack.wait_config_up().await;
+    }
     
     if let Some(config) = wifi_stack.stack.config_v4() {
         info!("Network configured!");
         info!("IP: {}", config.address.address());
         info!("Gateway: {:?}", config.gateway);
+        DEVICE_ADDRESS.signal(Some(config.address.address()));
     }
 
     // Turn on LED if connected
     wifi_stack.wifi_controller.gpio_set(0, true).await;
+}
+
+// Rejoin whenever the link drops, e.g. when the access point restarts, and keep
+// an eye on the signal in the meantime
+async fn supervise_wifi(wifi_stack: &mut WifiStack, credentials: &Credentials) -> ! {
+    loop {
+        if let Either::Second(()) = select(wifi_stack.stack.wait_link_down(), Timer::after(SIGNAL_INTERVAL)).await {
+            measure_network(&mut wifi_stack.wifi_controller, &credentials.ssid).await;
+            continue;
+        }
+        warn!("WiFi link lost, rejoining {}", credentials.ssid.as_str());
+        DEVICE_ADDRESS.signal(None);
+        wifi_stack.wifi_controller.gpio_set(0, false).await;
+
+        let mut backoff = REJOIN_BACKOFF_MIN;
+        while !join_network(wifi_stack, credentials, 1).await {
+            Timer::after(backoff).await;
+            backoff = (backoff * 2).min(REJOIN_BACKOFF_MAX);
+        }
+
+        // Back to DHCP, the old lease or the static fallback may not suit anymore
+        wifi_stack.stack.set_config_v4(ConfigV4::Dhcp(dhcp_config()));
+        acquire_address(wifi_stack).await;
+
+        // Connections that lived through it get a fresh Status, the others reconnect
+        DRAWING_EVENTS.immediate_publisher().publish_immediate(DrawingEvent::Clients);
+    }
+}
+
+async fn join_network(wifi_stack: &mut WifiStack, credentials: &Credentials, attempts: u32) -> bool {
+    info!("Connecting to WiFi: {}", credentials.ssid.as_str());
+    
+    for attempt in 1..=attempts {
+        match wifi_stack.wifi_controller
+            .join(&credentials.ssid, JoinOptions::new(credentials.password.as_bytes()))
+            .await
+        {
+            Ok(_) => {
+                info!("WiFi connected!");
+                NETWORK.lock(|network| *network.borrow_mut() = credentials.ssid.clone());
+                JOINS.fetch_add(1, Ordering::Relaxed);
+                return true;
+            }
+            Err(err) => {
+                warn!("WiFi join failed: {}, attempt {} of {}", err.status, attempt, attempts);
+                if attempt < attempts {
+                    Timer::after(Duration::from_secs(5)).await;
+                }
+            }
+        }
+    }
+    false
 }
\ No newline at end of file
@@ -0,0 +1,473 @@
+// file: provisioning.rs
+// desc: WiFi setup through an access point: a phone joins it, gets sent to a form
+// for the network name and password, which are kept in flash for the next boot
+
+use defmt::{info, warn};
+use core::fmt::Write as _;
+
+use embassy_futures::select::{select, select3, Either};
+use embassy_net::tcp::TcpSocket;
+use embassy_net::udp::{PacketMetadata, UdpSocket};
+use embassy_net::{ConfigV4, IpEndpoint, Ipv4Address, Ipv4Cidr, Stack, StaticConfigV4};
+use embassy_rp::flash::{Blocking, Flash, ERASE_SIZE};
+use embassy_rp::peripherals::FLASH;
+use embassy_time::{Duration, Timer};
+use heapless::{String, Vec};
+
+use crate::networking_task::{write_all, DEVICE_ADDRESS};
+use crate::setup_devices::{WifiStack, DEVICE_HOSTNAME};
+use crate::wifi_scan::scan_results;
+
+pub const FLASH_SIZE: usize = 2 * 1024 * 1024;
+
+pub type CredentialFlash = Flash<'static, FLASH, Blocking, FLASH_SIZE>;
+
+// The last sector of flash, left out of the program's room in memory.x
+const CREDENTIALS_OFFSET: u32 = (FLASH_SIZE - ERASE_SIZE) as u32;
+const MAGIC: [u8; 4] = *b"DWF1";
+// Magic, SSID length and bytes, password length and bytes, then the access
+// token's. Records from before tokens end in erased flash, which reads as none
+const RECORD_LEN: usize = 4 + 1 + 32 + 1 + 64 + 1 + 32;
+const TOKEN_AT: usize = 102;
+
+// Where the phone finds the form, handed out as its gateway and DNS server too
+const SETUP_ADDRESS: Ipv4Address = Ipv4Address::new(192, 168, 4, 1);
+const SETUP_CHANNEL: u8 = 6;
+
+// Nobody filling in the form may mean the usual network was only down for a
+// while, so restart and try it again
+const SETUP_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+
+// Phones that can be handed an address at once, from 192.168.4.10 up
+const DHCP_LEASES: usize = 4;
+const LEASE_SECS: u32 = 60 * 60;
+
+#[derive(Clone)]
+pub struct Credentials {
+    pub ssid: String<32>,
+    pub password: String<64>,
+    // Clients have to give this to draw, empty for anyone on the network
+    pub token: String<32>,
+}
+
+impl Credentials {
+    pub fn new(ssid: &str, password: &str) -> Option<Self> {
+        if ssid.is_empty() {
+            return None;
+        }
+        Some(Credentials {
+            ssid: String::try_from(ssid).ok()?,
+            password: String::try_from(password).ok()?,
+            token: String::new(),
+        })
+    }
+}
+
+/// The network set up through the form, if there is one
+pub fn load_credentials(flash: &mut CredentialFlash) -> Option<Credentials> {
+    let mut record = [0u8; RECORD_LEN];
+    flash.blocking_read(CREDENTIALS_OFFSET, &mut record).ok()?;
+    if record[..4] != MAGIC {
+        return None;
+    }
+    let ssid_len = (record[4] as usize).min(32);
+    let password_len = (record[37] as usize).min(64);
+    let ssid = core::str::from_utf8(&record[5..5 + ssid_len]).ok()?;
+    let password = core::str::from_utf8(&record[38..38 + password_len]).ok()?;
+    let mut credentials = Credentials::new(ssid, password)?;
+
+    let token_len = record[TOKEN_AT] as usize;
+    if token_len <= 32 {
+        if let Ok(token) = core::str::from_utf8(&record[TOKEN_AT + 1..TOKEN_AT + 1 + token_len]) {
+            let _ = credentials.token.push_str(token);
+        }
+    }
+    Some(credentials)
+}
+
+fn store_credentials(flash: &mut CredentialFlash, credentials: &Credentials) -> Result<(), embassy_rp::flash::Error> {
+    let mut record = [0u8; RECORD_LEN];
+    record[..4].copy_from_slice(&MAGIC);
+    record[4] = credentials.ssid.len() as u8;
+    record[5..5 + credentials.ssid.len()].copy_from_slice(credentials.ssid.as_bytes());
+    record[37] = credentials.password.len() as u8;
+    record[38..38 + credentials.password.len()].copy_from_slice(credentials.password.as_bytes());
+    record[TOKEN_AT] = credentials.token.len() as u8;
+    record[TOKEN_AT + 1..TOKEN_AT + 1 + credentials.token.len()].copy_from_slice(credentials.token.as_bytes());
+
+    flash.blocking_erase(CREDENTIALS_OFFSET, CREDENTIALS_OFFSET + ERASE_SIZE as u32)?;
+    flash.blocking_write(CREDENTIALS_OFFSET, &record)
+}
+
+/// Open the setup access point and serve the form until someone fills it in,
+/// then restart to join the network they gave
+pub async fn provision(wifi_stack: &mut WifiStack, flash: &mut CredentialFlash) -> ! {
+    // The hostname plus "-setup" has to fit the 32 bytes of an SSID
+    let mut network: String<32> = String::new();
+    let end = (0..=DEVICE_HOSTNAME.len().min(26))
+        .rev()
+        .find(|&end| DEVICE_HOSTNAME.is_char_boundary(end))
+        .unwrap_or(0);
+    let _ = network.push_str(&DEVICE_HOSTNAME[..end]);
+    let _ = network.push_str("-setup");
+
+    wifi_stack.wifi_controller.leave().await;
+    wifi_stack.wifi_controller.start_ap_open(&network, SETUP_CHANNEL).await;
+    wifi_stack.stack.set_config_v4(ConfigV4::Static(StaticConfigV4 {
+        address: Ipv4Cidr::new(SETUP_ADDRESS, 24),
+        dns_servers: Vec::new(),
+        gateway: Some(SETUP_ADDRESS),
+    }));
+    info!("No network to join, join {} and open http://{}", network.as_str(), SETUP_ADDRESS);
+    DEVICE_ADDRESS.signal(Some(SETUP_ADDRESS));
+
+    let stack = wifi_stack.stack;
+    let setup = select3(serve_dhcp(stack), serve_dns(stack), serve_form(stack, flash));
+    if let Either::Second(_) = select(setup, Timer::after(SETUP_TIMEOUT)).await {
+        info!("Nobody set up WiFi, restarting to try again");
+    }
+    Timer::after(Duration::from_secs(1)).await;
+    cortex_m::peripheral::SCB::sys_reset()
+}
+
+// Just enough DHCP for a phone to get an address: an offer for every discover
+// and an ack for every request, one address per hardware address
+async fn serve_dhcp(stack: &'static Stack<'static>) -> ! {
+    let mut rx_meta = [PacketMetadata::EMPTY; 4];
+    let mut rx_buffer = [0u8; 1024];
+    let mut tx_meta = [PacketMetadata::EMPTY; 4];
+    let mut tx_buffer = [0u8; 1024];
+    let mut socket = UdpSocket::new(*stack, &mut rx_meta, &mut rx_buffer, &mut tx_meta, &mut tx_buffer);
+    if socket.bind(67).is_err() {
+        warn!("Failed to bind the DHCP port");
+    }
+
+    let mut leases: [Option<[u8; 6]>; DHCP_LEASES] = [None; DHCP_LEASES];
+    let mut next_lease = 0;
+    let mut request = [0u8; 576];
+    let mut reply = [0u8; 300];
+
+    loop {
+        let Ok((len, _)) = socket.recv_from(&mut request).await else {
+            continue;
+        };
+        // A BOOTP request with the DHCP cookie, from an Ethernet address
+        let request = &request[..len];
+        if len < 240 || request[0] != 1 || request[2] != 6 || request[236..240] != [99, 130, 83, 99] {
+            continue;
+        }
+        let reply_type = match dhcp_option(&request[240..], 53) {
+            Some([1]) => 2, // Discover gets an offer
+            Some([3]) => 5, // Request gets an ack
+            _ => continue,
+        };
+
+        let mut hardware = [0u8; 6];
+        hardware.copy_from_slice(&request[28..34]);
+        let slot = match leases.iter().position(|lease| *lease == Some(hardware)) {
+            Some(slot) => slot,
+            None => {
+                // Full: the oldest lease goes, setup doesn't take long
+                let slot = next_lease;
+                next_lease = (next_lease + 1) % DHCP_LEASES;
+                leases[slot] = Some(hardware);
+                slot
+            }
+        };
+        let [a, b, c, _] = SETUP_ADDRESS.octets();
+        let address = [a, b, c, 10 + slot as u8];
+
+        reply.fill(0);
+        reply[0] = 2;
+        reply[1..3].copy_from_slice(&request[1..3]);
+        reply[4..8].copy_from_slice(&request[4..8]);
+        reply[10..12].copy_from_slice(&request[10..12]);
+        reply[16..20].copy_from_slice(&address);
+        reply[20..24].copy_from_slice(&SETUP_ADDRESS.octets());
+        reply[28..44].copy_from_slice(&request[28..44]);
+        reply[236..240].copy_from_slice(&[99, 130, 83, 99]);
+
+        let lease = LEASE_SECS.to_be_bytes();
+        let setup = SETUP_ADDRESS.octets();
+        let options: [(u8, &[u8]); 6] = [
+            (53, &[reply_type]),
+            (54, &setup),
+            (51, &lease),
+            (1, &[255, 255, 255, 0]),
+            (3, &setup),
+            (6, &setup),
+        ];
+        let mut len = 240;
+        for (code, value) in options {
+            reply[len] = code;
+            reply[len + 1] = value.len() as u8;
+            reply[len + 2..len + 2 + value.len()].copy_from_slice(value);
+            len += 2 + value.len();
+        }
+        reply[len] = 255;
+        len += 1;
+
+        let broadcast = IpEndpoint::new(Ipv4Address::BROADCAST.into(), 68);
+        let _ = socket.send_to(&reply[..len], broadcast).await;
+    }
+}
+
+fn dhcp_option(mut options: &[u8], code: u8) -> Option<&[u8]> {
+    while let [kind, rest @ ..] = options {
+        match kind {
+            0 => options = rest,
+            255 => return None,
+            _ => {
+                let (&len, rest) = rest.split_first()?;
+                let value = rest.get(..len as usize)?;
+                if *kind == code {
+                    return Some(value);
+                }
+                options = &rest[len as usize..];
+            }
+        }
+    }
+    None
+}
+
+// Every name is this device, which is what makes a phone pop up the form
+async fn serve_dns(stack: &'static Stack<'static>) -> ! {
+    let mut rx_meta = [PacketMetadata::EMPTY; 4];
+    let mut rx_buffer = [0u8; 1024];
+    let mut tx_meta = [PacketMetadata::EMPTY; 4];
+    let mut tx_buffer = [0u8; 1024];
+    let mut socket = UdpSocket::new(*stack, &mut rx_meta, &mut rx_buffer, &mut tx_meta, &mut tx_buffer);
+    if socket.bind(53).is_err() {
+        warn!("Failed to bind the DNS port");
+    }
+
+    let mut query = [0u8; 512];
+    loop {
+        let Ok((len, endpoint)) = socket.recv_from(&mut query).await else {
+            continue;
+        };
+        let Some(reply_len) = dns_reply(&mut query, len) else {
+            continue;
+        };
+        let _ = socket.send_to(&query[..reply_len], endpoint).await;
+    }
+}
+
+// Turn the query in `packet` into its answer in place, returning the answer's length
+fn dns_reply(packet: &mut [u8], len: usize) -> Option<usize> {
+    // A standard query with a single question
+    if len < 12 || packet[2] & 0xf8 != 0 || packet[4..6] != [0, 1] {
+        return None;
+    }
+    let mut end = 12;
+    while *packet.get(end)? != 0 {
+        end += 1 + packet[end] as usize;
+    }
+    // Past the name's terminator, type and class
+    end += 5;
+    if end > len {
+        return None;
+    }
+    let is_address = packet[end - 4..end - 2] == [0, 1];
+
+    // A response, recursion desired and available, no records but the answer
+    packet[2] = 0x81;
+    packet[3] = 0x80;
+    packet[6..12].copy_from_slice(&[0, is_address as u8, 0, 0, 0, 0]);
+    if !is_address {
+        return Some(end);
+    }
+    // Pointer to the question's name, A, IN, a minute to live, the address
+    let answer = [0xc0, 0x0c, 0, 1, 0, 1, 0, 0, 0, 60, 0, 4];
+    packet.get_mut(end..end + answer.len())?.copy_from_slice(&answer);
+    packet.get_mut(end + answer.len()..end + answer.len() + 4)?.copy_from_slice(&SETUP_ADDRESS.octets());
+    Some(end + answer.len() + 4)
+}
+
+// The form on every path, until a POST of it has a network to store
+async fn serve_form(stack: &'static Stack<'static>, flash: &mut CredentialFlash) {
+    let mut rx_buffer = [0; 1024];
+    let mut tx_buffer = [0; 1024];
+
+    loop {
+        let mut socket = TcpSocket::new(*stack, &mut rx_buffer, &mut tx_buffer);
+        socket.set_timeout(Some(Duration::from_secs(10)));
+        if socket.accept(80).await.is_err() {
+            Timer::after(Duration::from_millis(100)).await;
+            continue;
+        }
+
+        let mut request = [0u8; 1024];
+        let saved = match read_form(&mut socket, &mut request).await {
+            Some(body) => match form_credentials(body) {
+                Some(credentials) => match store_credentials(flash, &credentials) {
+                    Ok(()) => {
+                        info!("Saved WiFi network {}", credentials.ssid.as_str());
+                        let _ = write_page(&mut socket, Page::Saved(&credentials.ssid)).await;
+                        true
+                    }
+                    Err(_) => {
+                        warn!("Failed to save WiFi network");
+                        let _ = write_page(&mut socket, Page::Form(Some("Couldn't save that, try again"))).await;
+                        false
+                    }
+                },
+                None => {
+                    let _ = write_page(&mut socket, Page::Form(Some("That network name or token is too long"))).await;
+                    false
+                }
+            },
+            None => {
+                let _ = write_page(&mut socket, Page::Form(None)).await;
+                false
+            }
+        };
+        let _ = socket.flush().await;
+        socket.close();
+        Timer::after(Duration::from_millis(10)).await;
+        if saved {
+            return;
+        }
+    }
+}
+
+// The body of a POST, None for anything else
+async fn read_form<'a>(socket: &mut TcpSocket<'_>, request: &'a mut [u8]) -> Option<&'a [u8]> {
+    let mut read_cursor = 0;
+    loop {
+        let n = socket.read(&mut request[read_cursor..]).await.ok()?;
+        if n == 0 {
+            return None;
+        }
+        read_cursor += n;
+
+        let mut headers = [httparse::EMPTY_HEADER; 16];
+        let mut parsed = httparse::Request::new(&mut headers);
+        match parsed.parse(&request[..read_cursor]) {
+            Ok(httparse::Status::Complete(header_len)) => {
+                if parsed.method != Some("POST") {
+                    return None;
+                }
+                let body_len: usize = parsed
+                    .headers
+                    .iter()
+                    .find(|header| header.name.eq_ignore_ascii_case("content-length"))
+                    .and_then(|header| core::str::from_utf8(header.value).ok()?.trim().parse().ok())?;
+                if header_len + body_len > request.len() {
+                    return None;
+                }
+                while read_cursor < header_len + body_len {
+                    let n = socket.read(&mut request[read_cursor..]).await.ok()?;
+                    if n == 0 {
+                        return None;
+                    }
+                    read_cursor += n;
+                }
+                return Some(&request[header_len..header_len + body_len]);
+            }
+            Ok(httparse::Status::Partial) if read_cursor < request.len() => {}
+            _ => return None,
+        }
+    }
+}
+
+fn form_credentials(body: &[u8]) -> Option<Credentials> {
+    let mut ssid: String<32> = String::new();
+    let mut password: String<64> = String::new();
+    let mut token: String<32> = String::new();
+    for field in body.split(|&byte| byte == b'&') {
+        let mut parts = field.splitn(2, |&byte| byte == b'=');
+        let (name, value) = (parts.next()?, parts.next().unwrap_or(&[]));
+        match name {
+            b"ssid" => ssid = url_decode(value)?,
+            b"password" => password = url_decode(value)?,
+            b"token" => token = url_decode(value)?,
+            _ => {}
+        }
+    }
+    let mut credentials = Credentials::new(&ssid, &password)?;
+    credentials.token = token;
+    Some(credentials)
+}
+
+/// Form and query encoding: '+' for spaces and %XX for everything else out of the ordinary
+pub fn url_decode<const N: usize>(value: &[u8]) -> Option<String<N>> {
+    let mut bytes: Vec<u8, N> = Vec::new();
+    let mut i = 0;
+    while i < value.len() {
+        let byte = match value[i] {
+            b'+' => b' ',
+            b'%' => {
+                // from_str_radix alone would take a sign, as in "%+5"
+                let hex = value.get(i + 1..i + 3)?;
+                if !hex.iter().all(u8::is_ascii_hexdigit) {
+                    return None;
+                }
+                i += 2;
+                u8::from_str_radix(core::str::from_utf8(hex).ok()?, 16).ok()?
+            }
+            byte => byte,
+        };
+        bytes.push(byte).ok()?;
+        i += 1;
+    }
+    String::from_utf8(bytes).ok()
+}
+
+enum Page<'a> {
+    // With a complaint about the last try, if there was one
+    Form(Option<&'a str>),
+    Saved(&'a str),
+}
+
+async fn write_page(socket: &mut TcpSocket<'_>, page: Page<'_>) -> Result<(), embassy_net::tcp::Error> {
+    write_all(socket, b"HTTP/1.1 200 OK\r\nContent-Type: text/html; charset=utf-8\r\nConnection: close\r\n\r\n").await?;
+    write_all(socket, b"<!DOCTYPE html><meta name=viewport content=\"width=device-width\"><title>Doodle rs setup</title>").await?;
+
+    let mut html: String<512> = String::new();
+    match page {
+        Page::Form(complaint) => {
+            let _ = write!(html, "<h1>Doodle rs setup</h1>");
+            if let Some(complaint) = complaint {
+                let _ = write!(html, "<p><b>{}</b></p>", complaint);
+            }
+            let _ = write!(
+                html,
+                "<form method=post action=/><p>Network<br><input name=ssid list=networks maxlength=32 required></p>\
+                 <p>Password<br><input name=password type=password maxlength=64></p>\
+                 <p>Access token, to keep drawing to those who know it<br><input name=token maxlength=32></p>\
+                 <button>Join</button></form>"
+            );
+            write_all(socket, html.as_bytes()).await?;
+
+            // The networks from the boot scan, to pick from
+            write_all(socket, b"<datalist id=networks>").await?;
+            for entry in scan_results() {
+                html.clear();
+                let _ = write!(html, "<option value=\"");
+                write_escaped(&mut html, &entry.ssid);
+                let _ = write!(html, "\">");
+                write_all(socket, html.as_bytes()).await?;
+            }
+            write_all(socket, b"</datalist>").await?;
+        }
+        Page::Saved(ssid) => {
+            let _ = write!(html, "<h1>Saved</h1><p>Restarting to join ");
+            write_escaped(&mut html, ssid);
+            let _ = write!(html, ". If it can't, this network comes back.</p>");
+            write_all(socket, html.as_bytes()).await?;
+        }
+    }
+    Ok(())
+}
+
+fn write_escaped<const N: usize>(html: &mut String<N>, text: &str) {
+    for c in text.chars() {
+        let _ = match c {
+            '&' => html.push_str("&amp;"),
+            '<' => html.push_str("&lt;"),
+            '"' => html.push_str("&quot;"),
+            c => html.push(c).map_err(|_| ()),
+        };
+    }
+}
@@ -3,8 +3,8 @@
 use cyw43_pio::{PioSpi, RM2_CLOCK_DIVIDER};
 use defmt::*;
 use embassy_executor::Spawner;
-use embassy_net::{Config as WifiConfig, Stack, StackResources, Ipv4Address, Ipv4Cidr, StaticConfigV4};
-use heapless::Vec;
+use embassy_net::{Config as WifiConfig, DhcpConfig, Stack, StackResources, Ipv4Address, Ipv4Cidr, StaticConfigV4};
+use heapless::{String, Vec};
 use embassy_rp::bind_interrupts;
 use embassy_rp::gpio::{Level, Output};
 use embassy_rp::peripherals::{DMA_CH0, 
@@ -42,6 +42,102 @@ async fn net_task(mut runner: embassy_net::Runner<'static, cyw43::NetDriver<'sta
     runner.run().await
 }
 
+// Name the device reports about itself, override with the DEVICE_HOSTNAME env variable.
+// Kept to 32 characters, the limit for a DHCP hostname option
+pub const DEVICE_HOSTNAME: &str = match option_env!("DEVICE_HOSTNAME") {
+    Some(hostname) => hostname,
+    None => "doodle-rs",
+};
+const _: () = assert!(DEVICE_HOSTNAME.len() <= 32, "DEVICE_HOSTNAME is longer than 32 characters");
+
+// Address to fall back to when no DHCP server answers, override with the
+// STATIC_ADDRESS and STATIC_GATEWAY env variables
+const STATIC_ADDRESS: &str = match option_env!("STATIC_ADDRESS") {
+    Some(address) => address,
+    None => "192.168.68.100/24",
+};
+const STATIC_GATEWAY: &str = match option_env!("STATIC_GATEWAY") {
+    Some(gateway) => gateway,
+    None => "192.168.68.1",
+};
+
+// Parsed while building, so a typo fails the build instead of the device at boot
+const STATIC_CIDR: ([u8; 4], u8) = parse_address(STATIC_ADDRESS, true);
+const STATIC_ROUTER: [u8; 4] = parse_address(STATIC_GATEWAY, false).0;
+
+// Dotted IPv4 octets, with a prefix length after a '/' for the address
+const fn parse_address(text: &str, cidr: bool) -> ([u8; 4], u8) {
+    let bytes = text.as_bytes();
+    let mut octets = [0u8; 4];
+    let mut octet = 0;
+    let mut value = 0u32;
+    let mut digits = 0;
+    let mut i = 0;
+    while i < bytes.len() && bytes[i] != b'/' {
+        if bytes[i] == b'.' {
+            if digits == 0 || octet == 3 || value > 255 {
+                invalid_address(cidr);
+            }
+            octets[octet] = value as u8;
+            octet += 1;
+            (value, digits) = (0, 0);
+        } else if bytes[i].is_ascii_digit() && digits < 3 {
+            value = value * 10 + (bytes[i] - b'0') as u32;
+            digits += 1;
+        } else {
+            invalid_address(cidr);
+        }
+        i += 1;
+    }
+    if digits == 0 || octet != 3 || value > 255 || (i < bytes.len()) != cidr {
+        invalid_address(cidr);
+    }
+    octets[3] = value as u8;
+    if !cidr {
+        return (octets, 32);
+    }
+
+    let mut prefix = 0u32;
+    let start = i + 1;
+    i = start;
+    while i < bytes.len() {
+        if !bytes[i].is_ascii_digit() || i - start == 2 {
+            invalid_address(cidr);
+        }
+        prefix = prefix * 10 + (bytes[i] - b'0') as u32;
+        i += 1;
+    }
+    if i == start || prefix > 32 {
+        invalid_address(cidr);
+    }
+    (octets, prefix as u8)
+}
+
+const fn invalid_address(cidr: bool) -> ! {
+    if cidr {
+        panic!("STATIC_ADDRESS is not like 192.168.68.100/24")
+    } else {
+        panic!("STATIC_GATEWAY is not like 192.168.68.1")
+    }
+}
+
+pub fn static_config() -> StaticConfigV4 {
+    let ([a, b, c, d], prefix) = STATIC_CIDR;
+    let [w, x, y, z] = STATIC_ROUTER;
+    StaticConfigV4 {
+        address: Ipv4Cidr::new(Ipv4Address::new(a, b, c, d), prefix),
+        dns_servers: Vec::new(),
+        gateway: Some(Ipv4Address::new(w, x, y, z)),
+    }
+}
+
+/// Ask for an address under the device's hostname
+pub fn dhcp_config() -> DhcpConfig {
+    let mut dhcp = DhcpConfig::default();
+    dhcp.hostname = String::try_from(DEVICE_HOSTNAME).ok();
+    dhcp
+}
+
 pub struct WifiStack {
     pub wifi_controller: cyw43::Control<'static>,
     pub stack: &'static Stack<'static>,
@@ -84,12 +180,9 @@ pub async fn setup_wifi(
     wifi_controller.gpio_set(0, false).await;
     info!("WiFi initialized!");
     
-    // Set up network stack
-    let config = WifiConfig::ipv4_static(StaticConfigV4 {
-        address: Ipv4Cidr::new(Ipv4Address::new(192, 168, 68, 100), 24),
-        dns_servers: Vec::new(),
-        gateway: Some(Ipv4Address::new(192, 168, 68, 1)),
-    });
+    // Set up network stack, asking DHCP for an address. `connect_wifi` falls
+    // back to `static_config` if nobody answers
+    let config = WifiConfig::dhcpv4(dhcp_config());
     let seed = rng.next_u64();
     
     static RESOURCES: StaticCell<StackResources<10>> = StaticCell::new();
@@ -0,0 +1,129 @@
+// file: udp_task.rs
+// desc: the binary protocol over plain UDP, for streaming pixels on a trusted LAN
+// without TCP and WebSocket in the way
+
+use defmt::{info, warn};
+use core::sync::atomic::Ordering;
+
+use embassy_net::Stack;
+use embassy_net::udp::{PacketMetadata, UdpMetadata, UdpSocket};
+use embassy_time::Instant;
+
+use doodle_protocol::{
+    decompress_packet, stroke_pixels, Capabilities, DecodeError, Message, Packet, SequenceTracker, MAX_MESSAGE_LEN,
+};
+
+use crate::display_task::{drawing_credit, queue_drawing, DrawingPipe, CANVAS_HEIGHT, CANVAS_WIDTH};
+use crate::networking_task::{next_client_id, publish_stroke, DrawingEvent, CORRUPT_MESSAGES, DRAWING_EVENTS};
+
+pub const UDP_PORT: u16 = 4444;
+
+// Only drawing goes over UDP, nothing here needs the others
+const CAPABILITIES: Capabilities = Capabilities::BATCHING.union(Capabilities::GRAYSCALE);
+
+// A sender hears which of its datagrams arrived after this many, or sooner once
+// the display's queue runs low. There are no timers, a sender waiting on credit
+// sends a Ping, which is always answered with an Ack too
+const ACK_EVERY: u16 = 16;
+const LOW_CREDIT: u8 = 8;
+
+// The sender the sequence numbers belong to. Datagrams from anyone else start over
+struct Sender {
+    endpoint: UdpMetadata,
+    author: u8,
+    received: SequenceTracker,
+}
+
+#[embassy_executor::task]
+pub async fn udp_task(stack: &'static Stack<'static>, drawing_pipe: &'static DrawingPipe) {
+    let mut rx_meta = [PacketMetadata::EMPTY; 8];
+    let mut rx_buffer = [0u8; 2048];
+    let mut tx_meta = [PacketMetadata::EMPTY; 4];
+    let mut tx_buffer = [0u8; 256];
+    let mut socket = UdpSocket::new(*stack, &mut rx_meta, &mut rx_buffer, &mut tx_meta, &mut tx_buffer);
+    if socket.bind(UDP_PORT).is_err() {
+        warn!("Failed to bind UDP port {}", UDP_PORT);
+        return;
+    }
+    info!("Listening for UDP on port {}", UDP_PORT);
+
+    let mut datagram = [0u8; MAX_MESSAGE_LEN];
+    let mut inflate_buffer = [0u8; MAX_MESSAGE_LEN];
+    let mut current: Option<Sender> = None;
+    let mut seq: u16 = 0;
+
+    loop {
+        let Ok((len, endpoint)) = socket.recv_from(&mut datagram).await else {
+            continue;
+        };
+        let received_at = Instant::now();
+
+        if current.as_ref().is_none_or(|sender| sender.endpoint.endpoint != endpoint.endpoint) {
+            let author = next_client_id();
+            info!("UDP sender {} draws as #{}", endpoint.endpoint, author);
+            current = Some(Sender { endpoint, author, received: SequenceTracker::new() });
+        }
+        let Some(sender) = &mut current else {
+            continue;
+        };
+
+        let decoded = decompress_packet(&datagram[..len], &mut inflate_buffer).and_then(Packet::decode);
+        if let Ok(packet) = decoded {
+            sender.received.receive(packet.seq, false);
+        }
+
+        let mut reply = None;
+        let mut ack_now = false;
+        match decoded.map(|packet| packet.message) {
+            Ok(Message::Draw { message, .. }) => {
+                queue_drawing(drawing_pipe, sender.author, message).await;
+                DRAWING_EVENTS.immediate_publisher().publish_immediate(DrawingEvent::Draw {
+                    from: None,
+                    author: sender.author,
+                    message,
+                });
+            }
+            Ok(Message::Stroke { pixels, .. }) => {
+                for message in stroke_pixels(pixels) {
+                    queue_drawing(drawing_pipe, sender.author, message).await;
+                }
+                publish_stroke(None, sender.author, pixels);
+            }
+            Ok(Message::Hello { .. }) => {
+                reply = Some(Message::Hello {
+                    client_id: sender.author,
+                    width: CANVAS_WIDTH as u8,
+                    height: CANVAS_HEIGHT as u8,
+                    capabilities: CAPABILITIES,
+                });
+            }
+            Ok(Message::Ping(token)) => {
+                reply = Some(Message::Ping(token));
+                ack_now = true;
+            }
+            Ok(Message::Echo { sent_ms, .. }) => {
+                let pico_us = received_at.elapsed().as_micros().min(u16::MAX as u64) as u16;
+                reply = Some(Message::Echo { sent_ms, pico_us });
+            }
+            Ok(other) => info!("Ignoring {} message over UDP", other.message_type() as u8),
+            Err(DecodeError::Checksum) => {
+                CORRUPT_MESSAGES.fetch_add(1, Ordering::Relaxed);
+                warn!("Dropping corrupt UDP datagram from {}", endpoint.endpoint);
+            }
+            Err(_) => warn!("Ignoring malformed UDP datagram of {} bytes", len),
+        }
+
+        let credit = drawing_credit(drawing_pipe);
+        ack_now |= sender.received.unacked() >= ACK_EVERY || credit < LOW_CREDIT;
+        let ack = if ack_now { sender.received.ack(credit) } else { None };
+        for message in reply.into_iter().chain(ack) {
+            let packet = Packet { seq, message };
+            seq = seq.wrapping_add(1);
+
+            let mut bytes = [0u8; 32];
+            if let Ok(len) = packet.encode(&mut bytes) {
+                let _ = socket.send_to(&bytes[..len], sender.endpoint).await;
+            }
+        }
+    }
+}
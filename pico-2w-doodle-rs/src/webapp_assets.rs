@@ -0,0 +1,18 @@
+// file: webapp_assets.rs
+// desc: the webapp bundled by build.rs, so browsing to the Pico gets the drawing app
+
+pub struct Asset {
+    pub path: &'static str,
+    pub content_type: &'static str,
+    // Gzipped
+    pub body: &'static [u8],
+}
+
+include!(concat!(env!("OUT_DIR"), "/webapp_assets.rs"));
+
+/// The bundled file for a request path, "/" being the page itself
+pub fn find_asset(path: &str) -> Option<&'static Asset> {
+    let path = path.split(['?', '#']).next().unwrap_or(path);
+    let path = if path == "/" { "/index.html" } else { path };
+    ASSETS.iter().find(|asset| asset.path == path)
+}
@@ -0,0 +1,141 @@
+// file: wifi_scan.rs
+// desc: scan nearby Wi-Fi networks so the device can be placed where the signal is strong
+
+use core::cell::RefCell;
+use core::fmt::Write;
+use core::str::from_utf8;
+
+use defmt::{info, warn};
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::signal::Signal;
+use heapless::{String, Vec};
+
+use cyw43::ScanOptions;
+
+use crate::json_command;
+
+// Enough for a busy apartment block, the OLED only has room for the first few
+pub const MAX_SCAN_RESULTS: usize = 8;
+
+#[derive(Clone)]
+pub struct ScanEntry {
+    pub ssid: String<32>,
+    pub channel: u8,
+    pub rssi: i16,
+}
+
+pub type ScanResults = Vec<ScanEntry, MAX_SCAN_RESULTS>;
+
+// Latest scan, strongest network first. Read by /status and the display task
+pub static SCAN_RESULTS: Mutex<CriticalSectionRawMutex, RefCell<ScanResults>> =
+    Mutex::new(RefCell::new(Vec::new()));
+
+// Raised once a scan has finished, so the display can show the results screen
+pub static SCAN_DONE: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+pub fn scan_results() -> ScanResults {
+    SCAN_RESULTS.lock(|results| results.borrow().clone())
+}
+
+/// What the last scan found of `ssid`
+pub fn find_network(ssid: &str) -> Option<ScanEntry> {
+    SCAN_RESULTS.lock(|results| results.borrow().iter().find(|entry| entry.ssid == ssid).cloned())
+}
+
+/// Read the signal of the access point the radio is joined to into `ssid`'s
+/// entry. Unlike a scan this doesn't take the radio off its channel, so drawing
+/// carries on while it's measured
+pub async fn measure_network(wifi_controller: &mut cyw43::Control<'static>, ssid: &str) {
+    let Ok(name) = String::try_from(ssid) else {
+        return;
+    };
+    let rssi = wifi_controller.get_rssi().await.clamp(i16::MIN as i32, 0) as i16;
+
+    SCAN_RESULTS.lock(|results| {
+        let mut results = results.borrow_mut();
+        match results.iter_mut().find(|entry| entry.ssid == ssid) {
+            Some(entry) => entry.rssi = rssi,
+            // Crowded out of the boot scan, the weakest goes to make room. Its
+            // channel is unknown without a scan
+            None => {
+                if results.is_full() {
+                    results.pop();
+                }
+                let _ = results.push(ScanEntry { ssid: name, channel: 0, rssi });
+            }
+        }
+        results.sort_unstable_by(|a, b| b.rssi.cmp(&a.rssi));
+    });
+}
+
+/// Scan all channels and store one entry per SSID, keeping its strongest access point
+pub async fn scan_networks(wifi_controller: &mut cyw43::Control<'static>) {
+    info!("Scanning for WiFi networks...");
+
+    let mut results = ScanResults::new();
+    let mut scanner = wifi_controller.scan(ScanOptions::default()).await;
+
+    while let Some(bss) = scanner.next().await {
+        let ssid_len = (bss.ssid_len as usize).min(bss.ssid.len());
+        let Ok(ssid) = from_utf8(&bss.ssid[..ssid_len]) else {
+            continue;
+        };
+        // Hidden networks can't be joined by name, so they aren't worth listing
+        if ssid.is_empty() {
+            continue;
+        }
+
+        let rssi = bss.rssi;
+        let channel = (bss.chanspec & 0xff) as u8;
+
+        if let Some(existing) = results.iter_mut().find(|entry| entry.ssid == ssid) {
+            if rssi > existing.rssi {
+                existing.rssi = rssi;
+                existing.channel = channel;
+            }
+            continue;
+        }
+
+        let mut name = String::new();
+        let _ = name.push_str(ssid);
+        let entry = ScanEntry { ssid: name, channel, rssi };
+
+        if results.push(entry.clone()).is_err() {
+            // Full: replace the weakest network if this one is stronger
+            if let Some(weakest) = results.iter_mut().min_by_key(|entry| entry.rssi) {
+                if weakest.rssi < rssi {
+                    *weakest = entry;
+                }
+            }
+        }
+    }
+    drop(scanner);
+
+    results.sort_unstable_by(|a, b| b.rssi.cmp(&a.rssi));
+
+    info!("Found {} networks", results.len());
+    for entry in results.iter() {
+        info!("  {} ch{} {}dBm", entry.ssid.as_str(), entry.channel, entry.rssi);
+    }
+    if results.is_empty() {
+        warn!("WiFi scan found no networks");
+    }
+
+    SCAN_RESULTS.lock(|stored| *stored.borrow_mut() = results);
+    SCAN_DONE.signal(());
+}
+
+/// JSON array of the networks for /status
+pub fn write_status<W: Write>(out: &mut W) -> core::fmt::Result {
+    out.write_char('[')?;
+    for (i, entry) in scan_results().iter().enumerate() {
+        if i > 0 {
+            out.write_char(',')?;
+        }
+        out.write_str(r#"{"ssid":"#)?;
+        json_command::write_string(out, &entry.ssid)?;
+        write!(out, r#","channel":{},"rssi":{}}}"#, entry.channel, entry.rssi)?;
+    }
+    out.write_char(']')
+}
@@ -0,0 +1,89 @@
+// file: brush.rs
+// desc: brush footprint, eraser areas and the hover preview overlay
+
+use web_sys::CanvasRenderingContext2d;
+
+use crate::i18n::Key;
+use crate::AppConfig;
+
+pub const BRUSH_SIZES: [usize; 3] = [1, 2, 3];
+
+/// Gray levels the pen offers, out of doodle_protocol::GRAY_LEVELS
+pub const INK_LEVELS: [u8; 5] = [15, 12, 9, 6, 3];
+
+const PREVIEW_COLOR: &str = "rgba(66, 133, 244, 0.35)";
+
+/// What dragging across the canvas does
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Tool {
+    Pen,
+    // Clears the cells under the brush
+    Eraser,
+    // Clears the rectangle dragged out, in one message
+    EraseArea,
+}
+
+impl Tool {
+    pub const ALL: [Tool; 3] = [Tool::Pen, Tool::Eraser, Tool::EraseArea];
+
+    pub fn label(self) -> Key {
+        match self {
+            Tool::Pen => Key::Pen,
+            Tool::Eraser => Key::Eraser,
+            Tool::EraseArea => Key::EraseArea,
+        }
+    }
+
+    pub fn code(self) -> &'static str {
+        match self {
+            Tool::Pen => "pen",
+            Tool::Eraser => "eraser",
+            Tool::EraseArea => "erase-area",
+        }
+    }
+
+    pub fn from_code(code: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|tool| tool.code() == code)
+    }
+}
+
+/// Grid cells covered by a square brush of side `size` around (x, y), clipped to the grid
+pub fn footprint(x: usize, y: usize, size: usize, config: &AppConfig) -> Vec<(usize, usize)> {
+    let back = (size.max(1) - 1) / 2;
+    let forward = size.max(1) / 2;
+
+    let xs = x.saturating_sub(back)..=(x + forward).min(config.grid_width - 1);
+    let ys = y.saturating_sub(back)..=(y + forward).min(config.grid_height - 1);
+    ys.flat_map(|y| xs.clone().map(move |x| (x, y))).collect()
+}
+
+/// The rectangle with corners `from` and `to` as (x, y, width, height)
+pub fn area(from: (usize, usize), to: (usize, usize)) -> (usize, usize, usize, usize) {
+    (
+        from.0.min(to.0),
+        from.1.min(to.1),
+        from.0.abs_diff(to.0) + 1,
+        from.1.abs_diff(to.1) + 1,
+    )
+}
+
+/// Grid cells of the rectangle with corners `from` and `to`
+pub fn area_cells(from: (usize, usize), to: (usize, usize)) -> Vec<(usize, usize)> {
+    let (x, y, width, height) = area(from, to);
+    (y..y + height).flat_map(|y| (x..x + width).map(move |x| (x, y))).collect()
+}
+
+/// Redraw the overlay with the cells the brush would fill, or clear it when `cells` is empty
+pub fn draw_preview(ctx: &CanvasRenderingContext2d, cells: &[(usize, usize)], config: &AppConfig) {
+    ctx.clear_rect(0.0, 0.0, config.canvas_width, config.canvas_height);
+
+    ctx.set_fill_style_str(PREVIEW_COLOR);
+    for &(x, y) in cells {
+        ctx.fill_rect(
+            x as f64 * config.pixel_size,
+            y as f64 * config.pixel_size,
+            config.pixel_size,
+            config.pixel_size,
+        );
+    }
+}
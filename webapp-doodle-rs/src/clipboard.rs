@@ -0,0 +1,37 @@
+// file: clipboard.rs
+// desc: copy the rendered canvas to the system clipboard as a PNG
+
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::JsCast;
+use wasm_bindgen_futures::JsFuture;
+use web_sys::{ClipboardItem, HtmlCanvasElement};
+
+// Promise of the canvas PNG. The clipboard item is built from the promise
+// rather than the blob so the write starts inside the click's user activation
+fn canvas_png(canvas: &HtmlCanvasElement) -> js_sys::Promise {
+    js_sys::Promise::new(&mut |resolve, reject| {
+        let reject_empty = reject.clone();
+        let on_blob = Closure::once_into_js(move |blob: JsValue| {
+            let _ = if blob.is_null() {
+                reject_empty.call1(&JsValue::NULL, &"canvas produced no image".into())
+            } else {
+                resolve.call1(&JsValue::NULL, &blob)
+            };
+        });
+
+        if let Err(e) = canvas.to_blob(on_blob.unchecked_ref()) {
+            let _ = reject.call1(&JsValue::NULL, &e);
+        }
+    })
+}
+
+pub async fn copy_canvas(canvas: HtmlCanvasElement) -> Result<(), JsValue> {
+    let clipboard = web_sys::window().ok_or("no window")?.navigator().clipboard();
+
+    let record = js_sys::Object::new();
+    js_sys::Reflect::set(&record, &"image/png".into(), &canvas_png(&canvas))?;
+    let item = ClipboardItem::new_with_record_from_str_to_blob_promise(&record)?;
+
+    JsFuture::from(clipboard.write(&js_sys::Array::of1(&item))).await?;
+    Ok(())
+}
@@ -0,0 +1,112 @@
+// file: collect.rs
+// desc: guided data collection, prompting for each digit in turn and storing labeled drawings
+
+use leptos::*;
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::JsCast;
+use web_sys::IdbTransactionMode;
+
+use crate::db::{self, request_future, SAMPLES_STORE};
+use crate::i18n::{t, translate, use_language, Key};
+use crate::share;
+
+const LABELS: usize = 10;
+
+// Stored as the canvas at full resolution, in the share link encoding, so any
+// later preprocessing can be run on the original drawing
+fn sample_record(label: u8, grid: &[Vec<bool>]) -> Result<js_sys::Object, JsValue> {
+    let record = js_sys::Object::new();
+    js_sys::Reflect::set(&record, &"label".into(), &label.into())?;
+    js_sys::Reflect::set(&record, &"canvas".into(), &share::encode_grid(grid).into())?;
+    js_sys::Reflect::set(&record, &"captured".into(), &js_sys::Date::now().into())?;
+    Ok(record)
+}
+
+async fn save_sample(label: u8, grid: Vec<Vec<bool>>) -> Result<(), JsValue> {
+    let db = db::open_db().await?;
+    let store = db
+        .transaction_with_str_and_mode(SAMPLES_STORE, IdbTransactionMode::Readwrite)?
+        .object_store(SAMPLES_STORE)?;
+    request_future(&store.add(&sample_record(label, &grid)?)?).await?;
+    Ok(())
+}
+
+/// How many samples are stored for each digit
+async fn load_counts() -> Result<[u32; LABELS], JsValue> {
+    let db = db::open_db().await?;
+    let store = db.transaction_with_str(SAMPLES_STORE)?.object_store(SAMPLES_STORE)?;
+    let records: js_sys::Array = request_future(&store.get_all()?).await?.dyn_into()?;
+
+    let mut counts = [0; LABELS];
+    for record in records.iter() {
+        let label = js_sys::Reflect::get(&record, &"label".into()).ok().and_then(|label| label.as_f64());
+        if let Some(count) = label.and_then(|label| counts.get_mut(label as usize)) {
+            *count += 1;
+        }
+    }
+    Ok(counts)
+}
+
+// The digit with the fewest samples, so the dataset stays balanced
+fn next_label(counts: &[u32; LABELS]) -> u8 {
+    (0..LABELS).min_by_key(|&label| counts[label]).unwrap_or(0) as u8
+}
+
+#[component]
+pub fn CollectPanel(
+    #[prop(into)] grid: Signal<Vec<Vec<bool>>>,
+    // Called after a sample is stored, to clear the canvas for the next one
+    #[prop(into)] on_saved: Callback<()>,
+) -> impl IntoView {
+    let language = use_language();
+    let (counts, set_counts) = create_signal([0u32; LABELS]);
+    let (label, set_label) = create_signal(0u8);
+    let (saving, set_saving) = create_signal(false);
+
+    spawn_local(async move {
+        match load_counts().await {
+            Ok(stored) => {
+                set_counts.set(stored);
+                set_label.set(next_label(&stored));
+            }
+            Err(e) => log::warn!("Failed to load collected samples: {:?}", e),
+        }
+    });
+
+    let is_blank = move || grid.with(|grid| !grid.iter().flatten().any(|pixel| *pixel));
+
+    let save = move |_| {
+        let digit = label.get_untracked();
+        let drawing = grid.get_untracked();
+        set_saving.set(true);
+        spawn_local(async move {
+            match save_sample(digit, drawing).await {
+                Ok(()) => {
+                    log::info!("Saved a sample of {}", digit);
+                    set_counts.update(|counts| counts[digit as usize] += 1);
+                    set_label.set(next_label(&counts.get_untracked()));
+                    on_saved.call(());
+                }
+                Err(e) => log::error!("Failed to save sample: {:?}", e),
+            }
+            set_saving.set(false);
+        });
+    };
+    let skip = move |_| set_label.update(|label| *label = (*label + 1) % LABELS as u8);
+
+    view! {
+        <details class="collect-panel">
+            <summary>{t(Key::CollectSamples)}</summary>
+            <p class="collect-prompt">
+                {move || translate(language.get(), Key::DrawDigit).replace("{digit}", &label.get().to_string())}
+            </p>
+            <button prop:disabled=move || saving.get() || is_blank() on:click=save>{t(Key::SaveSample)}</button>
+            <button on:click=skip>{t(Key::Skip)}</button>
+            <p>{t(Key::SamplesCollected)} {move || counts.get().iter().sum::<u32>()}</p>
+            <table>
+                <tr>{(0..LABELS).map(|digit| view! { <th>{digit}</th> }).collect_view()}</tr>
+                <tr>{(0..LABELS).map(|digit| view! { <td>{move || counts.get()[digit]}</td> }).collect_view()}</tr>
+            </table>
+        </details>
+    }
+}
@@ -0,0 +1,103 @@
+// file: compression.rs
+// desc: candidate canvas encodings, compared by the debug panel's compression report
+
+use crate::share;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Scheme {
+    // One bit per pixel, trailing blank bytes dropped. What share links use
+    Packed,
+    // Alternating runs of blank and inked pixels, starting with blank. What
+    // Frame messages to the Pico use
+    RunLength,
+    // Gaps between pixels that differ from the previous canvas
+    Delta,
+}
+
+impl Scheme {
+    pub const ALL: [Scheme; 3] = [Scheme::Packed, Scheme::RunLength, Scheme::Delta];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Scheme::Packed => "packed",
+            Scheme::RunLength => "RLE",
+            Scheme::Delta => "delta",
+        }
+    }
+
+    /// Encode `grid`. Delta needs the canvas the receiver already has, the others ignore it
+    pub fn encode(&self, grid: &[Vec<bool>], previous: &[Vec<bool>]) -> Vec<u8> {
+        match self {
+            Scheme::Packed => share::pack_grid(grid),
+            Scheme::RunLength => encode_runs(grid),
+            Scheme::Delta => encode_delta(grid, previous),
+        }
+    }
+
+    pub fn decode(&self, bytes: &[u8], previous: &[Vec<bool>], width: usize, height: usize) -> Option<Vec<Vec<bool>>> {
+        match self {
+            Scheme::Packed => Some(share::unpack_grid(bytes, width, height)),
+            Scheme::RunLength => decode_runs(bytes, width, height),
+            Scheme::Delta => decode_delta(bytes, previous),
+        }
+    }
+}
+
+// LEB128 style: 7 bits per byte, high bit set while more bytes follow
+fn push_varint(out: &mut Vec<u8>, mut value: usize) {
+    while value >= 0x80 {
+        out.push((value as u8 & 0x7f) | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn read_varint(bytes: &mut impl Iterator<Item = u8>) -> Option<usize> {
+    let mut value = 0usize;
+    for shift in (0..usize::BITS).step_by(7) {
+        let byte = bytes.next()?;
+        value |= ((byte & 0x7f) as usize) << shift;
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+    }
+    None
+}
+
+fn encode_runs(grid: &[Vec<bool>]) -> Vec<u8> {
+    let mut out = Vec::new();
+    doodle_protocol::encode_runs(grid.iter().flatten().copied(), |byte| out.push(byte));
+    out
+}
+
+fn decode_runs(bytes: &[u8], width: usize, height: usize) -> Option<Vec<Vec<bool>>> {
+    let mut grid = vec![vec![false; width]; height];
+    doodle_protocol::decode_runs(bytes, width * height, |i| grid[i / width][i % width] = true).ok()?;
+    Some(grid)
+}
+
+fn encode_delta(grid: &[Vec<bool>], previous: &[Vec<bool>]) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut last = 0;
+    let changed = grid.iter().flatten().zip(previous.iter().flatten()).enumerate();
+    for (i, (pixel, before)) in changed {
+        if pixel != before {
+            push_varint(&mut out, i - last);
+            last = i;
+        }
+    }
+    out
+}
+
+fn decode_delta(bytes: &[u8], previous: &[Vec<bool>]) -> Option<Vec<Vec<bool>>> {
+    let width = previous.first().map_or(0, |row| row.len()).max(1);
+    let mut grid = previous.to_vec();
+    let mut bytes = bytes.iter().copied().peekable();
+    let mut index = 0;
+    while bytes.peek().is_some() {
+        index += read_varint(&mut bytes)?;
+        let pixel = grid.get_mut(index / width)?.get_mut(index % width)?;
+        *pixel = !*pixel;
+    }
+    Some(grid)
+}
@@ -0,0 +1,70 @@
+// file: db.rs
+// desc: the app's IndexedDB database, shared by everything that keeps data across visits
+
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::JsCast;
+use wasm_bindgen_futures::JsFuture;
+use web_sys::{IdbDatabase, IdbObjectStoreParameters, IdbRequest};
+
+const DB_NAME: &str = "doodle-rs";
+// Bump when adding a store so the upgrade below creates it
+const DB_VERSION: u32 = 2;
+
+// One record per page load, keyed by its start time so saving again overwrites it
+pub const SESSIONS_STORE: &str = "sessions";
+// Labeled drawings from the data collection mode, keyed by an increasing id
+pub const SAMPLES_STORE: &str = "samples";
+
+/// Resolve with the request's result once it succeeds
+pub fn request_future(request: &IdbRequest) -> JsFuture {
+    let promise = js_sys::Promise::new(&mut |resolve, reject| {
+        let done = request.clone();
+        let on_success = Closure::once_into_js(move |_: web_sys::Event| {
+            let _ = resolve.call1(&JsValue::NULL, &done.result().unwrap_or(JsValue::UNDEFINED));
+        });
+        let on_error = Closure::once_into_js(move |_: web_sys::Event| {
+            let _ = reject.call1(&JsValue::NULL, &"IndexedDB request failed".into());
+        });
+        request.set_onsuccess(Some(on_success.unchecked_ref()));
+        request.set_onerror(Some(on_error.unchecked_ref()));
+    });
+    JsFuture::from(promise)
+}
+
+// Create whichever stores an older version of the database lacks
+fn upgrade(db: &IdbDatabase) -> Result<(), JsValue> {
+    let existing = db.object_store_names();
+
+    if !existing.contains(SESSIONS_STORE) {
+        let params = IdbObjectStoreParameters::new();
+        params.set_key_path(&"started".into());
+        db.create_object_store_with_optional_parameters(SESSIONS_STORE, &params)?;
+    }
+    if !existing.contains(SAMPLES_STORE) {
+        let params = IdbObjectStoreParameters::new();
+        params.set_auto_increment(true);
+        db.create_object_store_with_optional_parameters(SAMPLES_STORE, &params)?;
+    }
+    Ok(())
+}
+
+pub async fn open_db() -> Result<IdbDatabase, JsValue> {
+    let factory = web_sys::window()
+        .ok_or("no window")?
+        .indexed_db()?
+        .ok_or("IndexedDB unavailable")?;
+    let request = factory.open_with_u32(DB_NAME, DB_VERSION)?;
+
+    let upgrading = request.clone();
+    let on_upgrade = Closure::once_into_js(move |_: web_sys::Event| {
+        let Ok(db) = upgrading.result().and_then(|db| db.dyn_into::<IdbDatabase>()) else {
+            return;
+        };
+        if let Err(e) = upgrade(&db) {
+            log::error!("Failed to upgrade database: {:?}", e);
+        }
+    });
+    request.set_onupgradeneeded(Some(on_upgrade.unchecked_ref()));
+
+    Ok(request_future(&request).await?.dyn_into()?)
+}
@@ -0,0 +1,391 @@
+// file: debug.rs
+// desc: collapsible debug panel with developer tools
+
+use leptos::*;
+use wasm_bindgen_futures::JsFuture;
+
+use crate::compression::Scheme;
+use crate::i18n::{t, Key};
+use crate::history::{Author, DrawEvent};
+use crate::recorder::{self, Playback, Recording};
+use crate::transport::{self, NetworkConditions};
+
+// Repeat each encode and decode so the timings rise above the timer's resolution
+const TIMING_RUNS: u32 = 50;
+
+// Upper bounds of the latency histogram's buckets, in ms. Slower round trips
+// go in a last bucket of their own
+const LATENCY_BUCKETS_MS: [f64; 6] = [25.0, 50.0, 100.0, 250.0, 500.0, 1000.0];
+
+/// A round trip to the Pico, and how much of it the Pico spent on the Echo
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LatencySample {
+    pub round_trip_ms: f64,
+    pub pico_us: u16,
+}
+
+// Latency, jitter and drop-rate controls for the transport's network simulator
+#[component]
+fn NetworkSimulator() -> impl IntoView {
+    let (conditions, set_conditions) = create_signal(transport::network_conditions());
+
+    create_effect(move |_| {
+        transport::set_network_conditions(conditions.get());
+    });
+
+    let update = move |apply: fn(&mut NetworkConditions, f64), ev: ev::Event| {
+        let value = event_target_value(&ev).parse::<f64>().unwrap_or(0.0).max(0.0);
+        set_conditions.update(|conditions| apply(conditions, value));
+    };
+
+    view! {
+        <fieldset class="debug-section">
+            <legend>{t(Key::NetworkSimulator)}</legend>
+            <label>
+                {t(Key::Latency)}
+                <input type="number" min="0" step="10"
+                    prop:value=move || conditions.get().latency_ms
+                    on:input=move |ev| update(|c, v| c.latency_ms = v as u32, ev)
+                />
+            </label>
+            <label>
+                {t(Key::Jitter)}
+                <input type="number" min="0" step="10"
+                    prop:value=move || conditions.get().jitter_ms
+                    on:input=move |ev| update(|c, v| c.jitter_ms = v as u32, ev)
+                />
+            </label>
+            <label>
+                {t(Key::DropRate)}
+                <input type="number" min="0" max="100" step="1"
+                    prop:value=move || (conditions.get().drop_rate * 100.0).round()
+                    on:input=move |ev| update(|c, v| c.drop_rate = v.min(100.0) / 100.0, ev)
+                />
+            </label>
+            <button on:click=move |_| set_conditions.set(NetworkConditions::default())>{t(Key::Reset)}</button>
+        </fieldset>
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct SchemeResult {
+    scheme: Scheme,
+    bytes: usize,
+    encode_us: f64,
+    decode_us: f64,
+    round_trips: bool,
+}
+
+fn now_ms() -> f64 {
+    web_sys::window()
+        .and_then(|window| window.performance())
+        .map_or_else(js_sys::Date::now, |performance| performance.now())
+}
+
+// Average time of `TIMING_RUNS` calls, in microseconds
+fn time_us<T>(mut run: impl FnMut() -> T) -> f64 {
+    let start = now_ms();
+    for _ in 0..TIMING_RUNS {
+        std::hint::black_box(run());
+    }
+    (now_ms() - start) * 1000.0 / TIMING_RUNS as f64
+}
+
+fn measure(scheme: Scheme, grid: &[Vec<bool>], previous: &[Vec<bool>]) -> SchemeResult {
+    let height = grid.len();
+    let width = grid.first().map_or(0, |row| row.len());
+
+    let encoded = scheme.encode(grid, previous);
+    let decoded = scheme.decode(&encoded, previous, width, height);
+
+    SchemeResult {
+        scheme,
+        bytes: encoded.len(),
+        encode_us: time_us(|| scheme.encode(grid, previous)),
+        decode_us: time_us(|| scheme.decode(&encoded, previous, width, height)),
+        round_trips: decoded.as_deref() == Some(grid),
+    }
+}
+
+// Encodes the canvas with every scheme and recommends the smallest, with
+// encode time breaking ties. Delta is measured against the canvas of the
+// previous run, like a client that is already in sync (a blank canvas at first)
+#[component]
+fn CompressionReport(#[prop(into)] grid: Signal<Vec<Vec<bool>>>) -> impl IntoView {
+    let (results, set_results) = create_signal(Vec::<SchemeResult>::new());
+    let baseline = store_value(None::<Vec<Vec<bool>>>);
+
+    let analyze = move |_| {
+        let grid = grid.get_untracked();
+        let previous = baseline
+            .get_value()
+            .filter(|previous| previous.len() == grid.len())
+            .unwrap_or_else(|| grid.iter().map(|row| vec![false; row.len()]).collect());
+
+        let results: Vec<_> = Scheme::ALL
+            .into_iter()
+            .map(|scheme| measure(scheme, &grid, &previous))
+            .collect();
+        for result in results.iter() {
+            log::info!("Compression: {:?}", result);
+        }
+
+        baseline.set_value(Some(grid));
+        set_results.set(results);
+    };
+
+    let recommended = move || {
+        results.with(|results| {
+            results
+                .iter()
+                .filter(|result| result.round_trips)
+                .min_by(|a, b| a.bytes.cmp(&b.bytes).then(a.encode_us.total_cmp(&b.encode_us)))
+                .map(|result| result.scheme.name())
+        })
+    };
+
+    view! {
+        <fieldset class="debug-section">
+            <legend>{t(Key::Compression)}</legend>
+            <button on:click=analyze>{t(Key::Analyze)}</button>
+            <Show when=move || results.with(|results| !results.is_empty())>
+                <table class="compression-table">
+                    <tr>
+                        <th>{t(Key::Scheme)}</th>
+                        <th>{t(Key::Bytes)}</th>
+                        <th>{t(Key::EncodeTime)}</th>
+                        <th>{t(Key::DecodeTime)}</th>
+                    </tr>
+                    {move || results.get().into_iter().map(|result| view! {
+                        <tr class:error=!result.round_trips>
+                            <td>{result.scheme.name()}</td>
+                            <td>{result.bytes}</td>
+                            <td>{format!("{:.1}", result.encode_us)}</td>
+                            <td>{format!("{:.1}", result.decode_us)}</td>
+                        </tr>
+                    }).collect_view()}
+                </table>
+                <p>{t(Key::Recommended)} {recommended}</p>
+            </Show>
+        </fieldset>
+    }
+}
+
+// How the recent round trips to the Pico spread out. The Pico's own time shows
+// whether a slow link is the network or the firmware
+#[component]
+fn LatencyHistogram(#[prop(into)] samples: Signal<Vec<LatencySample>>) -> impl IntoView {
+    let buckets = move || {
+        let mut counts = [0usize; LATENCY_BUCKETS_MS.len() + 1];
+        samples.with(|samples| {
+            for sample in samples {
+                let bucket = LATENCY_BUCKETS_MS
+                    .iter()
+                    .position(|bound| sample.round_trip_ms < *bound)
+                    .unwrap_or(LATENCY_BUCKETS_MS.len());
+                counts[bucket] += 1;
+            }
+        });
+        counts
+    };
+    let median = move || {
+        let mut round_trips: Vec<f64> = samples.with(|samples| samples.iter().map(|sample| sample.round_trip_ms).collect());
+        round_trips.sort_by(f64::total_cmp);
+        round_trips.get(round_trips.len() / 2).copied()
+    };
+    let pico_us = move || {
+        samples.with(|samples| {
+            let total: u32 = samples.iter().map(|sample| sample.pico_us as u32).sum();
+            total / samples.len().max(1) as u32
+        })
+    };
+
+    view! {
+        <fieldset class="debug-section">
+            <legend>{t(Key::RoundTripLatency)}</legend>
+            <Show when=move || samples.with(|samples| !samples.is_empty())>
+                <table>
+                    {move || {
+                        let counts = buckets();
+                        let most = counts.iter().copied().max().unwrap_or(0);
+                        counts.into_iter().enumerate().map(|(bucket, count)| {
+                            let label = match LATENCY_BUCKETS_MS.get(bucket) {
+                                Some(bound) => format!("< {} ms", bound),
+                                None => format!("≥ {} ms", LATENCY_BUCKETS_MS[LATENCY_BUCKETS_MS.len() - 1]),
+                            };
+                            view! {
+                                <tr>
+                                    <td>{label}</td>
+                                    <td><progress max=most value=count></progress></td>
+                                    <td>{count}</td>
+                                </tr>
+                            }
+                        }).collect_view()
+                    }}
+                </table>
+                <p>{t(Key::MedianLatency)} {move || median().map(|ms| format!("{:.0}", ms))}</p>
+                <p>{t(Key::PicoProcessing)} {pico_us}</p>
+            </Show>
+        </fieldset>
+    }
+}
+
+// Records drawing and Pico traffic until stopped, then downloads it as JSON
+// so a bug can be reproduced from the file
+#[component]
+fn SessionRecorder(#[prop(into)] grid: Signal<Vec<Vec<bool>>>, pico_url: &'static str) -> impl IntoView {
+    let (recording, set_recording) = create_signal(recorder::is_recording());
+
+    let start = move |_| {
+        recorder::start(pico_url, grid.get_untracked());
+        set_recording.set(true);
+    };
+    let stop = move |_| {
+        set_recording.set(false);
+        if let Some(session) = recorder::stop() {
+            if let Err(e) = recorder::download(&session) {
+                log::error!("Failed to download session recording: {:?}", e);
+            }
+        }
+    };
+
+    view! {
+        <fieldset class="debug-section">
+            <legend>{t(Key::SessionRecording)}</legend>
+            <Show
+                when=move || recording.get()
+                fallback=move || view! { <button on:click=start>{t(Key::Record)}</button> }
+            >
+                <button on:click=stop>{t(Key::StopAndDownload)}</button>
+            </Show>
+        </fieldset>
+    }
+}
+
+// Replays a recorded session onto the canvas, and onto the Pico if asked to.
+// The canvas has to be on the grid the session was recorded on
+#[component]
+fn SessionPlayer(
+    #[prop(into)] grid: Signal<Vec<Vec<bool>>>,
+    on_reset: Callback<Vec<Vec<bool>>>,
+    on_event: Callback<(DrawEvent, Author)>,
+) -> impl IntoView {
+    let (session, set_session) = create_signal(None::<Recording>);
+    let (speed, set_speed) = create_signal(1.0);
+    let (to_pico, set_to_pico) = create_signal(false);
+    let (playing, set_playing) = create_signal(false);
+    let playback = store_value(None::<Playback>);
+
+    let on_file = move |ev: ev::Event| {
+        let input = event_target::<web_sys::HtmlInputElement>(&ev);
+        let Some(file) = input.files().and_then(|files| files.get(0)) else {
+            return;
+        };
+        spawn_local(async move {
+            let json = JsFuture::from(file.text()).await.ok().and_then(|json| json.as_string());
+            let recording = json.as_deref().and_then(Recording::from_json);
+            if recording.is_none() {
+                log::warn!("Not a session recording: {}", file.name());
+            }
+            set_session.set(recording);
+        });
+    };
+
+    let play = move |_| {
+        let Some(recording) = session.get_untracked() else {
+            return;
+        };
+        let size = grid.with_untracked(|grid| (grid.first().map_or(0, |row| row.len()), grid.len()));
+        if (recording.width(), recording.height()) != size {
+            log::warn!(
+                "Session was recorded on a {}x{} grid, switch to it before playing",
+                recording.width(),
+                recording.height()
+            );
+            return;
+        }
+
+        let to_pico = to_pico.get_untracked();
+        on_reset.call(recording.grid.clone());
+        if to_pico {
+            transport::send_grid(&recording.grid);
+        }
+        let replay = move |event: DrawEvent, author: Author| {
+            on_event.call((event, author));
+            if to_pico {
+                match event {
+                    DrawEvent::Pixel { x, y, state } => transport::send_pixel(x, y, state),
+                    DrawEvent::Clear => transport::send_clear(),
+                    DrawEvent::ClearRect { x, y, width, height } => {
+                        transport::send_clear_rect(x, y, width, height)
+                    }
+                }
+            }
+        };
+
+        set_playing.set(true);
+        let done = move || set_playing.set(false);
+        playback.set_value(Some(recorder::play(&recording, speed.get_untracked(), replay, done)));
+    };
+
+    let stop = move |_| {
+        playback.set_value(None);
+        set_playing.set(false);
+    };
+
+    view! {
+        <fieldset class="debug-section">
+            <legend>{t(Key::SessionPlayback)}</legend>
+            <input type="file" accept=".json,application/json" on:change=on_file />
+            <label>
+                {t(Key::Speed)}
+                <select on:change=move |ev| {
+                    set_speed.set(event_target_value(&ev).parse().unwrap_or(1.0));
+                }>
+                    {[0.5, 1.0, 2.0, 4.0, 10.0].into_iter().map(|option| view! {
+                        <option value=option.to_string() selected=move || speed.get() == option>
+                            {format!("{}x", option)}
+                        </option>
+                    }).collect_view()}
+                </select>
+            </label>
+            <label>
+                <input type="checkbox"
+                    prop:checked=move || to_pico.get()
+                    on:change=move |ev| set_to_pico.set(event_target_checked(&ev))
+                />
+                {t(Key::SendToPico)}
+            </label>
+            <Show
+                when=move || playing.get()
+                fallback=move || view! {
+                    <button on:click=play disabled=move || session.with(Option::is_none)>{t(Key::Play)}</button>
+                }
+            >
+                <button on:click=stop>{t(Key::Stop)}</button>
+            </Show>
+        </fieldset>
+    }
+}
+
+#[component]
+pub fn DebugPanel(
+    #[prop(into)] grid: Signal<Vec<Vec<bool>>>,
+    pico_url: &'static str,
+    #[prop(into)] corrupt_messages: Signal<u32>,
+    #[prop(into)] latency: Signal<Vec<LatencySample>>,
+    #[prop(into)] on_replay_reset: Callback<Vec<Vec<bool>>>,
+    #[prop(into)] on_replay_event: Callback<(DrawEvent, Author)>,
+) -> impl IntoView {
+    view! {
+        <details class="debug-panel">
+            <summary>{t(Key::Debug)}</summary>
+            <NetworkSimulator />
+            <p>{t(Key::CorruptMessages)} {move || corrupt_messages.get()}</p>
+            <LatencyHistogram samples=latency />
+            <CompressionReport grid=grid />
+            <SessionRecorder grid=grid pico_url=pico_url />
+            <SessionPlayer grid=grid on_reset=on_replay_reset on_event=on_replay_event />
+        </details>
+    }
+}
@@ -0,0 +1,131 @@
+// file: history.rs
+// desc: record drawing events so the canvas can be rewound to any earlier point
+
+use doodle_protocol::PixelState;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DrawEvent {
+    Pixel { x: usize, y: usize, state: PixelState },
+    Clear,
+    // Turns off every pixel of the rectangle that lies on the grid
+    ClearRect { x: usize, y: usize, width: usize, height: usize },
+}
+
+impl DrawEvent {
+    pub fn apply(&self, grid: &mut [Vec<bool>]) {
+        match *self {
+            DrawEvent::Pixel { x, y, state } => {
+                if let Some(pixel) = grid.get_mut(y).and_then(|row| row.get_mut(x)) {
+                    *pixel = state.apply(*pixel, x, y);
+                }
+            }
+            DrawEvent::Clear => {
+                for row in grid.iter_mut() {
+                    row.fill(false);
+                }
+            }
+            DrawEvent::ClearRect { x, y, width, height } => {
+                for row in grid.iter_mut().skip(y).take(height) {
+                    for pixel in row.iter_mut().skip(x).take(width) {
+                        *pixel = false;
+                    }
+                }
+            }
+        }
+    }
+}
+
+// Who made a drawing event. Other browsers are told apart by the id the Pico
+// gave them, tabs of this browser all count as local
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Author {
+    Local,
+    // 0 is the Pico itself, e.g. an idle clear
+    Remote(u8),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct DrawHistory {
+    // Grid the history starts from, e.g. a doodle loaded from a share link
+    base: Vec<Vec<bool>>,
+    events: Vec<(DrawEvent, Author)>,
+}
+
+impl DrawHistory {
+    pub fn new(base: Vec<Vec<bool>>) -> Self {
+        Self { base, events: Vec::new() }
+    }
+
+    pub fn len(&self) -> usize {
+        self.events.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+
+    pub fn push(&mut self, event: DrawEvent, author: Author) {
+        self.events.push((event, author));
+    }
+
+    /// Drop every event after `len`, used when drawing resumes from a rewound point
+    pub fn truncate(&mut self, len: usize) {
+        self.events.truncate(len);
+    }
+
+    /// Rebuild the grid as it was after the first `len` events
+    pub fn snapshot(&self, len: usize) -> Vec<Vec<bool>> {
+        let mut grid = self.base.clone();
+        for (event, _) in self.events.iter().take(len) {
+            event.apply(&mut grid);
+        }
+        grid
+    }
+
+    /// Everyone who has drawn, in order of their first event
+    pub fn authors(&self) -> Vec<Author> {
+        let mut authors = Vec::new();
+        for (_, author) in self.events.iter() {
+            if !authors.contains(author) {
+                authors.push(*author);
+            }
+        }
+        authors
+    }
+
+    /// Who inked each pixel of `snapshot(len)`. Pixels from the base grid have no author
+    pub fn owners(&self, len: usize) -> Vec<Vec<Option<Author>>> {
+        let mut owners: Vec<Vec<Option<Author>>> =
+            self.base.iter().map(|row| vec![None; row.len()]).collect();
+        // A toggle's result depends on the pixel, so follow the grid along
+        let mut grid = self.base.clone();
+        for (event, author) in self.events.iter().take(len) {
+            event.apply(&mut grid);
+            match *event {
+                DrawEvent::Pixel { x, y, .. } => {
+                    if let Some(owner) = owners.get_mut(y).and_then(|row| row.get_mut(x)) {
+                        *owner = grid[y][x].then_some(*author);
+                    }
+                }
+                DrawEvent::Clear => {
+                    for row in owners.iter_mut() {
+                        row.fill(None);
+                    }
+                }
+                DrawEvent::ClearRect { x, y, width, height } => {
+                    for row in owners.iter_mut().skip(y).take(height) {
+                        for owner in row.iter_mut().skip(x).take(width) {
+                            *owner = None;
+                        }
+                    }
+                }
+            }
+        }
+        owners
+    }
+
+    /// Undo everything one participant did, as if they had never drawn
+    pub fn remove_author(&mut self, author: Author) {
+        self.events.retain(|(_, by)| *by != author);
+    }
+}
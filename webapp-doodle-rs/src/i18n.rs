@@ -0,0 +1,410 @@
+// file: i18n.rs
+// desc: UI translations and the language picker
+
+use leptos::*;
+
+use crate::settings::local_storage;
+
+const STORAGE_KEY: &str = "doodle-rs.language";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Language {
+    English,
+    Spanish,
+}
+
+impl Language {
+    pub const ALL: [Language; 2] = [Language::English, Language::Spanish];
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            Language::English => "en",
+            Language::Spanish => "es",
+        }
+    }
+
+    // Names are shown in their own language so they can be found from any UI language
+    pub fn native_name(&self) -> &'static str {
+        match self {
+            Language::English => "English",
+            Language::Spanish => "Español",
+        }
+    }
+
+    pub fn from_code(code: &str) -> Option<Self> {
+        let primary = code.split('-').next()?.to_ascii_lowercase();
+        Self::ALL.into_iter().find(|lang| lang.code() == primary)
+    }
+}
+
+// Every user-visible string in the UI
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Key {
+    Title,
+    Intro,
+    LanguageLabel,
+    Clear,
+    Share,
+    CopyImage,
+    ImageCopied,
+    CopyFailed,
+    Qr,
+    InvertColors,
+    Spectate,
+    Timeline,
+    Resolution,
+    PixelsDrawn,
+    RoundTrip,
+    ShareFailed,
+    Connecting,
+    Connected,
+    Disconnected,
+    Offline,
+    Settings,
+    GridResolution,
+    GridCropped,
+    BrushSize,
+    IdleClear,
+    Participants,
+    You,
+    Participant,
+    UndoStrokes,
+    Stats,
+    ThisSession,
+    PastSessions,
+    NoPastSessions,
+    Sessions,
+    Started,
+    Strokes,
+    Drawings,
+    StrokesPerDrawing,
+    Pixels,
+    SessionLength,
+    Debug,
+    NetworkSimulator,
+    Latency,
+    Jitter,
+    DropRate,
+    Reset,
+    TourCanvas,
+    TourClear,
+    TourConnection,
+    TourNext,
+    TourFinish,
+    TourClose,
+    TourSkip,
+    Compression,
+    Analyze,
+    Scheme,
+    Bytes,
+    EncodeTime,
+    DecodeTime,
+    Recommended,
+    SessionRecording,
+    Record,
+    StopAndDownload,
+    SessionPlayback,
+    Speed,
+    SendToPico,
+    Play,
+    Stop,
+    CollectSamples,
+    DrawDigit,
+    SaveSample,
+    Skip,
+    SamplesCollected,
+    ResyncPico,
+    CorruptMessages,
+    Incompatible,
+    PicoStatus,
+    PixelsOn,
+    Uptime,
+    SignalStrength,
+    ConnectedBrowsers,
+    NoStatusYet,
+    EraseArea,
+    Tool,
+    Pen,
+    Eraser,
+    Ink,
+    RoundTripLatency,
+    MedianLatency,
+    PicoProcessing,
+    Unauthorized,
+    AccessTokenPrompt,
+    WifiChannel,
+    WifiJoins,
+    SocketErrors,
+    DroppedEvents,
+    SignalHistory,
+}
+
+/// Look up a string. Templates may contain `{size}` which callers fill in with `format_size`,
+/// or a participant's `{id}`
+pub fn translate(lang: Language, key: Key) -> &'static str {
+    match lang {
+        Language::English => match key {
+            Key::Title => "Doodle-RS",
+            Key::Intro => "Draw on the canvas below. Each square represents a pixel on your {size} OLED display.",
+            Key::LanguageLabel => "Language",
+            Key::Clear => "Clear",
+            Key::Share => "Share",
+            Key::CopyImage => "Copy image",
+            Key::ImageCopied => "Image copied to clipboard",
+            Key::CopyFailed => "Could not copy the image",
+            Key::Qr => "QR",
+            Key::InvertColors => "Invert colors",
+            Key::Spectate => "Spectator mode",
+            Key::Timeline => "History",
+            Key::Resolution => "Resolution: {size} pixels",
+            Key::PixelsDrawn => "Pixels drawn: ",
+            Key::RoundTrip => "Round trip to the Pico: ",
+            Key::ShareFailed => "Could not create a share link",
+            Key::Connecting => "Connecting...",
+            Key::Connected => "Connected",
+            Key::Disconnected => "Disconnected",
+            Key::Offline => "Offline",
+            Key::Settings => "Settings",
+            Key::GridResolution => "Grid resolution",
+            Key::GridCropped => "The Pico only shows the top-left {size} of this grid.",
+            Key::BrushSize => "Brush size",
+            Key::IdleClear => "Clear the Pico after idle minutes (0 = never)",
+            Key::Participants => "Participants",
+            Key::You => "You",
+            Key::Participant => "Participant {id}",
+            Key::UndoStrokes => "Undo strokes",
+            Key::Stats => "Statistics",
+            Key::ThisSession => "This session",
+            Key::PastSessions => "Past sessions",
+            Key::NoPastSessions => "No past sessions yet.",
+            Key::Sessions => "Sessions",
+            Key::Started => "Started",
+            Key::Strokes => "Strokes",
+            Key::Drawings => "Drawings",
+            Key::StrokesPerDrawing => "Strokes per drawing",
+            Key::Pixels => "Pixels",
+            Key::SessionLength => "Session length",
+            Key::Debug => "Debug",
+            Key::NetworkSimulator => "Network simulator",
+            Key::Latency => "Latency (ms) ",
+            Key::Jitter => "Jitter (ms) ",
+            Key::DropRate => "Drop rate (%) ",
+            Key::Reset => "Reset",
+            Key::TourCanvas => "Draw here with the mouse. Every square is one pixel on the Pico's OLED.",
+            Key::TourClear => "Clear wipes the canvas here and on the Pico.",
+            Key::TourConnection => "This shows whether the Pico is connected. Drawing works offline too, it just isn't mirrored.",
+            Key::TourNext => "Next",
+            Key::TourFinish => "Got it",
+            Key::TourClose => "Close",
+            Key::TourSkip => "Don't show again",
+            Key::Compression => "Canvas compression",
+            Key::Analyze => "Analyze canvas",
+            Key::Scheme => "Scheme",
+            Key::Bytes => "Bytes",
+            Key::EncodeTime => "Encode (µs)",
+            Key::DecodeTime => "Decode (µs)",
+            Key::Recommended => "Recommended: ",
+            Key::SessionRecording => "Session recording",
+            Key::Record => "Record",
+            Key::StopAndDownload => "Stop and download",
+            Key::SessionPlayback => "Session playback",
+            Key::Speed => "Speed: ",
+            Key::SendToPico => "Send to Pico",
+            Key::Play => "Play",
+            Key::Stop => "Stop",
+            Key::CollectSamples => "Collect samples",
+            Key::DrawDigit => "Draw a {digit}",
+            Key::SaveSample => "Save sample",
+            Key::Skip => "Skip",
+            Key::SamplesCollected => "Samples: ",
+            Key::ResyncPico => "Resync Pico",
+            Key::CorruptMessages => "Corrupt messages: ",
+            Key::Incompatible => "Update needed",
+            Key::PicoStatus => "Pico status",
+            Key::PixelsOn => "Pixels on",
+            Key::Uptime => "Uptime",
+            Key::SignalStrength => "WiFi signal",
+            Key::ConnectedBrowsers => "Connected browsers",
+            Key::NoStatusYet => "No status from the Pico yet",
+            Key::EraseArea => "Erase area",
+            Key::Tool => "Tool",
+            Key::Pen => "Pen",
+            Key::Eraser => "Eraser",
+            Key::Ink => "Ink",
+            Key::RoundTripLatency => "Round trip latency",
+            Key::MedianLatency => "Median (ms): ",
+            Key::PicoProcessing => "Pico processing (µs): ",
+            Key::Unauthorized => "Token needed",
+            Key::AccessTokenPrompt => "Access token for this Pico",
+            Key::WifiChannel => "WiFi channel",
+            Key::WifiJoins => "WiFi joins",
+            Key::SocketErrors => "Socket errors",
+            Key::DroppedEvents => "Dropped drawing events",
+            Key::SignalHistory => "Signal over time",
+        },
+        Language::Spanish => match key {
+            Key::Title => "Doodle-RS",
+            Key::Intro => "Dibuja en el lienzo. Cada cuadro representa un píxel de tu pantalla OLED de {size}.",
+            Key::LanguageLabel => "Idioma",
+            Key::Clear => "Borrar",
+            Key::Share => "Compartir",
+            Key::CopyImage => "Copiar imagen",
+            Key::ImageCopied => "Imagen copiada al portapapeles",
+            Key::CopyFailed => "No se pudo copiar la imagen",
+            Key::Qr => "QR",
+            Key::InvertColors => "Invertir colores",
+            Key::Spectate => "Modo espectador",
+            Key::Timeline => "Historial",
+            Key::Resolution => "Resolución: {size} píxeles",
+            Key::PixelsDrawn => "Píxeles dibujados: ",
+            Key::RoundTrip => "Ida y vuelta a la Pico: ",
+            Key::ShareFailed => "No se pudo crear el enlace para compartir",
+            Key::Connecting => "Conectando...",
+            Key::Connected => "Conectado",
+            Key::Disconnected => "Desconectado",
+            Key::Offline => "Sin conexión",
+            Key::Settings => "Ajustes",
+            Key::GridResolution => "Resolución de la cuadrícula",
+            Key::GridCropped => "La Pico solo muestra los {size} superiores izquierdos de esta cuadrícula.",
+            Key::BrushSize => "Tamaño del pincel",
+            Key::IdleClear => "Borrar la Pico tras minutos de inactividad (0 = nunca)",
+            Key::Participants => "Participantes",
+            Key::You => "Tú",
+            Key::Participant => "Participante {id}",
+            Key::UndoStrokes => "Deshacer trazos",
+            Key::Stats => "Estadísticas",
+            Key::ThisSession => "Esta sesión",
+            Key::PastSessions => "Sesiones anteriores",
+            Key::NoPastSessions => "Aún no hay sesiones anteriores.",
+            Key::Sessions => "Sesiones",
+            Key::Started => "Inicio",
+            Key::Strokes => "Trazos",
+            Key::Drawings => "Dibujos",
+            Key::StrokesPerDrawing => "Trazos por dibujo",
+            Key::Pixels => "Píxeles",
+            Key::SessionLength => "Duración de la sesión",
+            Key::Debug => "Depuración",
+            Key::NetworkSimulator => "Simulador de red",
+            Key::Latency => "Latencia (ms) ",
+            Key::Jitter => "Variación (ms) ",
+            Key::DropRate => "Pérdida (%) ",
+            Key::Reset => "Restablecer",
+            Key::TourCanvas => "Dibuja aquí con el ratón. Cada cuadro es un píxel de la pantalla OLED de la Pico.",
+            Key::TourClear => "Borrar limpia el lienzo aquí y en la Pico.",
+            Key::TourConnection => "Aquí ves si la Pico está conectada. También puedes dibujar sin conexión, solo que no se refleja.",
+            Key::TourNext => "Siguiente",
+            Key::TourFinish => "Entendido",
+            Key::TourClose => "Cerrar",
+            Key::TourSkip => "No volver a mostrar",
+            Key::Compression => "Compresión del lienzo",
+            Key::Analyze => "Analizar lienzo",
+            Key::Scheme => "Esquema",
+            Key::Bytes => "Bytes",
+            Key::EncodeTime => "Codificar (µs)",
+            Key::DecodeTime => "Decodificar (µs)",
+            Key::Recommended => "Recomendado: ",
+            Key::SessionRecording => "Grabación de sesión",
+            Key::Record => "Grabar",
+            Key::StopAndDownload => "Detener y descargar",
+            Key::SessionPlayback => "Reproducción de sesión",
+            Key::Speed => "Velocidad: ",
+            Key::SendToPico => "Enviar a la Pico",
+            Key::Play => "Reproducir",
+            Key::Stop => "Detener",
+            Key::CollectSamples => "Recoger muestras",
+            Key::DrawDigit => "Dibuja un {digit}",
+            Key::SaveSample => "Guardar muestra",
+            Key::Skip => "Saltar",
+            Key::SamplesCollected => "Muestras: ",
+            Key::ResyncPico => "Resincronizar la Pico",
+            Key::CorruptMessages => "Mensajes dañados: ",
+            Key::Incompatible => "Requiere actualización",
+            Key::PicoStatus => "Estado de la Pico",
+            Key::PixelsOn => "Píxeles encendidos",
+            Key::Uptime => "Tiempo encendida",
+            Key::SignalStrength => "Señal WiFi",
+            Key::ConnectedBrowsers => "Navegadores conectados",
+            Key::NoStatusYet => "La Pico aún no ha enviado su estado",
+            Key::EraseArea => "Borrar zona",
+            Key::Tool => "Herramienta",
+            Key::Pen => "Lápiz",
+            Key::Eraser => "Goma",
+            Key::Ink => "Tinta",
+            Key::RoundTripLatency => "Latencia de ida y vuelta",
+            Key::MedianLatency => "Mediana (ms): ",
+            Key::PicoProcessing => "Procesamiento en la Pico (µs): ",
+            Key::Unauthorized => "Se necesita token",
+            Key::AccessTokenPrompt => "Token de acceso de esta Pico",
+            Key::WifiChannel => "Canal WiFi",
+            Key::WifiJoins => "Conexiones WiFi",
+            Key::SocketErrors => "Errores de socket",
+            Key::DroppedEvents => "Eventos de dibujo perdidos",
+            Key::SignalHistory => "Señal en el tiempo",
+        },
+    }
+}
+
+pub fn format_size(template: &str, width: usize, height: usize) -> String {
+    template.replace("{size}", &format!("{}x{}", width, height))
+}
+
+// Stored choice first, then the browser's preferred language
+fn initial_language() -> Language {
+    let stored = local_storage().and_then(|storage| storage.get_item(STORAGE_KEY).ok()?);
+    let browser = web_sys::window().and_then(|window| window.navigator().language());
+
+    stored
+        .or(browser)
+        .and_then(|code| Language::from_code(&code))
+        .unwrap_or(Language::English)
+}
+
+/// Make the current language available to every component below the caller
+pub fn provide_i18n() {
+    let (language, set_language) = create_signal(initial_language());
+
+    create_effect(move |_| {
+        let code = language.get().code();
+        if let Some(storage) = local_storage() {
+            let _ = storage.set_item(STORAGE_KEY, code);
+        }
+    });
+
+    provide_context(language);
+    provide_context(set_language);
+}
+
+pub fn use_language() -> ReadSignal<Language> {
+    expect_context::<ReadSignal<Language>>()
+}
+
+/// Reactive translation for use directly in views
+pub fn t(key: Key) -> impl Fn() -> &'static str + Copy + 'static {
+    let language = use_language();
+    move || translate(language.get(), key)
+}
+
+#[component]
+pub fn LanguagePicker() -> impl IntoView {
+    let language = use_language();
+    let set_language = expect_context::<WriteSignal<Language>>();
+
+    let on_change = move |ev: ev::Event| {
+        if let Some(lang) = Language::from_code(&event_target_value(&ev)) {
+            set_language.set(lang);
+        }
+    };
+
+    view! {
+        <label class="language-picker">
+            {t(Key::LanguageLabel)} " "
+            <select on:change=on_change>
+                {Language::ALL.into_iter().map(|lang| view! {
+                    <option value=lang.code() prop:selected=move || language.get() == lang>
+                        {lang.native_name()}
+                    </option>
+                }).collect_view()}
+            </select>
+        </label>
+    }
+}
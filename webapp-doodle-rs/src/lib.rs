@@ -2,42 +2,136 @@
 // desc: serve webapp with configuration
 
 pub mod web;
+pub mod share;
+pub mod qr;
+pub mod transport;
+pub mod debug;
+pub mod i18n;
+pub mod history;
+pub mod settings;
+pub mod clipboard;
+pub mod brush;
+pub mod stats;
+pub mod tabs;
+pub mod compression;
+pub mod tour;
+pub mod recorder;
+pub mod db;
+pub mod collect;
+pub mod pico_status;
 
 use leptos::*;
 use wasm_bindgen::prelude::*;
 
 // Configuration struct
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub struct AppConfig {
     pub pico_url: &'static str,
-    pub pixel_grid_size: usize,
-    pub canvas_size: f64,
+    pub grid_width: usize,
+    pub grid_height: usize,
+    pub canvas_width: f64,
+    pub canvas_height: f64,
     pub pixel_size: f64,
+    // Draw without ever connecting to the Pico
+    pub offline: bool,
+    // The grid was picked in the URL and wins over the stored setting
+    pub grid_from_url: bool,
 }
 
 impl AppConfig {
-    pub fn new(pico_url: &'static str, pixel_grid_size: usize, canvas_size: f64) -> Self {
+    // Drawable area below the title bar on the 128x64 OLED
+    pub const OLED_GRID: (usize, usize) = (128, 48);
+
+    /// `canvas_size` is the length of the longer canvas side, pixels stay square
+    pub fn new(pico_url: &'static str, grid_width: usize, grid_height: usize, canvas_size: f64) -> Self {
+        let pixel_size = canvas_size / grid_width.max(grid_height) as f64;
         Self {
             pico_url,
-            pixel_grid_size,
-            canvas_size,
-            pixel_size: canvas_size / pixel_grid_size as f64,
+            grid_width,
+            grid_height,
+            canvas_width: pixel_size * grid_width as f64,
+            canvas_height: pixel_size * grid_height as f64,
+            pixel_size,
+            offline: false,
+            grid_from_url: false,
+        }
+    }
+
+    /// Same canvas footprint with a different grid resolution
+    pub fn with_grid(&self, grid_width: usize, grid_height: usize) -> Self {
+        Self {
+            offline: self.offline,
+            grid_from_url: self.grid_from_url,
+            ..Self::new(self.pico_url, grid_width, grid_height, self.canvas_width.max(self.canvas_height))
         }
     }
+
+    /// Apply `?pico=<host>&grid=<preset or WxH>&offline=1` style options from the
+    /// page URL, so a kiosk setup can be bookmarked. Unknown or malformed values are ignored
+    pub fn with_query(mut self, query: &str) -> Self {
+        let Ok(params) = web_sys::UrlSearchParams::new_with_str(query) else {
+            return self;
+        };
+
+        if let Some(pico) = params.get("pico").filter(|pico| !pico.trim().is_empty()) {
+            // Lives for the whole page anyway
+            self.pico_url = Box::leak(pico.trim().to_string().into_boxed_str());
+        }
+
+        let grid = params.get("grid").and_then(|grid| {
+            settings::GridPreset::from_code(&grid).or_else(|| {
+                let (width, height) = grid.split_once('x')?;
+                settings::GridPreset::from_dimensions(width.parse().ok()?, height.parse().ok()?)
+            })
+        });
+        if let Some(grid) = grid {
+            let (width, height) = grid.dimensions();
+            self = self.with_grid(width, height);
+            self.grid_from_url = true;
+        }
+
+        if let Some(offline) = params.get("offline") {
+            self.offline = matches!(offline.as_str(), "" | "1" | "true");
+        }
+
+        self
+    }
+
+    pub fn empty_grid(&self) -> Vec<Vec<bool>> {
+        vec![vec![false; self.grid_width]; self.grid_height]
+    }
 }
 
 impl Default for AppConfig {
     fn default() -> Self {
-        Self::new("192.168.68.100", 48, 480.0)
+        Self::new("192.168.68.100", 48, 48, 480.0)
     }
 }
 
+/// The page's host if the Pico served it. The firmware serves the app on port 80
+/// over plain HTTP, which the dev server and the hosted copies don't
+fn served_by_pico(location: &web_sys::Location) -> Option<String> {
+    let host = location.hostname().ok()?;
+    let plain_http = location.protocol().ok()? == "http:" && location.port().ok()?.is_empty();
+    (plain_http && !matches!(host.as_str(), "localhost" | "127.0.0.1" | "")).then_some(host)
+}
+
 #[wasm_bindgen(start)]
 pub fn main() {
     console_error_panic_hook::set_once();
     console_log::init_with_level(log::Level::Debug).ok();
     
-    let config = AppConfig::default();
+    let location = web_sys::window().map(|window| window.location());
+    let query = location
+        .as_ref()
+        .and_then(|location| location.search().ok())
+        .unwrap_or_default();
+    let mut config = AppConfig::default();
+    if let Some(host) = location.as_ref().and_then(served_by_pico) {
+        config.pico_url = Box::leak(host.into_boxed_str());
+    }
+    let config = config.with_query(&query);
+    log::info!("Starting with {:?}", config);
     
     leptos::mount_to_body(move || view! {
         <web::App config=config />
@@ -0,0 +1,84 @@
+// file: pico_status.rs
+// desc: what the Pico reports about itself in its Status messages
+
+use leptos::*;
+
+use doodle_protocol::Status;
+
+use crate::i18n::{t, Key};
+
+// Status samples of the signal kept for the chart, ten minutes of them
+pub const RSSI_SAMPLES: usize = 120;
+
+// The chart's range in dBm, anything outside is drawn at the edge
+const RSSI_RANGE: (i8, i8) = (-90, -30);
+const CHART_SIZE: (usize, usize) = (240, 40);
+
+fn format_uptime(secs: u32) -> String {
+    format!("{}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60)
+}
+
+// The signal over the last samples as a line, stronger higher up
+#[component]
+fn RssiChart(#[prop(into)] history: Signal<Vec<i8>>) -> impl IntoView {
+    let (width, height) = CHART_SIZE;
+    let points = move || {
+        history.with(|history| {
+            let step = width as f64 / (RSSI_SAMPLES - 1) as f64;
+            history
+                .iter()
+                .enumerate()
+                .map(|(i, rssi)| {
+                    let (weakest, strongest) = RSSI_RANGE;
+                    let strength = (rssi.clamp(&weakest, &strongest) - weakest) as f64 / (strongest - weakest) as f64;
+                    format!("{:.1},{:.1}", i as f64 * step, height as f64 * (1.0 - strength))
+                })
+                .collect::<Vec<_>>()
+                .join(" ")
+        })
+    };
+
+    view! {
+        <Show when=move || history.with(|history| history.len() > 1)>
+            <p>{t(Key::SignalHistory)}</p>
+            <svg class="rssi-chart" width=width height=height viewBox=format!("0 0 {} {}", width, height)>
+                <polyline points=points fill="none" stroke="currentColor" stroke-width="1.5" />
+            </svg>
+        </Show>
+    }
+}
+
+#[component]
+pub fn PicoStatusPanel(
+    // Latest Status from the Pico, None until one arrives on this connection
+    #[prop(into)] status: Signal<Option<Status>>,
+    // Signal of the joined network from the recent Status messages, oldest first
+    #[prop(into)] rssi_history: Signal<Vec<i8>>,
+) -> impl IntoView {
+    view! {
+        <details class="status-panel">
+            <summary>{t(Key::PicoStatus)}</summary>
+            {move || match status.get() {
+                None => view! { <p>{t(Key::NoStatusYet)}</p> }.into_view(),
+                Some(status) => view! {
+                    <p>{t(Key::PixelsOn)} ": " {status.pixels_on}</p>
+                    <p>{t(Key::Uptime)} ": " {format_uptime(status.uptime_secs)}</p>
+                    <p>
+                        {t(Key::SignalStrength)} ": "
+                        {if status.rssi == 0 { "?".to_string() } else { format!("{} dBm", status.rssi) }}
+                    </p>
+                    <p>
+                        {t(Key::WifiChannel)} ": "
+                        {if status.channel == 0 { "?".to_string() } else { status.channel.to_string() }}
+                    </p>
+                    <p>{t(Key::WifiJoins)} ": " {status.joins}</p>
+                    <p>{t(Key::ConnectedBrowsers)} ": " {status.clients}</p>
+                    <p>{t(Key::CorruptMessages)} {status.corrupt_messages}</p>
+                    <p>{t(Key::SocketErrors)} ": " {status.socket_errors}</p>
+                    <p>{t(Key::DroppedEvents)} ": " {status.dropped_events}</p>
+                }.into_view(),
+            }}
+            <RssiChart history=rssi_history />
+        </details>
+    }
+}
@@ -0,0 +1,75 @@
+// file: qr.rs
+// desc: QR code component so phones can open the app or a shared doodle by scanning
+
+use leptos::*;
+use qrcodegen::{QrCode, QrCodeEcc};
+use wasm_bindgen::JsCast;
+use web_sys::{CanvasRenderingContext2d, HtmlCanvasElement};
+
+const MODULE_SIZE: f64 = 4.0;
+const QUIET_ZONE: i32 = 4;
+
+/// URL of the running webapp without any share fragment
+pub fn app_url() -> Option<String> {
+    let location = web_sys::window()?.location();
+    Some(format!("{}{}", location.origin().ok()?, location.pathname().ok()?))
+}
+
+fn draw_qr_code(canvas: &HtmlCanvasElement, qr: &QrCode) -> Option<()> {
+    let ctx = canvas
+        .get_context("2d")
+        .ok()??
+        .dyn_into::<CanvasRenderingContext2d>()
+        .ok()?;
+
+    let modules = qr.size() + 2 * QUIET_ZONE;
+    let side = modules as f64 * MODULE_SIZE;
+    canvas.set_width(side as u32);
+    canvas.set_height(side as u32);
+
+    ctx.set_fill_style_str("#ffffff");
+    ctx.fill_rect(0.0, 0.0, side, side);
+
+    ctx.set_fill_style_str("#000000");
+    for y in 0..qr.size() {
+        for x in 0..qr.size() {
+            if qr.get_module(x, y) {
+                ctx.fill_rect(
+                    (x + QUIET_ZONE) as f64 * MODULE_SIZE,
+                    (y + QUIET_ZONE) as f64 * MODULE_SIZE,
+                    MODULE_SIZE,
+                    MODULE_SIZE,
+                );
+            }
+        }
+    }
+    Some(())
+}
+
+#[component]
+pub fn QrCodeView(#[prop(into)] text: Signal<String>) -> impl IntoView {
+    let canvas_ref = create_node_ref::<leptos::html::Canvas>();
+
+    create_effect(move |_| {
+        let text = text.get();
+        let Some(canvas) = canvas_ref.get() else {
+            return;
+        };
+
+        match QrCode::encode_text(&text, QrCodeEcc::Medium) {
+            Ok(qr) => {
+                if draw_qr_code(canvas.unchecked_ref::<HtmlCanvasElement>(), &qr).is_none() {
+                    log::error!("Failed to draw QR code");
+                }
+            }
+            Err(e) => log::error!("Failed to encode QR code: {:?}", e),
+        }
+    });
+
+    view! {
+        <div class="qr-code">
+            <canvas _ref=canvas_ref />
+            <p class="qr-caption">{move || text.get()}</p>
+        </div>
+    }
+}
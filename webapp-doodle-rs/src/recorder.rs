@@ -0,0 +1,315 @@
+// file: recorder.rs
+// desc: record drawing events and Pico traffic into a JSON session file for bug reports,
+// and play such files back
+
+use std::cell::RefCell;
+use std::time::Duration;
+
+use leptos::{set_timeout_with_handle, TimeoutHandle};
+
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::JsCast;
+
+use doodle_protocol::PixelState;
+
+use crate::history::{Author, DrawEvent};
+use crate::share;
+
+// Bumped whenever the file layout changes
+const FORMAT_VERSION: u32 = 2;
+
+thread_local! {
+    static RECORDING: RefCell<Option<Recording>> = const { RefCell::new(None) };
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Recorded {
+    Draw(DrawEvent, Author),
+    Sent(Traffic),
+    Received(Traffic),
+}
+
+// A WebSocket message as it went over the wire
+#[derive(Clone, Debug, PartialEq)]
+pub enum Traffic {
+    Text(String),
+    Binary(Vec<u8>),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Recording {
+    pub started_ms: f64,
+    pub pico_url: String,
+    // Canvas when recording started, events are replayed on top of it
+    pub grid: Vec<Vec<bool>>,
+    // (ms since started, what happened)
+    pub events: Vec<(f64, Recorded)>,
+}
+
+impl Recording {
+    fn to_json(&self) -> Result<String, JsValue> {
+        let session = js_sys::Object::new();
+        js_sys::Reflect::set(&session, &"version".into(), &FORMAT_VERSION.into())?;
+        js_sys::Reflect::set(&session, &"started".into(), &self.started_ms.into())?;
+        js_sys::Reflect::set(&session, &"pico".into(), &self.pico_url.as_str().into())?;
+        js_sys::Reflect::set(&session, &"width".into(), &self.width().into())?;
+        js_sys::Reflect::set(&session, &"height".into(), &self.height().into())?;
+        js_sys::Reflect::set(&session, &"canvas".into(), &share::encode_grid(&self.grid).into())?;
+
+        let events = js_sys::Array::new();
+        for (at, recorded) in self.events.iter() {
+            events.push(&event_record(*at, recorded)?);
+        }
+        js_sys::Reflect::set(&session, &"events".into(), &events)?;
+
+        js_sys::JSON::stringify_with_replacer_and_space(&session, &JsValue::NULL, &2.into())?
+            .as_string()
+            .ok_or_else(|| "session did not serialize to a string".into())
+    }
+
+    /// Parse a file written by `download`
+    pub fn from_json(json: &str) -> Option<Self> {
+        let session = js_sys::JSON::parse(json).ok()?;
+        let field = |name: &str| js_sys::Reflect::get(&session, &name.into()).ok();
+
+        let version = field("version")?.as_f64()? as u32;
+        // Version 1 stored pixel states as bools, which still read fine
+        if !(1..=FORMAT_VERSION).contains(&version) {
+            log::warn!("Unsupported session file version {}", version);
+            return None;
+        }
+        let width = field("width")?.as_f64()? as usize;
+        let height = field("height")?.as_f64()? as usize;
+        let grid = share::decode_grid(&field("canvas")?.as_string()?, width, height)?;
+
+        let events: js_sys::Array = field("events")?.dyn_into().ok()?;
+        let events = events
+            .iter()
+            .map(|record| parse_event(&record))
+            .collect::<Option<Vec<_>>>()?;
+
+        Some(Self {
+            started_ms: field("started")?.as_f64()?,
+            pico_url: field("pico")?.as_string()?,
+            grid,
+            events,
+        })
+    }
+
+    pub fn width(&self) -> usize {
+        self.grid.first().map_or(0, |row| row.len())
+    }
+
+    pub fn height(&self) -> usize {
+        self.grid.len()
+    }
+}
+
+fn author_value(author: &Author) -> JsValue {
+    match author {
+        Author::Local => "local".into(),
+        Author::Remote(id) => (*id).into(),
+    }
+}
+
+fn parse_author(value: &JsValue) -> Option<Author> {
+    match value.as_string().as_deref() {
+        Some("local") => Some(Author::Local),
+        Some(_) => None,
+        None => Some(Author::Remote(value.as_f64()? as u8)),
+    }
+}
+
+fn parse_event(record: &JsValue) -> Option<(f64, Recorded)> {
+    let field = |name: &str| js_sys::Reflect::get(record, &name.into()).ok();
+    let number = |name: &str| field(name)?.as_f64();
+
+    let traffic = || {
+        if let Some(text) = field("text")?.as_string() {
+            return Some(Traffic::Text(text));
+        }
+        let bytes: js_sys::Array = field("binary")?.dyn_into().ok()?;
+        let bytes = bytes.iter().map(|byte| Some(byte.as_f64()? as u8)).collect::<Option<_>>()?;
+        Some(Traffic::Binary(bytes))
+    };
+
+    let recorded = match field("kind")?.as_string()?.as_str() {
+        "pixel" => Recorded::Draw(
+            DrawEvent::Pixel {
+                x: number("x")? as usize,
+                y: number("y")? as usize,
+                state: match field("state")?.as_bool() {
+                    Some(on) => PixelState::from(on),
+                    None => PixelState::from_byte(number("state")? as u8)?,
+                },
+            },
+            parse_author(&field("author")?)?,
+        ),
+        "clear" => Recorded::Draw(DrawEvent::Clear, parse_author(&field("author")?)?),
+        "clear_rect" => Recorded::Draw(
+            DrawEvent::ClearRect {
+                x: number("x")? as usize,
+                y: number("y")? as usize,
+                width: number("width")? as usize,
+                height: number("height")? as usize,
+            },
+            parse_author(&field("author")?)?,
+        ),
+        "sent" => Recorded::Sent(traffic()?),
+        "received" => Recorded::Received(traffic()?),
+        _ => return None,
+    };
+    Some((number("t")?, recorded))
+}
+
+fn event_record(at: f64, recorded: &Recorded) -> Result<js_sys::Object, JsValue> {
+    let record = js_sys::Object::new();
+    let set = |key: &str, value: JsValue| js_sys::Reflect::set(&record, &key.into(), &value);
+
+    set("t", at.into())?;
+    let traffic = match recorded {
+        Recorded::Draw(DrawEvent::Pixel { x, y, state }, author) => {
+            set("kind", "pixel".into())?;
+            set("x", (*x).into())?;
+            set("y", (*y).into())?;
+            set("state", state.to_byte().into())?;
+            set("author", author_value(author))?;
+            None
+        }
+        Recorded::Draw(DrawEvent::Clear, author) => {
+            set("kind", "clear".into())?;
+            set("author", author_value(author))?;
+            None
+        }
+        Recorded::Draw(DrawEvent::ClearRect { x, y, width, height }, author) => {
+            set("kind", "clear_rect".into())?;
+            set("x", (*x).into())?;
+            set("y", (*y).into())?;
+            set("width", (*width).into())?;
+            set("height", (*height).into())?;
+            set("author", author_value(author))?;
+            None
+        }
+        Recorded::Sent(traffic) => {
+            set("kind", "sent".into())?;
+            Some(traffic)
+        }
+        Recorded::Received(traffic) => {
+            set("kind", "received".into())?;
+            Some(traffic)
+        }
+    };
+    match traffic {
+        Some(Traffic::Text(text)) => {
+            set("text", text.as_str().into())?;
+        }
+        // A plain array, typed arrays stringify as objects keyed by index
+        Some(Traffic::Binary(bytes)) => {
+            let bytes: js_sys::Array = bytes.iter().map(|&byte| JsValue::from(byte)).collect();
+            set("binary", bytes.into())?;
+        }
+        None => {}
+    }
+    Ok(record)
+}
+
+pub fn is_recording() -> bool {
+    RECORDING.with(|recording| recording.borrow().is_some())
+}
+
+/// Start a fresh recording from `grid`, dropping any unfinished one
+pub fn start(pico_url: &str, grid: Vec<Vec<bool>>) {
+    log::info!("Session recording started");
+    let recording = Recording {
+        started_ms: js_sys::Date::now(),
+        pico_url: pico_url.to_string(),
+        grid,
+        events: Vec::new(),
+    };
+    RECORDING.with(|current| *current.borrow_mut() = Some(recording));
+}
+
+pub fn stop() -> Option<Recording> {
+    let recording = RECORDING.with(|current| current.borrow_mut().take());
+    if let Some(recording) = recording.as_ref() {
+        log::info!("Session recording stopped after {} events", recording.events.len());
+    }
+    recording
+}
+
+/// Add to the recording in progress, if any
+pub fn record(recorded: Recorded) {
+    RECORDING.with(|current| {
+        if let Some(recording) = current.borrow_mut().as_mut() {
+            let at = js_sys::Date::now() - recording.started_ms;
+            recording.events.push((at, recorded));
+        }
+    });
+}
+
+/// Offer `recording` to the user as a JSON file
+pub fn download(recording: &Recording) -> Result<(), JsValue> {
+    let json = recording.to_json()?;
+    let document = web_sys::window().ok_or("no window")?.document().ok_or("no document")?;
+
+    let options = web_sys::BlobPropertyBag::new();
+    options.set_type("application/json");
+    let parts = js_sys::Array::of1(&json.into());
+    let blob = web_sys::Blob::new_with_str_sequence_and_options(&parts, &options)?;
+    let url = web_sys::Url::create_object_url_with_blob(&blob)?;
+
+    let link: web_sys::HtmlAnchorElement = document.create_element("a")?.dyn_into()?;
+    link.set_href(&url);
+    link.set_download(&format!("doodle-session-{}.json", recording.started_ms as u64));
+    link.click();
+
+    web_sys::Url::revoke_object_url(&url)
+}
+
+/// A playback in progress, cancelled when stopped or dropped
+pub struct Playback {
+    timeouts: Vec<TimeoutHandle>,
+}
+
+impl Playback {
+    pub fn stop(&mut self) {
+        for timeout in self.timeouts.drain(..) {
+            timeout.clear();
+        }
+    }
+}
+
+impl Drop for Playback {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+/// Replay the drawing events of `recording`, `speed` times as fast as they were
+/// recorded. The recorded traffic is left out, the Pico answers live traffic itself
+pub fn play(
+    recording: &Recording,
+    speed: f64,
+    on_event: impl Fn(DrawEvent, Author) + Clone + 'static,
+    on_done: impl FnOnce() + 'static,
+) -> Playback {
+    let speed = speed.max(0.01);
+    let delay = |at: f64| Duration::from_millis((at / speed).max(0.0) as u64);
+
+    let mut timeouts = Vec::new();
+    for (at, recorded) in recording.events.iter() {
+        if let Recorded::Draw(event, author) = *recorded {
+            let on_event = on_event.clone();
+            if let Ok(timeout) = set_timeout_with_handle(move || on_event(event, author), delay(*at)) {
+                timeouts.push(timeout);
+            }
+        }
+    }
+
+    let end = recording.events.last().map_or(0.0, |(at, _)| *at);
+    if let Ok(timeout) = set_timeout_with_handle(on_done, delay(end)) {
+        timeouts.push(timeout);
+    }
+
+    Playback { timeouts }
+}
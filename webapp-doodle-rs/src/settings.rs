@@ -0,0 +1,191 @@
+// file: settings.rs
+// desc: user settings persisted in localStorage and the settings panel
+
+use leptos::*;
+
+use crate::brush::BRUSH_SIZES;
+use crate::i18n::{t, Key};
+use crate::transport;
+
+const GRID_STORAGE_KEY: &str = "doodle-rs.grid";
+const BRUSH_STORAGE_KEY: &str = "doodle-rs.brush";
+
+pub fn local_storage() -> Option<web_sys::Storage> {
+    web_sys::window()?.local_storage().ok()?
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GridPreset {
+    Grid28,
+    Grid48,
+    Grid64,
+    Oled,
+}
+
+impl GridPreset {
+    pub const ALL: [GridPreset; 4] = [
+        GridPreset::Grid28,
+        GridPreset::Grid48,
+        GridPreset::Grid64,
+        GridPreset::Oled,
+    ];
+
+    pub fn dimensions(&self) -> (usize, usize) {
+        match self {
+            GridPreset::Grid28 => (28, 28),
+            GridPreset::Grid48 => (48, 48),
+            GridPreset::Grid64 => (64, 64),
+            GridPreset::Oled => crate::AppConfig::OLED_GRID,
+        }
+    }
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            GridPreset::Grid28 => "28",
+            GridPreset::Grid48 => "48",
+            GridPreset::Grid64 => "64",
+            GridPreset::Oled => "oled",
+        }
+    }
+
+    pub fn from_code(code: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|preset| preset.code() == code)
+    }
+
+    pub fn from_dimensions(width: usize, height: usize) -> Option<Self> {
+        Self::ALL.into_iter().find(|preset| preset.dimensions() == (width, height))
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Settings {
+    pub grid: GridPreset,
+    pub brush_size: usize,
+}
+
+impl Settings {
+    // Anything missing from storage falls back to the startup config, and a grid
+    // picked in the URL wins over the stored one
+    fn load(config: &crate::AppConfig) -> Self {
+        let stored = |key: &str| local_storage().and_then(|storage| storage.get_item(key).ok()?);
+        let configured = GridPreset::from_dimensions(config.grid_width, config.grid_height);
+
+        Self {
+            grid: configured
+                .filter(|_| config.grid_from_url)
+                .or_else(|| stored(GRID_STORAGE_KEY).and_then(|code| GridPreset::from_code(&code)))
+                .or(configured)
+                .unwrap_or(GridPreset::Grid48),
+            brush_size: stored(BRUSH_STORAGE_KEY)
+                .and_then(|size| size.parse().ok())
+                .filter(|size| BRUSH_SIZES.contains(size))
+                .unwrap_or(1),
+        }
+    }
+
+    fn save(&self) {
+        if let Some(storage) = local_storage() {
+            let _ = storage.set_item(GRID_STORAGE_KEY, self.grid.code());
+            let _ = storage.set_item(BRUSH_STORAGE_KEY, &self.brush_size.to_string());
+        }
+    }
+}
+
+// Settings that live on the Pico rather than in the browser. None until it reports them
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct DeviceSettings {
+    pub idle_clear_minutes: Option<u32>,
+}
+
+/// Load the stored settings and make them available to every component below the caller
+pub fn provide_settings(config: &crate::AppConfig) -> RwSignal<Settings> {
+    let settings = create_rw_signal(Settings::load(config));
+
+    // Only save changes, so settings from the URL don't replace the stored ones
+    create_effect(move |loaded: Option<()>| {
+        let settings = settings.get();
+        if loaded.is_some() {
+            settings.save();
+        }
+    });
+
+    provide_context(settings);
+    provide_context(create_rw_signal(DeviceSettings::default()));
+    settings
+}
+
+pub fn use_settings() -> RwSignal<Settings> {
+    expect_context::<RwSignal<Settings>>()
+}
+
+pub fn use_device_settings() -> RwSignal<DeviceSettings> {
+    expect_context::<RwSignal<DeviceSettings>>()
+}
+
+#[component]
+pub fn SettingsPanel() -> impl IntoView {
+    let settings = use_settings();
+    let device_settings = use_device_settings();
+
+    let on_grid_change = move |ev: ev::Event| {
+        if let Some(grid) = GridPreset::from_code(&event_target_value(&ev)) {
+            settings.update(|settings| settings.grid = grid);
+        }
+    };
+
+    let on_brush_change = move |ev: ev::Event| {
+        if let Ok(size) = event_target_value(&ev).parse() {
+            settings.update(|settings| settings.brush_size = size);
+        }
+    };
+
+    // The Pico confirms the value it settled on, which updates the field
+    let on_idle_change = move |ev: ev::Event| {
+        if let Ok(minutes) = event_target_value(&ev).parse::<u32>() {
+            transport::send_text(format!("idle {}", minutes));
+        }
+    };
+
+    view! {
+        <details class="settings-panel">
+            <summary>{t(Key::Settings)}</summary>
+            <label>
+                {t(Key::GridResolution)} " "
+                <select on:change=on_grid_change>
+                    {GridPreset::ALL.into_iter().map(|preset| {
+                        let (width, height) = preset.dimensions();
+                        view! {
+                            <option
+                                value=preset.code()
+                                prop:selected=move || settings.get().grid == preset
+                            >
+                                {format!("{}x{}", width, height)}
+                            </option>
+                        }
+                    }).collect_view()}
+                </select>
+            </label>
+            <label>
+                {t(Key::BrushSize)} " "
+                <select on:change=on_brush_change>
+                    {BRUSH_SIZES.into_iter().map(|size| view! {
+                        <option
+                            value=size.to_string()
+                            prop:selected=move || settings.get().brush_size == size
+                        >
+                            {format!("{}x{}", size, size)}
+                        </option>
+                    }).collect_view()}
+                </select>
+            </label>
+            <label>
+                {t(Key::IdleClear)} " "
+                <input type="number" min="0" step="1"
+                    prop:value=move || device_settings.get().idle_clear_minutes.unwrap_or(0)
+                    prop:disabled=move || device_settings.get().idle_clear_minutes.is_none()
+                    on:change=on_idle_change
+                />
+            </label>
+        </details>
+    }
+}
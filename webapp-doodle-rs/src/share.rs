@@ -0,0 +1,118 @@
+// file: share.rs
+// desc: encode the pixel grid into a URL fragment so doodles can be shared without a backend
+
+const BASE64URL: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+const FRAGMENT_KEY: &str = "d=";
+
+// Pack the grid row-major into bits, dropping trailing zero bytes (mostly empty canvases
+// end in blank rows, so this is where the bulk of the compression comes from)
+pub fn pack_grid(grid: &[Vec<bool>]) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    for (i, pixel) in grid.iter().flatten().enumerate() {
+        if i % 8 == 0 {
+            bytes.push(0u8);
+        }
+        if *pixel {
+            *bytes.last_mut().unwrap() |= 0x80 >> (i % 8);
+        }
+    }
+
+    while bytes.last() == Some(&0) {
+        bytes.pop();
+    }
+    bytes
+}
+
+pub fn unpack_grid(bytes: &[u8], width: usize, height: usize) -> Vec<Vec<bool>> {
+    let mut grid = vec![vec![false; width]; height];
+    for (i, pixel) in grid.iter_mut().flatten().enumerate() {
+        if let Some(byte) = bytes.get(i / 8) {
+            *pixel = byte & (0x80 >> (i % 8)) != 0;
+        }
+    }
+    grid
+}
+
+fn base64url_encode(bytes: &[u8]) -> String {
+    let mut out = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let b = [chunk[0], *chunk.get(1).unwrap_or(&0), *chunk.get(2).unwrap_or(&0)];
+        let n = (b[0] as u32) << 16 | (b[1] as u32) << 8 | b[2] as u32;
+
+        // No padding: the decoder infers the tail length from the string length
+        for i in 0..=chunk.len() {
+            out.push(BASE64URL[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+        }
+    }
+    out
+}
+
+fn base64url_decode(text: &str) -> Option<Vec<u8>> {
+    let mut out = Vec::with_capacity(text.len() * 3 / 4);
+    for chunk in text.as_bytes().chunks(4) {
+        if chunk.len() < 2 {
+            return None;
+        }
+
+        let mut n = 0u32;
+        for (i, c) in chunk.iter().enumerate() {
+            let value = BASE64URL.iter().position(|b| b == c)? as u32;
+            n |= value << (18 - 6 * i);
+        }
+        for i in 0..chunk.len() - 1 {
+            out.push((n >> (16 - 8 * i)) as u8);
+        }
+    }
+    Some(out)
+}
+
+/// Encode a grid as `<width>x<height>:<base64url bits>`
+pub fn encode_grid(grid: &[Vec<bool>]) -> String {
+    let width = grid.first().map_or(0, |row| row.len());
+    format!("{}x{}:{}", width, grid.len(), base64url_encode(&pack_grid(grid)))
+}
+
+// Older links carry a single `<size>` for square grids
+fn parse_dimensions(size: &str) -> Option<(usize, usize)> {
+    match size.split_once('x') {
+        Some((width, height)) => Some((width.parse().ok()?, height.parse().ok()?)),
+        None => {
+            let side = size.parse().ok()?;
+            Some((side, side))
+        }
+    }
+}
+
+/// Decode a payload produced by `encode_grid`, rejecting payloads for a different grid size
+pub fn decode_grid(payload: &str, width: usize, height: usize) -> Option<Vec<Vec<bool>>> {
+    let (size, data) = payload.split_once(':')?;
+    if parse_dimensions(size)? != (width, height) {
+        log::warn!("Shared doodle is {}, expected {}x{}", size, width, height);
+        return None;
+    }
+
+    let bytes = base64url_decode(data)?;
+    if bytes.len() > (width * height).div_ceil(8) {
+        return None;
+    }
+    Some(unpack_grid(&bytes, width, height))
+}
+
+/// Load a shared grid from the page's URL fragment, if one is present
+pub fn grid_from_location(width: usize, height: usize) -> Option<Vec<Vec<bool>>> {
+    let hash = web_sys::window()?.location().hash().ok()?;
+    let payload = hash.strip_prefix('#')?.strip_prefix(FRAGMENT_KEY)?;
+
+    let grid = decode_grid(payload, width, height);
+    if grid.is_none() {
+        log::warn!("Ignoring malformed share link");
+    }
+    grid
+}
+
+/// Store the grid in the URL fragment and return the full shareable link
+pub fn share_grid(grid: &[Vec<bool>]) -> Option<String> {
+    let location = web_sys::window()?.location();
+    location.set_hash(&format!("{}{}", FRAGMENT_KEY, encode_grid(grid))).ok()?;
+    location.href().ok()
+}
@@ -0,0 +1,250 @@
+// file: stats.rs
+// desc: per-session drawing statistics, with past sessions kept in IndexedDB
+
+use leptos::*;
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::JsCast;
+use web_sys::IdbTransactionMode;
+
+use crate::db::{self, request_future, SESSIONS_STORE};
+use crate::i18n::{t, Key};
+
+const RECENT_SESSIONS: usize = 5;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SessionStats {
+    pub started_ms: f64,
+    pub strokes: u32,
+    pub pixels: u32,
+    // Drawings ended by a clear, the one on the canvas is counted once it has a stroke
+    pub finished_drawings: u32,
+    pub current_strokes: u32,
+}
+
+impl SessionStats {
+    fn new() -> Self {
+        Self {
+            started_ms: js_sys::Date::now(),
+            strokes: 0,
+            pixels: 0,
+            finished_drawings: 0,
+            current_strokes: 0,
+        }
+    }
+
+    pub fn drawings(&self) -> u32 {
+        self.finished_drawings + u32::from(self.current_strokes > 0)
+    }
+
+    pub fn strokes_per_drawing(&self) -> f64 {
+        match self.drawings() {
+            0 => 0.0,
+            drawings => self.strokes as f64 / drawings as f64,
+        }
+    }
+
+    pub fn record_stroke(&mut self) {
+        self.strokes += 1;
+        self.current_strokes += 1;
+    }
+
+    pub fn record_pixels(&mut self, count: u32) {
+        self.pixels += count;
+    }
+
+    pub fn record_clear(&mut self) {
+        if self.current_strokes > 0 {
+            self.finished_drawings += 1;
+            self.current_strokes = 0;
+        }
+    }
+
+    fn to_record(self) -> Result<js_sys::Object, JsValue> {
+        let record = js_sys::Object::new();
+        js_sys::Reflect::set(&record, &"started".into(), &self.started_ms.into())?;
+        js_sys::Reflect::set(&record, &"strokes".into(), &self.strokes.into())?;
+        js_sys::Reflect::set(&record, &"pixels".into(), &self.pixels.into())?;
+        js_sys::Reflect::set(&record, &"drawings".into(), &self.drawings().into())?;
+        Ok(record)
+    }
+}
+
+// Summary of a stored session, as read back from IndexedDB
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PastSession {
+    pub started_ms: f64,
+    pub strokes: u32,
+    pub pixels: u32,
+    pub drawings: u32,
+}
+
+impl PastSession {
+    fn from_record(record: &JsValue) -> Option<Self> {
+        let field = |name: &str| js_sys::Reflect::get(record, &name.into()).ok()?.as_f64();
+        Some(Self {
+            started_ms: field("started")?,
+            strokes: field("strokes")? as u32,
+            pixels: field("pixels")? as u32,
+            drawings: field("drawings")? as u32,
+        })
+    }
+
+    pub fn strokes_per_drawing(&self) -> f64 {
+        match self.drawings {
+            0 => 0.0,
+            drawings => self.strokes as f64 / drawings as f64,
+        }
+    }
+}
+
+async fn save_session(stats: SessionStats) -> Result<(), JsValue> {
+    let db = db::open_db().await?;
+    let store = db
+        .transaction_with_str_and_mode(SESSIONS_STORE, IdbTransactionMode::Readwrite)?
+        .object_store(SESSIONS_STORE)?;
+    request_future(&store.put(&stats.to_record()?)?).await?;
+    Ok(())
+}
+
+/// Stored sessions, oldest first
+async fn load_sessions() -> Result<Vec<PastSession>, JsValue> {
+    let db = db::open_db().await?;
+    let store = db.transaction_with_str(SESSIONS_STORE)?.object_store(SESSIONS_STORE)?;
+    let records: js_sys::Array = request_future(&store.get_all()?).await?.dyn_into()?;
+    Ok(records.iter().filter_map(|record| PastSession::from_record(&record)).collect())
+}
+
+fn persist(stats: SessionStats) {
+    spawn_local(async move {
+        if let Err(e) = save_session(stats).await {
+            log::warn!("Failed to save session stats: {:?}", e);
+        }
+    });
+}
+
+/// Start counting for this page load and make the stats available below the caller
+pub fn provide_stats() -> RwSignal<SessionStats> {
+    let stats = create_rw_signal(SessionStats::new());
+
+    // Saving on every pixel would hammer IndexedDB, so save per stroke and clear
+    // and catch the last stroke's pixels when the page goes away
+    create_effect(move |previous: Option<(u32, u32)>| {
+        let marks = stats.with(|stats| (stats.strokes, stats.finished_drawings));
+        if previous.is_some_and(|previous| previous != marks) {
+            persist(stats.get_untracked());
+        }
+        marks
+    });
+    let pagehide_listener = window_event_listener_untyped("pagehide", move |_| {
+        if stats.with_untracked(|stats| stats.strokes > 0) {
+            persist(stats.get_untracked());
+        }
+    });
+    on_cleanup(move || pagehide_listener.remove());
+
+    provide_context(stats);
+    stats
+}
+
+pub fn use_stats() -> RwSignal<SessionStats> {
+    expect_context::<RwSignal<SessionStats>>()
+}
+
+fn format_duration(ms: f64) -> String {
+    let seconds = (ms / 1000.0) as u64;
+    format!("{}:{:02}", seconds / 60, seconds % 60)
+}
+
+fn format_date(ms: f64) -> String {
+    let date = js_sys::Date::new(&ms.into());
+    String::from(date.to_locale_string("default", &JsValue::UNDEFINED))
+}
+
+#[component]
+pub fn StatsPanel() -> impl IntoView {
+    let stats = use_stats();
+    let (past_sessions, set_past_sessions) = create_signal(Vec::<PastSession>::new());
+    // Ticks once a second so the session length stays current
+    let (now, set_now) = create_signal(js_sys::Date::now());
+
+    let load = move || {
+        spawn_local(async move {
+            match load_sessions().await {
+                Ok(sessions) => set_past_sessions.set(sessions),
+                Err(e) => log::warn!("Failed to load session stats: {:?}", e),
+            }
+        });
+    };
+    load();
+
+    let on_toggle = move |ev: ev::Event| {
+        let open = event_target::<web_sys::HtmlDetailsElement>(&ev).open();
+        if open {
+            set_now.set(js_sys::Date::now());
+            load();
+        }
+    };
+    if let Ok(interval) = set_interval_with_handle(
+        move || set_now.set(js_sys::Date::now()),
+        std::time::Duration::from_secs(1),
+    ) {
+        on_cleanup(move || interval.clear());
+    }
+
+    // This session is stored too, leave it out of the history
+    let previous_sessions = move || {
+        let started = stats.with(|stats| stats.started_ms);
+        past_sessions.with(|sessions| {
+            sessions
+                .iter()
+                .filter(|session| session.started_ms != started)
+                .copied()
+                .collect::<Vec<_>>()
+        })
+    };
+
+    view! {
+        <details class="stats-panel" on:toggle=on_toggle>
+            <summary>{t(Key::Stats)}</summary>
+            <h4>{t(Key::ThisSession)}</h4>
+            <p>{t(Key::Strokes)} ": " {move || stats.get().strokes}</p>
+            <p>{t(Key::Drawings)} ": " {move || stats.get().drawings()}</p>
+            <p>{t(Key::StrokesPerDrawing)} ": " {move || format!("{:.1}", stats.get().strokes_per_drawing())}</p>
+            <p>{t(Key::Pixels)} ": " {move || stats.get().pixels}</p>
+            <p>{t(Key::SessionLength)} ": " {move || format_duration(now.get() - stats.get().started_ms)}</p>
+
+            <h4>{t(Key::PastSessions)}</h4>
+            {move || {
+                let sessions = previous_sessions();
+                if sessions.is_empty() {
+                    return view! { <p>{t(Key::NoPastSessions)}</p> }.into_view();
+                }
+
+                let strokes: u32 = sessions.iter().map(|session| session.strokes).sum();
+                let drawings: u32 = sessions.iter().map(|session| session.drawings).sum();
+                let average = if drawings == 0 { 0.0 } else { strokes as f64 / drawings as f64 };
+
+                view! {
+                    <p>{t(Key::Sessions)} ": " {sessions.len()}</p>
+                    <p>{t(Key::StrokesPerDrawing)} ": " {format!("{:.1}", average)}</p>
+                    <table>
+                        <tr>
+                            <th>{t(Key::Started)}</th>
+                            <th>{t(Key::Drawings)}</th>
+                            <th>{t(Key::StrokesPerDrawing)}</th>
+                            <th>{t(Key::Pixels)}</th>
+                        </tr>
+                        {sessions.iter().rev().take(RECENT_SESSIONS).map(|session| view! {
+                            <tr>
+                                <td>{format_date(session.started_ms)}</td>
+                                <td>{session.drawings}</td>
+                                <td>{format!("{:.1}", session.strokes_per_drawing())}</td>
+                                <td>{session.pixels}</td>
+                            </tr>
+                        }).collect_view()}
+                    </table>
+                }.into_view()
+            }}
+        </details>
+    }
+}
@@ -0,0 +1,232 @@
+// file: tabs.rs
+// desc: share one Pico connection and one canvas between tabs of the same browser
+
+use std::time::Duration;
+
+use leptos::*;
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::JsCast;
+use web_sys::{BroadcastChannel, MessageEvent};
+
+use doodle_protocol::PixelState;
+
+use crate::debug::LatencySample;
+use crate::history::{Author, DrawEvent};
+use crate::settings::local_storage;
+use crate::transport::ConnectionState;
+
+const CHANNEL_NAME: &str = "doodle-rs";
+// "<tab id> <ms since epoch>" of the tab that owns the WebSocket
+const LEADER_KEY: &str = "doodle-rs.leader";
+// A leader that stops renewing (closed, crashed, frozen in the background) is
+// replaced after this long
+const LEASE_MS: f64 = 3000.0;
+const RENEW_INTERVAL: Duration = Duration::from_secs(1);
+
+// Messages between tabs, sent as small "<kind> <args>" strings like the Pico's text commands
+#[derive(Clone, Debug, PartialEq)]
+pub enum TabMessage {
+    // A drawing change made in one tab or relayed by the leader, for the others
+    // and the leader's transport
+    Event(DrawEvent, Author),
+    // A new follower asking the leader for the current canvas
+    SyncRequest,
+    // The leader's whole canvas, in the share link encoding
+    State(String),
+    // The leader's link to the Pico
+    Connection(ConnectionState),
+    // Text the Pico sent to the leader
+    Pico(String),
+    // A round trip the leader timed, and the Pico's part of it
+    RoundTrip(LatencySample),
+    // The client id the Pico gave the leader's connection, and the size of its display
+    Hello { client_id: u8, width: usize, height: usize },
+}
+
+impl TabMessage {
+    fn to_text(&self) -> String {
+        match self {
+            TabMessage::Event(DrawEvent::Pixel { x, y, state }, author) => {
+                format!("pixel {} {} {} {}", x, y, state.to_byte(), author_code(author))
+            }
+            TabMessage::Event(DrawEvent::Clear, author) => format!("clear {}", author_code(author)),
+            TabMessage::Event(DrawEvent::ClearRect { x, y, width, height }, author) => {
+                format!("clear_rect {} {} {} {} {}", x, y, width, height, author_code(author))
+            }
+            TabMessage::SyncRequest => "sync".to_string(),
+            TabMessage::State(payload) => format!("state {}", payload),
+            TabMessage::Connection(state) => format!("connection {}", state.code()),
+            TabMessage::Pico(text) => format!("pico {}", text),
+            TabMessage::RoundTrip(sample) => format!("rtt {} {}", sample.round_trip_ms, sample.pico_us),
+            TabMessage::Hello { client_id, width, height } => format!("hello {} {}x{}", client_id, width, height),
+        }
+    }
+
+    fn from_text(text: &str) -> Option<Self> {
+        let (kind, args) = text.split_once(' ').unwrap_or((text, ""));
+        match kind {
+            "pixel" => {
+                let mut args = args.split(' ');
+                let mut number = || args.next()?.parse::<usize>().ok();
+                let (x, y, state) = (number()?, number()?, number()?);
+                let author = parse_author(args.next()?)?;
+                let state = PixelState::from_byte(state.try_into().ok()?)?;
+                Some(TabMessage::Event(DrawEvent::Pixel { x, y, state }, author))
+            }
+            "clear" => Some(TabMessage::Event(DrawEvent::Clear, parse_author(args)?)),
+            "clear_rect" => {
+                let mut args = args.split(' ');
+                let mut number = || args.next()?.parse::<usize>().ok();
+                let (x, y, width, height) = (number()?, number()?, number()?, number()?);
+                let author = parse_author(args.next()?)?;
+                Some(TabMessage::Event(DrawEvent::ClearRect { x, y, width, height }, author))
+            }
+            "sync" => Some(TabMessage::SyncRequest),
+            "state" => Some(TabMessage::State(args.to_string())),
+            "connection" => ConnectionState::from_code(args).map(TabMessage::Connection),
+            "pico" => Some(TabMessage::Pico(args.to_string())),
+            "rtt" => {
+                let (round_trip_ms, pico_us) = args.split_once(' ')?;
+                Some(TabMessage::RoundTrip(LatencySample {
+                    round_trip_ms: round_trip_ms.parse().ok()?,
+                    pico_us: pico_us.parse().ok()?,
+                }))
+            }
+            "hello" => {
+                let (client_id, size) = args.split_once(' ')?;
+                let (width, height) = size.split_once('x')?;
+                Some(TabMessage::Hello {
+                    client_id: client_id.parse().ok()?,
+                    width: width.parse().ok()?,
+                    height: height.parse().ok()?,
+                })
+            }
+            _ => None,
+        }
+    }
+}
+
+fn author_code(author: &Author) -> String {
+    match author {
+        Author::Local => "local".to_string(),
+        Author::Remote(id) => id.to_string(),
+    }
+}
+
+fn parse_author(code: &str) -> Option<Author> {
+    match code {
+        "local" => Some(Author::Local),
+        id => id.parse().ok().map(Author::Remote),
+    }
+}
+
+/// This tab's end of the channel shared by every tab of the app
+pub struct TabChannel {
+    channel: BroadcastChannel,
+}
+
+impl TabChannel {
+    pub fn open(on_message: impl Fn(TabMessage) + 'static) -> Option<Self> {
+        let channel = match BroadcastChannel::new(CHANNEL_NAME) {
+            Ok(channel) => channel,
+            Err(e) => {
+                log::warn!("BroadcastChannel unavailable, tabs won't be kept in sync: {:?}", e);
+                return None;
+            }
+        };
+
+        let onmessage = Closure::wrap(Box::new(move |e: MessageEvent| {
+            match e.data().as_string().as_deref().and_then(TabMessage::from_text) {
+                Some(message) => on_message(message),
+                None => log::debug!("Ignoring unexpected message from another tab"),
+            }
+        }) as Box<dyn FnMut(MessageEvent)>);
+        channel.set_onmessage(Some(onmessage.as_ref().unchecked_ref()));
+        onmessage.forget();
+
+        Some(Self { channel })
+    }
+
+    pub fn post(&self, message: &TabMessage) {
+        if let Err(e) = self.channel.post_message(&message.to_text().into()) {
+            log::error!("Failed to post to other tabs: {:?}", e);
+        }
+    }
+
+    pub fn close(&self) {
+        self.channel.set_onmessage(None);
+        self.channel.close();
+    }
+}
+
+fn parse_lease(lease: &str) -> Option<(&str, f64)> {
+    let (id, at) = lease.split_once(' ')?;
+    Some((id, at.parse().ok()?))
+}
+
+// Take or renew the lease unless another tab holds a fresh one. Two tabs racing
+// for an expired lease both win briefly, the loser sees the other's lease on its
+// next renewal and steps down
+fn claim_leadership(tab_id: &str) -> bool {
+    let Some(storage) = local_storage() else {
+        return true;
+    };
+    let now = js_sys::Date::now();
+
+    let lease = storage.get_item(LEADER_KEY).ok().flatten();
+    let held_elsewhere = lease
+        .as_deref()
+        .and_then(parse_lease)
+        .is_some_and(|(id, at)| id != tab_id && now - at < LEASE_MS);
+    if held_elsewhere {
+        return false;
+    }
+
+    let _ = storage.set_item(LEADER_KEY, &format!("{} {}", tab_id, now));
+    true
+}
+
+fn release_leadership(tab_id: &str) {
+    let Some(storage) = local_storage() else {
+        return;
+    };
+    let lease = storage.get_item(LEADER_KEY).ok().flatten();
+    if lease.as_deref().and_then(parse_lease).is_some_and(|(id, _)| id == tab_id) {
+        let _ = storage.remove_item(LEADER_KEY);
+    }
+}
+
+#[derive(Clone, Copy)]
+struct IsLeader(ReadSignal<bool>);
+
+/// Elect one tab to own the Pico connection and make the result available below the caller
+pub fn provide_leadership() {
+    let tab_id = format!("{:08x}", (js_sys::Math::random() * u32::MAX as f64) as u32);
+    let (is_leader, set_is_leader) = create_signal(claim_leadership(&tab_id));
+
+    let renew_id = tab_id.clone();
+    if let Ok(interval) = set_interval_with_handle(
+        move || {
+            let leader = claim_leadership(&renew_id);
+            if leader != is_leader.get_untracked() {
+                log::info!("This tab is now the {}", if leader { "leader" } else { "follower" });
+                set_is_leader.set(leader);
+            }
+        },
+        RENEW_INTERVAL,
+    ) {
+        on_cleanup(move || interval.clear());
+    }
+
+    // Hand over right away instead of making followers wait out the lease
+    let pagehide_listener = window_event_listener_untyped("pagehide", move |_| {
+        release_leadership(&tab_id);
+    });
+    on_cleanup(move || pagehide_listener.remove());
+
+    provide_context(IsLeader(is_leader));
+}
+
+pub fn use_is_leader() -> ReadSignal<bool> {
+    expect_context::<IsLeader>().0
+}
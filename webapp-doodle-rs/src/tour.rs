@@ -0,0 +1,113 @@
+// file: tour.rs
+// desc: first-run tour that points out the main parts of the UI one at a time
+
+use leptos::*;
+
+use crate::i18n::{t, Key};
+use crate::settings::local_storage;
+
+// Set once the tour is finished or skipped for good
+const STORAGE_KEY: &str = "doodle-rs.tour";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TourStep {
+    Canvas,
+    Clear,
+    Connection,
+}
+
+impl TourStep {
+    const ORDER: [TourStep; 3] = [TourStep::Canvas, TourStep::Clear, TourStep::Connection];
+
+    fn text(&self) -> Key {
+        match self {
+            TourStep::Canvas => Key::TourCanvas,
+            TourStep::Clear => Key::TourClear,
+            TourStep::Connection => Key::TourConnection,
+        }
+    }
+
+    fn next(&self) -> Option<TourStep> {
+        let index = Self::ORDER.iter().position(|step| step == self)?;
+        Self::ORDER.get(index + 1).copied()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TourState {
+    Showing(TourStep),
+    // Closed for this visit, shown again next time
+    Dismissed,
+    // Finished or skipped, never shown again
+    Done,
+}
+
+impl TourState {
+    fn next(self) -> Self {
+        match self {
+            TourState::Showing(step) => step.next().map_or(TourState::Done, TourState::Showing),
+            other => other,
+        }
+    }
+}
+
+fn initial_state() -> TourState {
+    let done = local_storage()
+        .and_then(|storage| storage.get_item(STORAGE_KEY).ok()?)
+        .is_some();
+    if done {
+        TourState::Done
+    } else {
+        TourState::Showing(TourStep::Canvas)
+    }
+}
+
+/// Start the tour on a first visit and make its state available below the caller
+pub fn provide_tour() {
+    let tour = create_rw_signal(initial_state());
+
+    create_effect(move |_| {
+        if tour.get() == TourState::Done {
+            if let Some(storage) = local_storage() {
+                let _ = storage.set_item(STORAGE_KEY, "done");
+            }
+        }
+    });
+
+    provide_context(tour);
+}
+
+pub fn use_tour() -> RwSignal<TourState> {
+    expect_context::<RwSignal<TourState>>()
+}
+
+/// Reactive check for `class:tour-highlight` on the element a step points at
+pub fn highlighted(step: TourStep) -> impl Fn() -> bool + Copy + 'static {
+    let tour = use_tour();
+    move || tour.get() == TourState::Showing(step)
+}
+
+#[component]
+pub fn TourPopup() -> impl IntoView {
+    let tour = use_tour();
+
+    move || match tour.get() {
+        TourState::Showing(step) => {
+            let is_last = step.next().is_none();
+            view! {
+                <div class="tour-popup" role="dialog">
+                    <p>{t(step.text())}</p>
+                    <div class="tour-buttons">
+                        <button on:click=move |_| tour.set(TourState::Done)>{t(Key::TourSkip)}</button>
+                        <button on:click=move |_| tour.set(TourState::Dismissed)>{t(Key::TourClose)}</button>
+                        <button on:click=move |_| tour.update(|state| *state = state.next())>
+                            {t(if is_last { Key::TourFinish } else { Key::TourNext })}
+                        </button>
+                    </div>
+                </div>
+            }
+            .into_view()
+        }
+        TourState::Dismissed | TourState::Done => ().into_view(),
+    }
+}
@@ -0,0 +1,676 @@
+// file: transport.rs
+// desc: WebSocket transport to the Pico, with an optional poor-network simulator
+
+use leptos::*;
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::JsCast;
+use web_sys::{WebSocket, MessageEvent, CloseEvent, ErrorEvent};
+use std::rc::Rc;
+use std::cell::{Cell, RefCell};
+use std::time::Duration;
+
+use doodle_protocol::{
+    AckWatcher, Capabilities, DrawMessage, Message, Packet, PixelState, Role, MAX_MESSAGE_LEN, MAX_STROKE_PIXELS,
+};
+
+use crate::history::{Author, DrawEvent};
+use crate::i18n::Key;
+use crate::recorder::{self, Recorded, Traffic};
+use crate::settings::local_storage;
+use crate::AppConfig;
+
+// Global WebSocket connection - using thread-local storage for web environment
+thread_local! {
+    static WS_CONNECTION: Rc<RefCell<Option<WebSocket>>> = Rc::new(RefCell::new(None));
+    static NETWORK_CONDITIONS: Cell<NetworkConditions> = Cell::new(NetworkConditions::default());
+    // Time (ms since epoch) the last delayed message is due, so delays never reorder messages
+    static LAST_DELIVERY_MS: Cell<f64> = const { Cell::new(0.0) };
+    // Numbers our binary messages and checks the Pico's Acks for gaps, per connection
+    static ACKS: Cell<AckWatcher> = const { Cell::new(AckWatcher::new()) };
+    // Time (ms since epoch) the Pico last sent anything, heartbeat replies included
+    static LAST_HEARD_MS: Cell<f64> = const { Cell::new(0.0) };
+    // What both the Pico and this app support, from the Pico's Hello
+    static SHARED_CAPABILITIES: Cell<Capabilities> = const { Cell::new(Capabilities::NONE) };
+    // Pixels held back while the Pico is out of credit, at most one per cell
+    // unless toggled, sent as Acks grant more
+    static HELD_PIXELS: RefCell<Vec<(usize, usize, PixelState)>> = const { RefCell::new(Vec::new()) };
+}
+
+// Where the id this browser registers with is kept
+const CLIENT_ID_STORAGE_KEY: &str = "doodle-rs.client-id";
+// Followed by the Pico's address, each Pico can have its own access token
+const TOKEN_STORAGE_KEY: &str = "doodle-rs.token.";
+
+// Features of the protocol this app implements, offered to the Pico in Hello
+const CAPABILITIES: Capabilities = Capabilities::BATCHING
+    .union(Capabilities::GRAYSCALE)
+    .union(Capabilities::COMPRESSION);
+
+// WebSocket close code for "protocol error", what the Pico closes with when it
+// can't speak our protocol version
+const CLOSE_PROTOCOL_ERROR: u16 = 1002;
+// "Policy violation", for a missing or wrong access token
+const CLOSE_POLICY_VIOLATION: u16 = 1008;
+
+// The Pico acks within a fraction of a second, past this our messages are going nowhere
+const ACK_TIMEOUT_MS: u64 = 2000;
+// Heartbeats go out every couple of seconds, a Pico this quiet isn't there anymore
+const HEARTBEAT_TIMEOUT_MS: f64 = 6000.0;
+
+// State of the link to the Pico, shown in the connection indicator
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ConnectionState {
+    Connecting,
+    Connected,
+    Disconnected,
+    Offline,
+    // The Pico runs firmware with another protocol version, one of them needs updating
+    Incompatible,
+    // The Pico wants an access token we don't have
+    Unauthorized,
+}
+
+impl ConnectionState {
+    pub fn label(&self) -> Key {
+        match self {
+            ConnectionState::Connecting => Key::Connecting,
+            ConnectionState::Connected => Key::Connected,
+            ConnectionState::Disconnected => Key::Disconnected,
+            ConnectionState::Offline => Key::Offline,
+            ConnectionState::Incompatible => Key::Incompatible,
+            ConnectionState::Unauthorized => Key::Unauthorized,
+        }
+    }
+
+    // Used to relay the state to other tabs
+    pub fn code(&self) -> &'static str {
+        match self {
+            ConnectionState::Connecting => "connecting",
+            ConnectionState::Connected => "connected",
+            ConnectionState::Disconnected => "disconnected",
+            ConnectionState::Offline => "offline",
+            ConnectionState::Incompatible => "incompatible",
+            ConnectionState::Unauthorized => "unauthorized",
+        }
+    }
+
+    pub fn from_code(code: &str) -> Option<Self> {
+        [
+            ConnectionState::Connecting,
+            ConnectionState::Connected,
+            ConnectionState::Disconnected,
+            ConnectionState::Offline,
+            ConnectionState::Incompatible,
+            ConnectionState::Unauthorized,
+        ]
+        .into_iter()
+        .find(|state| state.code() == code)
+    }
+
+    pub fn css_class(&self) -> &'static str {
+        match self {
+            ConnectionState::Connecting => "connection-badge connecting",
+            ConnectionState::Connected => "connection-badge connected",
+            ConnectionState::Disconnected => "connection-badge disconnected",
+            ConnectionState::Offline => "connection-badge offline",
+            ConnectionState::Incompatible => "connection-badge incompatible",
+            ConnectionState::Unauthorized => "connection-badge unauthorized",
+        }
+    }
+}
+
+pub fn stored_token(pico_url: &str) -> Option<String> {
+    local_storage()?
+        .get_item(&format!("{}{}", TOKEN_STORAGE_KEY, pico_url))
+        .ok()?
+        .filter(|token| !token.is_empty())
+}
+
+pub fn store_token(pico_url: &str, token: &str) {
+    if let Some(storage) = local_storage() {
+        let _ = storage.set_item(&format!("{}{}", TOKEN_STORAGE_KEY, pico_url), token);
+    }
+}
+
+pub fn browser_online() -> bool {
+    web_sys::window()
+        .map(|window| window.navigator().on_line())
+        .unwrap_or(true)
+}
+
+/// Simulated network impairments applied to traffic in both directions.
+/// Messages are delayed in order, like a slow TCP link, and dropped at the
+/// application level, like a pixel lost to a full pipe on the Pico.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct NetworkConditions {
+    pub latency_ms: u32,
+    pub jitter_ms: u32,
+    pub drop_rate: f64,
+}
+
+impl NetworkConditions {
+    pub fn is_ideal(&self) -> bool {
+        self.latency_ms == 0 && self.jitter_ms == 0 && self.drop_rate <= 0.0
+    }
+
+    // Returns None if the message is dropped, otherwise how long to hold it
+    fn sample(&self) -> Option<Duration> {
+        if js_sys::Math::random() < self.drop_rate {
+            return None;
+        }
+
+        let jitter = js_sys::Math::random() * self.jitter_ms as f64;
+        let wanted = js_sys::Date::now() + self.latency_ms as f64 + jitter;
+
+        let due = LAST_DELIVERY_MS.with(|last| {
+            let due = wanted.max(last.get());
+            last.set(due);
+            due
+        });
+        Some(Duration::from_millis((due - js_sys::Date::now()).max(0.0) as u64))
+    }
+}
+
+pub fn network_conditions() -> NetworkConditions {
+    NETWORK_CONDITIONS.with(|conditions| conditions.get())
+}
+
+pub fn set_network_conditions(conditions: NetworkConditions) {
+    log::info!("Network simulator: {:?}", conditions);
+    NETWORK_CONDITIONS.with(|current| current.set(conditions));
+}
+
+// Run `deliver` now, later, or never depending on the simulated conditions
+fn through_simulator(deliver: impl FnOnce() + 'static) {
+    let conditions = network_conditions();
+    if conditions.is_ideal() {
+        deliver();
+        return;
+    }
+
+    match conditions.sample() {
+        Some(delay) if delay.is_zero() => deliver(),
+        Some(delay) => set_timeout(deliver, delay),
+        None => log::debug!("Network simulator dropped a message"),
+    }
+}
+
+// Application messages received from the Pico
+#[derive(Clone, Debug, PartialEq)]
+pub enum Incoming {
+    Text(String),
+    Binary(Vec<u8>),
+}
+
+// WebSocket setup and management functions
+pub fn connect(
+    config: AppConfig,
+    set_connection: WriteSignal<ConnectionState>,
+    on_message: impl Fn(Incoming) + 'static,
+) {
+    use wasm_bindgen::closure::Closure;
+
+    let pico_url = config.pico_url;
+    let on_message = Rc::new(on_message);
+
+    disconnect();
+    ACKS.with(|acks| acks.set(AckWatcher::new()));
+    HELD_PIXELS.with(|held| held.borrow_mut().clear());
+    SHARED_CAPABILITIES.with(|shared| shared.set(Capabilities::NONE));
+
+    log::info!("Connecting to WebSocket at ws://{}:80", pico_url);
+    set_connection.set(ConnectionState::Connecting);
+
+    // Create WebSocket connection, a browser can only give the token in the URL
+    let ws_url = match stored_token(pico_url) {
+        Some(token) => format!("ws://{}:80/ws?token={}", pico_url, js_sys::encode_uri_component(&token)),
+        None => format!("ws://{}:80/ws", pico_url),
+    };
+    let ws = match WebSocket::new(&ws_url) {
+        Ok(ws) => ws,
+        Err(e) => {
+            log::error!("Failed to create WebSocket: {:?}", e);
+            // The Pico has no TLS, and browsers keep HTTPS pages off plain ws://
+            let secure_page = web_sys::window().and_then(|window| window.location().protocol().ok());
+            if secure_page.as_deref() == Some("https:") {
+                log::error!("This page is served over HTTPS, open http://{}/ for the copy the Pico serves", pico_url);
+            }
+            set_connection.set(ConnectionState::Disconnected);
+            return;
+        }
+    };
+
+    // Set binary type to arraybuffer for efficient binary messages
+    ws.set_binary_type(web_sys::BinaryType::Arraybuffer);
+
+    // Setup onopen handler
+    let onopen = Closure::wrap(Box::new(move |_| {
+        log::info!("WebSocket connected!");
+        set_connection.set(ConnectionState::Connected);
+        LAST_HEARD_MS.with(|last| last.set(js_sys::Date::now()));
+
+        // Introduce ourselves, the Pico's Hello tells us the part of our grid it can show
+        send_protocol(
+            &Message::Hello {
+                client_id: 0,
+                width: config.grid_width as u8,
+                height: config.grid_height as u8,
+                capabilities: CAPABILITIES,
+            },
+            "hello",
+        );
+        // and ask for its idle clear setting
+        send_text("idle".to_string());
+    }) as Box<dyn FnMut(JsValue)>);
+    ws.set_onopen(Some(onopen.as_ref().unchecked_ref()));
+    onopen.forget();
+
+    // Setup onclose handler
+    let onclose = Closure::wrap(Box::new(move |e: CloseEvent| {
+        log::warn!("WebSocket closed: code={}, reason={}", e.code(), e.reason());
+        set_connection.set(if e.code() == CLOSE_PROTOCOL_ERROR {
+            ConnectionState::Incompatible
+        } else if e.code() == CLOSE_POLICY_VIOLATION {
+            ConnectionState::Unauthorized
+        } else if browser_online() {
+            ConnectionState::Disconnected
+        } else {
+            ConnectionState::Offline
+        });
+
+        // Clear connection
+        WS_CONNECTION.with(|ws_conn| {
+            *ws_conn.borrow_mut() = None;
+        });
+    }) as Box<dyn FnMut(CloseEvent)>);
+    ws.set_onclose(Some(onclose.as_ref().unchecked_ref()));
+    onclose.forget();
+
+    // Setup onerror handler
+    let onerror = Closure::wrap(Box::new(move |e: ErrorEvent| {
+        log::error!("WebSocket error: {:?}", e);
+    }) as Box<dyn FnMut(ErrorEvent)>);
+    ws.set_onerror(Some(onerror.as_ref().unchecked_ref()));
+    onerror.forget();
+
+    // Setup onmessage handler (for potential server messages)
+    let onmessage = Closure::wrap(Box::new(move |e: MessageEvent| {
+        let data = e.data();
+        let message = if let Some(text) = data.as_string() {
+            Incoming::Text(text)
+        } else if let Ok(buffer) = data.dyn_into::<js_sys::ArrayBuffer>() {
+            Incoming::Binary(js_sys::Uint8Array::new(&buffer).to_vec())
+        } else {
+            log::debug!("Ignoring unexpected message from server");
+            return;
+        };
+
+        let on_message = on_message.clone();
+        through_simulator(move || {
+            let traffic = match &message {
+                Incoming::Text(text) => Traffic::Text(text.clone()),
+                Incoming::Binary(bytes) => Traffic::Binary(bytes.clone()),
+            };
+            recorder::record(Recorded::Received(traffic));
+            LAST_HEARD_MS.with(|last| last.set(js_sys::Date::now()));
+            on_message(message)
+        });
+    }) as Box<dyn FnMut(MessageEvent)>);
+    ws.set_onmessage(Some(onmessage.as_ref().unchecked_ref()));
+    onmessage.forget();
+
+    // Store connection
+    WS_CONNECTION.with(|ws_conn| {
+        *ws_conn.borrow_mut() = Some(ws);
+    });
+}
+
+/// Close the current connection, if any. Its close handler is detached first so
+/// it doesn't clobber the state of whatever connection comes next
+pub fn disconnect() {
+    WS_CONNECTION.with(|ws_conn| {
+        if let Some(ws) = ws_conn.borrow().as_ref() {
+            ws.set_onclose(None);
+            let _ = ws.close();
+        }
+        *ws_conn.borrow_mut() = None;
+    });
+}
+
+// Send a binary message, `what` is only used for logging
+fn send_message(message: Vec<u8>, what: &'static str) {
+    let is_open = WS_CONNECTION.with(|ws_conn| {
+        ws_conn.borrow().as_ref().map(|ws| ws.ready_state() == WebSocket::OPEN)
+    });
+    match is_open {
+        Some(true) => {}
+        Some(false) => {
+            log::warn!("WebSocket not open, cannot send {}", what);
+            return;
+        }
+        None => return,
+    }
+
+    through_simulator(move || {
+        WS_CONNECTION.with(|ws_conn| {
+            if let Some(ws) = ws_conn.borrow().as_ref() {
+                match ws.send_with_u8_array(&message) {
+                    Ok(_) => {
+                        log::debug!("Sent {}: {:?}", what, message);
+                        recorder::record(Recorded::Sent(Traffic::Binary(message)));
+                    }
+                    Err(e) => {
+                        log::error!("Failed to send {}: {:?}", what, e);
+                    }
+                }
+            }
+        });
+    });
+}
+
+// Text messages carry small "<command> <args>" requests for the Pico
+pub fn send_text(text: String) {
+    let is_open = WS_CONNECTION.with(|ws_conn| {
+        ws_conn.borrow().as_ref().is_some_and(|ws| ws.ready_state() == WebSocket::OPEN)
+    });
+    if !is_open {
+        log::warn!("WebSocket not open, cannot send \"{}\"", text);
+        return;
+    }
+
+    through_simulator(move || {
+        WS_CONNECTION.with(|ws_conn| {
+            if let Some(ws) = ws_conn.borrow().as_ref() {
+                match ws.send_with_str(&text) {
+                    Ok(_) => recorder::record(Recorded::Sent(Traffic::Text(text))),
+                    Err(e) => log::error!("Failed to send \"{}\": {:?}", text, e),
+                }
+            }
+        });
+    });
+}
+
+// Numbered before the simulator can drop it, so simulated loss shows up as a gap
+fn send_protocol(message: &Message, what: &'static str) {
+    let resync = matches!(message, Message::Frame { .. });
+    let seq = ACKS.with(|acks| {
+        let mut watcher = acks.get();
+        let seq = watcher.send(js_sys::Date::now() as u64, resync, credit_cost(message));
+        acks.set(watcher);
+        seq
+    });
+    let packet = Packet { seq, message: *message };
+
+    let mut bytes = vec![0; packet.encoded_len()];
+    match packet.encode(&mut bytes) {
+        Ok(_) => send_message(compressed(bytes, message), what),
+        Err(e) => log::error!("Failed to encode {}: {:?}", what, e),
+    }
+}
+
+// Frames and strokes are worth compressing, when the Pico can unpack them and
+// it actually comes out shorter
+fn compressed(bytes: Vec<u8>, message: &Message) -> Vec<u8> {
+    let bulky = matches!(message, Message::Frame { .. } | Message::Stroke { .. });
+    if !bulky || !pico_supports(Capabilities::COMPRESSION) {
+        return bytes;
+    }
+    let mut out = vec![0; bytes.len()];
+    match doodle_protocol::compress_packet(&bytes, &mut out) {
+        Some(len) => {
+            out.truncate(len);
+            out
+        }
+        None => bytes,
+    }
+}
+
+// What a message takes out of the Pico's drawing queue, in pixels. Frames skip
+// the queue, they replace the canvas outright
+fn credit_cost(message: &Message) -> u8 {
+    match message {
+        Message::Draw { message, .. } => message.encode().len().div_ceil(DrawMessage::LEN) as u8,
+        Message::Stroke { pixels, .. } => (pixels.len() / DrawMessage::LEN) as u8,
+        _ => 0,
+    }
+}
+
+// The Pico fills in the author of our drawing itself
+fn send_drawing(message: DrawMessage, what: &'static str) {
+    send_protocol(&Message::Draw { author: 0, message: for_pico(message) }, what);
+}
+
+// Gray only goes to a Pico that takes it, older firmware gets it dithered
+fn for_pico(message: DrawMessage) -> DrawMessage {
+    if pico_supports(Capabilities::GRAYSCALE) {
+        message
+    } else {
+        message.monochrome()
+    }
+}
+
+fn pixel_message(x: usize, y: usize, state: PixelState) -> DrawMessage {
+    // Grids are at most 128 wide, so coordinates always fit the protocol's bytes
+    DrawMessage::Pixel { x: x as u8, y: y as u8, state }
+}
+
+pub fn send_pixel(x: usize, y: usize, state: PixelState) {
+    send_pixels(&[(x, y, state)]);
+}
+
+/// Send several pixels at once, as Strokes if the Pico takes them. Whatever
+/// the Pico has no credit for yet is held back and merged with later pixels
+pub fn send_pixels(pixels: &[(usize, usize, PixelState)]) {
+    HELD_PIXELS.with(|held| {
+        let mut held = held.borrow_mut();
+        for &(x, y, state) in pixels {
+            // A later state replaces an earlier one, toggles have to add up
+            match held.iter_mut().find(|(held_x, held_y, _)| (*held_x, *held_y) == (x, y)) {
+                Some(pixel) if state != PixelState::Toggle && pixel.2 != PixelState::Toggle => pixel.2 = state,
+                _ => held.push((x, y, state)),
+            }
+        }
+    });
+    send_held_pixels();
+}
+
+// Send as many held pixels as the Pico has credit for, or all of them to a
+// Pico that hasn't said
+fn send_held_pixels() {
+    let credit = ACKS.with(|acks| acks.get().credit());
+    let pixels = HELD_PIXELS.with(|held| {
+        let mut held = held.borrow_mut();
+        let count = credit.map_or(held.len(), |credit| held.len().min(credit as usize));
+        held.drain(..count).collect::<Vec<_>>()
+    });
+    if !pixels.is_empty() {
+        send_pixels_now(&pixels);
+    }
+}
+
+// Drop held pixels `keep` rejects, something sent now has made them moot
+fn forget_held_pixels(keep: impl Fn(usize, usize) -> bool) {
+    HELD_PIXELS.with(|held| held.borrow_mut().retain(|&(x, y, _)| keep(x, y)));
+}
+
+fn send_pixels_now(pixels: &[(usize, usize, PixelState)]) {
+    if pixels.len() < 2 || !pico_supports(Capabilities::BATCHING) {
+        for &(x, y, state) in pixels {
+            send_drawing(pixel_message(x, y, state), "pixel");
+        }
+        return;
+    }
+
+    for stroke in pixels.chunks(MAX_STROKE_PIXELS) {
+        let bytes: Vec<u8> = stroke
+            .iter()
+            .flat_map(|&(x, y, state)| for_pico(pixel_message(x, y, state)).encode())
+            .collect();
+        send_protocol(&Message::Stroke { author: 0, pixels: &bytes }, "stroke");
+    }
+}
+
+/// Handle an Ack from the Pico, sending what its credit now allows. Returns
+/// true if it missed some of our messages and needs the whole canvas again
+pub fn on_ack(acked: u16, latest: u16, credit: u8) -> bool {
+    let missed = ACKS.with(|acks| {
+        let mut watcher = acks.get();
+        let missed = watcher.acked(js_sys::Date::now() as u64, acked, latest, credit);
+        acks.set(watcher);
+        missed
+    });
+    send_held_pixels();
+    missed
+}
+
+/// Note what the Pico said it supports in its Hello
+pub fn set_pico_capabilities(capabilities: Capabilities) {
+    SHARED_CAPABILITIES.with(|shared| shared.set(CAPABILITIES.shared(capabilities)));
+}
+
+/// True if both ends of the current connection support `capability`
+pub fn pico_supports(capability: Capabilities) -> bool {
+    SHARED_CAPABILITIES.with(|shared| shared.get().contains(capability))
+}
+
+// The id this browser registers with, kept so the Pico sees the same browser
+// come back after a reconnect
+fn registered_id() -> u32 {
+    let storage = local_storage();
+    let stored = storage
+        .as_ref()
+        .and_then(|storage| storage.get_item(CLIENT_ID_STORAGE_KEY).ok().flatten())
+        .and_then(|id| u32::from_str_radix(&id, 16).ok());
+    if let Some(id) = stored {
+        return id;
+    }
+
+    let id = (js_sys::Math::random() * u32::MAX as f64) as u32;
+    if let Some(storage) = storage {
+        let _ = storage.set_item(CLIENT_ID_STORAGE_KEY, &format!("{:08x}", id));
+    }
+    id
+}
+
+/// Tell the Pico who we are and whether we draw or only watch, it ignores
+/// drawing from spectators
+pub fn send_register(spectating: bool) {
+    let role = if spectating { Role::Spectator } else { Role::Drawer };
+    send_protocol(&Message::Register { id: registered_id(), role }, "register");
+}
+
+/// Ask the Pico for its whole canvas, it answers with a Frame
+pub fn send_get_frame() {
+    send_protocol(&Message::GetFrame, "frame request");
+}
+
+/// Time the round trip to the Pico, which sends the Echo straight back
+pub fn send_echo() {
+    let echo = Message::Echo { sent_ms: js_sys::Date::now() as u64 as u32, pico_us: 0 };
+    send_protocol(&echo, "echo");
+}
+
+/// Milliseconds since we sent the Echo stamped `sent_ms`
+pub fn echo_round_trip_ms(sent_ms: u32) -> f64 {
+    (js_sys::Date::now() as u64 as u32).wrapping_sub(sent_ms) as f64
+}
+
+/// Ask the Pico for a sign of life, it answers with the same Ping
+pub fn send_heartbeat() {
+    send_protocol(&Message::Ping(js_sys::Date::now() as u64 as u32), "heartbeat");
+}
+
+/// True if the Pico hasn't answered heartbeats for a while, the connection
+/// may look open but nothing comes through it
+pub fn heartbeat_missed() -> bool {
+    LAST_HEARD_MS.with(|last| js_sys::Date::now() - last.get() > HEARTBEAT_TIMEOUT_MS)
+}
+
+/// True if the Pico has stopped acking what we send
+pub fn ack_overdue() -> bool {
+    ACKS.with(|acks| acks.get().overdue(js_sys::Date::now() as u64, ACK_TIMEOUT_MS))
+}
+
+pub fn send_clear() {
+    forget_held_pixels(|_, _| false);
+    send_drawing(DrawMessage::Clear, "clear command");
+}
+
+/// Clear a rectangle of the Pico's canvas in one message
+pub fn send_clear_rect(x: usize, y: usize, width: usize, height: usize) {
+    let message = DrawMessage::ClearRect {
+        x: x as u8,
+        y: y as u8,
+        width: width as u8,
+        height: height as u8,
+    };
+    forget_held_pixels(|held_x, held_y| {
+        !(x..x + width).contains(&held_x) || !(y..y + height).contains(&held_y)
+    });
+    send_drawing(message, "clear rect");
+}
+
+/// A drawing message the Pico relayed from another client, or made itself
+pub fn draw_event(author: u8, message: DrawMessage) -> (Author, DrawEvent) {
+    let event = match message {
+        DrawMessage::Pixel { x, y, state } => DrawEvent::Pixel {
+            x: x as usize,
+            y: y as usize,
+            state,
+        },
+        DrawMessage::Clear => DrawEvent::Clear,
+        DrawMessage::ClearRect { x, y, width, height } => DrawEvent::ClearRect {
+            x: x as usize,
+            y: y as usize,
+            width: width as usize,
+            height: height as usize,
+        },
+    };
+    (Author::Remote(author), event)
+}
+
+/// A Frame from the Pico as a `width` by `height` grid, its canvas in the top-left
+/// corner. None if the runs don't add up
+pub fn frame_grid(frame_width: u8, frame_height: u8, runs: &[u8], width: usize, height: usize) -> Option<Vec<Vec<bool>>> {
+    let (frame_width, frame_height) = (frame_width as usize, frame_height as usize);
+    let mut grid = vec![vec![false; width]; height];
+    doodle_protocol::decode_runs(runs, frame_width * frame_height, |i| {
+        let (x, y) = (i % frame_width, i / frame_width);
+        if x < width && y < height {
+            grid[y][x] = true;
+        }
+    })
+    .ok()?;
+    Some(grid)
+}
+
+/// Replace whatever the Pico shows with `grid`, as one run-length encoded frame,
+/// or one pixel message per inked cell if the frame would be too long
+pub fn send_grid(grid: &[Vec<bool>]) {
+    let width = grid.first().map_or(0, |row| row.len());
+    let mut runs = Vec::new();
+    doodle_protocol::encode_runs(grid.iter().flatten().copied(), |byte| runs.push(byte));
+    let frame = Message::Frame {
+        width: width as u8,
+        height: grid.len() as u8,
+        data: &runs,
+    };
+    // Measured as it goes on the wire, the sequence number doesn't change the length
+    let len = Packet { seq: 0, message: frame }.encoded_len();
+    if len <= MAX_MESSAGE_LEN {
+        // The frame has everything held back in it already
+        forget_held_pixels(|_, _| false);
+        send_protocol(&frame, "frame");
+        return;
+    }
+
+    log::debug!("Frame of {} bytes is too long, sending pixels", len);
+    send_clear();
+    for (y, row) in grid.iter().enumerate() {
+        for (x, pixel) in row.iter().enumerate() {
+            if *pixel {
+                send_pixel(x, y, PixelState::Set);
+            }
+        }
+    }
+}
@@ -2,26 +2,95 @@
 // desc: handle web app operations
 
 use leptos::*;
-use wasm_bindgen::prelude::*;
 use wasm_bindgen::JsCast;
-use web_sys::{CanvasRenderingContext2d, HtmlCanvasElement, MouseEvent, WebSocket, MessageEvent, CloseEvent, ErrorEvent};
-use std::rc::Rc;
-use std::cell::RefCell;
+use web_sys::{CanvasRenderingContext2d, HtmlCanvasElement, MouseEvent};
 
 use crate::AppConfig;
+use crate::share;
+use crate::clipboard;
+use crate::brush::{self, Tool};
+use crate::qr::{self, QrCodeView};
+use crate::transport::{self, ConnectionState, Incoming};
+use doodle_protocol::{decompress_packet, DecodeError, Message, Packet, PixelState, Status, GRAY_LEVELS, MAX_MESSAGE_LEN};
+use crate::debug::{DebugPanel, LatencySample};
+use crate::history::{Author, DrawEvent, DrawHistory};
+use crate::recorder::{self, Recorded};
+use crate::i18n::{self, t, translate, use_language, Key, LanguagePicker};
+use crate::settings::{self, SettingsPanel};
+use crate::stats::{self, StatsPanel};
+use crate::collect::CollectPanel;
+use crate::pico_status::{PicoStatusPanel, RSSI_SAMPLES};
+use crate::tabs::{self, TabChannel, TabMessage};
+use crate::tour::{self, TourPopup, TourStep};
 
-// Global WebSocket connection - using thread-local storage for web environment
-thread_local! {
-    static WS_CONNECTION: Rc<RefCell<Option<WebSocket>>> = Rc::new(RefCell::new(None));
-}
+// Round trips are measured with Echo messages the Pico sends straight back, sent
+// along with the heartbeat
+const ECHO_INTERVAL: std::time::Duration = std::time::Duration::from_secs(2);
+// Above this the connection badge turns red, drawing starts to feel laggy
+const SLOW_ROUND_TRIP_MS: f64 = 250.0;
+// Round trips kept for the latency histogram, a few minutes' worth
+const LATENCY_SAMPLES: usize = 100;
+
+// Pixels of the highlighted participant, visible on both color schemes
+const HIGHLIGHT_COLOR: &str = "#e53935";
 
 #[component]
 fn DrawingCanvas(config: AppConfig) -> impl IntoView {
     let canvas_ref = create_node_ref::<leptos::html::Canvas>();
-    let (pixel_grid, set_pixel_grid) = create_signal(
-        vec![vec![false; config.pixel_grid_size]; config.pixel_grid_size]
-    );
+    // Hover previews go on their own layer so they never trigger the grid redraw
+    let overlay_ref = create_node_ref::<leptos::html::Canvas>();
+    let settings = settings::use_settings();
+    let device_settings = settings::use_device_settings();
+    let session_stats = stats::use_stats();
+    // Start from a shared doodle if the page was opened from a share link
+    let initial_grid = share::grid_from_location(config.grid_width, config.grid_height)
+        .unwrap_or_else(|| config.empty_grid());
+    let (pixel_grid, set_pixel_grid) = create_signal(initial_grid.clone());
+    let history = create_rw_signal(DrawHistory::new(initial_grid));
+    // Some(n) while the timeline is rewound to just after event n, None when live
+    let (rewound_to, set_rewound_to) = create_signal(None::<usize>);
     let (is_drawing, set_is_drawing) = create_signal(false);
+    let (tool, set_tool) = create_signal(Tool::Pen);
+    // Gray level the pen draws with, the top level is plain black
+    let (ink, set_ink) = create_signal(GRAY_LEVELS - 1);
+    // Corner the eraser drag started from, and the cell it is over now
+    let erase_area = store_value(None::<((usize, usize), (usize, usize))>);
+    let (share_link, set_share_link) = create_signal(None::<String>);
+    let (connection, set_connection) = create_signal(ConnectionState::Connecting);
+    let (show_qr, set_show_qr) = create_signal(false);
+    let (share_failed, set_share_failed) = create_signal(false);
+    let (inverted, set_inverted) = create_signal(false);
+    // Spectators watch what the Pico shows and can't draw
+    let (spectating, set_spectating) = create_signal(false);
+    let (copy_status, set_copy_status) = create_signal(None::<Key>);
+    // Grid area the Pico reported it can display for our grid
+    let (pico_grid, set_pico_grid) = create_signal(None::<(usize, usize)>);
+    // Id the Pico gave this browser, and the participant whose pixels are highlighted
+    let (client_id, set_client_id) = create_signal(None::<u8>);
+    let (round_trip_ms, set_round_trip_ms) = create_signal(None::<f64>);
+    // The latest round trips, for the debug panel's histogram
+    let (latency, set_latency) = create_signal(Vec::<LatencySample>::new());
+    // Binary messages from the Pico that failed their checksum, shown in the debug panel
+    let (corrupt_messages, set_corrupt_messages) = create_signal(0u32);
+    let (pico_status, set_pico_status) = create_signal(None::<Status>);
+    let (rssi_history, set_rssi_history) = create_signal(Vec::<i8>::new());
+    let (highlighted, set_highlighted) = create_signal(None::<Author>);
+    let language = use_language();
+    // Only the leader tab talks to the Pico, the others mirror it over the tab channel
+    let is_leader = tabs::use_is_leader();
+    let tab_channel = store_value(None::<TabChannel>);
+    let post_to_tabs = move |message: TabMessage| {
+        tab_channel.with_value(|channel| {
+            if let Some(channel) = channel {
+                channel.post(&message);
+            }
+        });
+    };
+
+    // QR code points at the latest share link, or the app itself before anything is shared
+    let qr_text = Signal::derive(move || {
+        share_link.get().or_else(qr::app_url).unwrap_or_default()
+    });
     
     // Initialize canvas context
     let canvas_context = create_memo(move |_| {
@@ -34,37 +103,286 @@ fn DrawingCanvas(config: AppConfig) -> impl IntoView {
         })
     });
 
-    // Setup WebSocket connection when component mounts
+    // Drawing done elsewhere: another tab, another browser or the Pico itself. A rewound
+    // timeline keeps showing the past, the change is there once it's scrubbed back to live
+    let apply_remote = move |event: DrawEvent, author: Author| {
+        if let DrawEvent::Pixel { x, y, .. } = event {
+            if x >= config.grid_width || y >= config.grid_height {
+                return;
+            }
+        }
+        history.update(|history| history.push(event, author));
+        recorder::record(Recorded::Draw(event, author));
+        if rewound_to.get_untracked().is_none() {
+            set_pixel_grid.update(|grid| event.apply(grid));
+        }
+    };
+
+    // The Pico greets every connection with the id it labels our drawing with, and
+    // the size of its display, which shows the top-left of our grid
+    let has_connected = store_value(false);
+    let on_hello = move |id: u8, width: usize, height: usize| {
+        set_client_id.set(Some(id));
+        set_pico_grid.set(Some((width.min(config.grid_width), height.min(config.grid_height))));
+        if is_leader.get_untracked() {
+            transport::send_register(spectating.get_untracked());
+        }
+        // A fresh connection, catch up with the Pico
+        if spectating.get_untracked() {
+            transport::send_get_frame();
+        } else if is_leader.get_untracked() {
+            // After a reconnect the Pico may have missed some of our drawing, so
+            // resync it. A blank first connect starts from whatever others drew
+            let grid = pixel_grid.get_untracked();
+            if has_connected.get_value() || grid.iter().flatten().any(|pixel| *pixel) {
+                transport::send_grid(&grid);
+            } else {
+                transport::send_get_frame();
+            }
+        }
+        has_connected.set_value(true);
+    };
+
+    // Start over from what the Pico shows, which replaces the undo history too
+    let load_pico_canvas = move |grid: Vec<Vec<bool>>| {
+        if is_leader.get_untracked() {
+            post_to_tabs(TabMessage::State(share::encode_grid(&grid)));
+        }
+        history.set(DrawHistory::new(grid.clone()));
+        set_rewound_to.set(None);
+        set_pixel_grid.set(grid);
+    };
+
+    let on_round_trip = move |sample: LatencySample| {
+        set_round_trip_ms.set(Some(sample.round_trip_ms));
+        set_latency.update(|samples| {
+            if samples.len() == LATENCY_SAMPLES {
+                samples.remove(0);
+            }
+            samples.push(sample);
+        });
+    };
+
+    let on_message = move |message: Incoming| match message {
+        Incoming::Text(text) => {
+            if is_leader.get_untracked() {
+                post_to_tabs(TabMessage::Pico(text.clone()));
+            }
+            if let Some(Ok(minutes)) = text.strip_prefix("idle ").map(str::parse) {
+                device_settings.update(|device| device.idle_clear_minutes = Some(minutes));
+            } else {
+                log::debug!("Text from Pico: {}", text);
+            }
+        }
+        // Drawing from other browsers on the Pico, or the Pico clearing itself
+        // after sitting idle
+        Incoming::Binary(bytes) => match decompress_packet(&bytes, &mut [0; MAX_MESSAGE_LEN])
+            .and_then(Packet::decode)
+            .map(|packet| packet.message)
+        {
+            Ok(Message::Draw { author, message }) => {
+                let (author, event) = transport::draw_event(author, message);
+                apply_remote(event, author);
+                if is_leader.get_untracked() {
+                    post_to_tabs(TabMessage::Event(event, author));
+                }
+            }
+            // Another browser's stroke, relayed whole
+            Ok(Message::Stroke { author, pixels }) => {
+                for message in doodle_protocol::stroke_pixels(pixels) {
+                    let (author, event) = transport::draw_event(author, message);
+                    apply_remote(event, author);
+                    if is_leader.get_untracked() {
+                        post_to_tabs(TabMessage::Event(event, author));
+                    }
+                }
+            }
+            Ok(Message::Hello { client_id, width, height, capabilities }) => {
+                let (width, height) = (width as usize, height as usize);
+                transport::set_pico_capabilities(capabilities);
+                on_hello(client_id, width, height);
+                if is_leader.get_untracked() {
+                    post_to_tabs(TabMessage::Hello { client_id, width, height });
+                }
+            }
+            Ok(Message::Ack { acked, latest, credit }) => {
+                if transport::on_ack(acked, latest, credit) {
+                    log::warn!("The Pico missed messages {}..{}, resyncing", acked.wrapping_add(1), latest);
+                    transport::send_grid(&pixel_grid.get_untracked());
+                }
+            }
+            // The answer to GetFrame, or pushed by the Pico after another browser
+            // resynced it or when we fell behind
+            Ok(Message::Frame { width, height, data }) => {
+                match transport::frame_grid(width, height, data, config.grid_width, config.grid_height) {
+                    Some(grid) => load_pico_canvas(grid),
+                    None => log::warn!("Malformed frame from Pico"),
+                }
+            }
+            // Sent every few seconds, and straight away when a browser joins or leaves
+            Ok(Message::Status(status)) => {
+                if let Some(previous) = pico_status.get_untracked() {
+                    if status.clients != previous.clients {
+                        log::info!("{} browser(s) connected to the Pico, was {}", status.clients, previous.clients);
+                    }
+                }
+                // 0 is a Pico that doesn't know its signal
+                if status.rssi != 0 {
+                    set_rssi_history.update(|history| {
+                        if history.len() == RSSI_SAMPLES {
+                            history.remove(0);
+                        }
+                        history.push(status.rssi);
+                    });
+                }
+                set_pico_status.set(Some(status));
+            }
+            Ok(Message::Echo { sent_ms, pico_us }) => {
+                let sample = LatencySample { round_trip_ms: transport::echo_round_trip_ms(sent_ms), pico_us };
+                on_round_trip(sample);
+                if is_leader.get_untracked() {
+                    post_to_tabs(TabMessage::RoundTrip(sample));
+                }
+            }
+            // A heartbeat answer, hearing anything at all is what counts
+            Ok(Message::Ping(_)) => {}
+            Ok(other) => log::debug!("Ignoring {:?} message from the Pico", other.message_type()),
+            Err(DecodeError::Checksum) => {
+                set_corrupt_messages.update(|count| *count += 1);
+                log::warn!("Dropping corrupt message from the Pico: {:?}", bytes);
+            }
+            // Nothing more from this Pico can be read, so stop talking to it
+            Err(DecodeError::Version(version)) => {
+                log::error!(
+                    "The Pico speaks protocol version {}, this app speaks {}. Update whichever is older",
+                    version,
+                    doodle_protocol::VERSION
+                );
+                transport::disconnect();
+                set_connection.set(ConnectionState::Incompatible);
+            }
+            Err(e) => log::debug!("Malformed message from server ({:?}): {:?}", e, bytes),
+        },
+    };
+
+    // Setup WebSocket connection when component mounts, or when this tab takes over
+    // as leader. The canvas works without a network, so when offline (or told to
+    // stay offline in the URL) only the Pico mirroring is skipped
+    create_effect(move |_| {
+        if !is_leader.get() {
+            transport::disconnect();
+        } else if !config.offline && transport::browser_online() {
+            transport::connect(config, set_connection, on_message);
+        } else {
+            set_connection.set(ConnectionState::Offline);
+        }
+    });
+
+    // Ask for the access token when the Pico turns us away for the lack of one,
+    // and try again with it. Cancelling leaves the badge saying what's missing
     create_effect(move |_| {
-        setup_websocket(config.pico_url);
+        if connection.get() != ConnectionState::Unauthorized || !is_leader.get_untracked() {
+            return;
+        }
+        let current = transport::stored_token(config.pico_url).unwrap_or_default();
+        let answer = web_sys::window()
+            .and_then(|window| window.prompt_with_message_and_default(t(Key::AccessTokenPrompt)(), &current).ok())
+            .flatten();
+        if let Some(token) = answer.filter(|token| !token.trim().is_empty()) {
+            transport::store_token(config.pico_url, token.trim());
+            transport::connect(config, set_connection, on_message);
+        }
     });
 
-    // Redraw canvas when pixel grid changes
+    // Keep measuring while connected, and forget stale numbers otherwise. Drawing
+    // the Pico never acked may be lost, so it gets the whole canvas again. A Pico
+    // that stopped answering heartbeats is reconnected to
+    if let Ok(interval) = set_interval_with_handle(
+        move || {
+            if is_leader.get_untracked() && connection.get_untracked() == ConnectionState::Connected {
+                if transport::heartbeat_missed() {
+                    log::warn!("The Pico missed its heartbeats, reconnecting");
+                    set_connection.set(ConnectionState::Disconnected);
+                    transport::connect(config, set_connection, on_message);
+                    return;
+                }
+                transport::send_heartbeat();
+                transport::send_echo();
+                if transport::ack_overdue() {
+                    log::warn!("The Pico stopped acking, resyncing");
+                    transport::send_grid(&pixel_grid.get_untracked());
+                }
+            }
+        },
+        ECHO_INTERVAL,
+    ) {
+        on_cleanup(move || interval.clear());
+    }
+    create_effect(move |_| {
+        if connection.get() != ConnectionState::Connected {
+            set_round_trip_ms.set(None);
+            set_pico_status.set(None);
+            set_rssi_history.set(Vec::new());
+        }
+    });
+    let is_slow = move || round_trip_ms.get().is_some_and(|ms| ms > SLOW_ROUND_TRIP_MS);
+
+    // Follow the browser's network state, reconnecting once it comes back
+    let online_listener = window_event_listener_untyped("online", move |_| {
+        if is_leader.get_untracked() && !config.offline {
+            log::info!("Network back online, reconnecting");
+            transport::connect(config, set_connection, on_message);
+        }
+    });
+    let offline_listener = window_event_listener_untyped("offline", move |_| {
+        if is_leader.get_untracked() {
+            log::warn!("Network offline, Pico mirroring paused");
+            set_connection.set(ConnectionState::Offline);
+        }
+    });
+    on_cleanup(move || {
+        online_listener.remove();
+        offline_listener.remove();
+    });
+
+    // Redraw canvas when pixel grid or color mode changes
     create_effect(move |_| {
         let grid = pixel_grid.get();
         
+        // Inverted mode draws white-on-black like MNIST digits and the OLED.
+        // This only changes rendering, the grid always stores "pixel is inked"
+        let (background, grid_line, ink) = if inverted.get() {
+            ("#000000", "#333333", "#ffffff")
+        } else {
+            ("#ffffff", "#e0e0e0", "#000000")
+        };
+        
         if let Some(ctx) = canvas_context.get() {
             // Clear canvas
-            ctx.clear_rect(0.0, 0.0, config.canvas_size, config.canvas_size);
+            ctx.set_fill_style_str(background);
+            ctx.fill_rect(0.0, 0.0, config.canvas_width, config.canvas_height);
             
-            // Draw grid lines (light gray)
-            ctx.set_stroke_style_str("#e0e0e0");
+            // Draw grid lines
+            ctx.set_stroke_style_str(grid_line);
             ctx.set_line_width(1.0);
             ctx.begin_path();
             
-            for i in 0..=config.pixel_grid_size {
+            // Vertical lines
+            for i in 0..=config.grid_width {
                 let pos = i as f64 * config.pixel_size;
-                // Vertical lines
                 ctx.move_to(pos, 0.0);
-                ctx.line_to(pos, config.canvas_size);
-                // Horizontal lines
+                ctx.line_to(pos, config.canvas_height);
+            }
+            // Horizontal lines
+            for i in 0..=config.grid_height {
+                let pos = i as f64 * config.pixel_size;
                 ctx.move_to(0.0, pos);
-                ctx.line_to(config.canvas_size, pos);
+                ctx.line_to(config.canvas_width, pos);
             }
             ctx.stroke();
             
-            // Draw filled pixels (black squares)
-            ctx.set_fill_style_str("#000000");
+            // Draw filled pixels
+            ctx.set_fill_style_str(ink);
             for (y, row) in grid.iter().enumerate() {
                 for (x, pixel) in row.iter().enumerate() {
                     if *pixel {
@@ -74,6 +392,22 @@ fn DrawingCanvas(config: AppConfig) -> impl IntoView {
                     }
                 }
             }
+            
+            // Recolor one participant's pixels
+            if let Some(author) = highlighted.get() {
+                let position = rewound_to.get().unwrap_or_else(|| history.with(|history| history.len()));
+                let owners = history.with(|history| history.owners(position));
+                ctx.set_fill_style_str(HIGHLIGHT_COLOR);
+                for (y, row) in owners.iter().enumerate() {
+                    for (x, owner) in row.iter().enumerate() {
+                        if *owner == Some(author) {
+                            let rect_x = x as f64 * config.pixel_size;
+                            let rect_y = y as f64 * config.pixel_size;
+                            ctx.fill_rect(rect_x, rect_y, config.pixel_size, config.pixel_size);
+                        }
+                    }
+                }
+            }
         }
     });
 
@@ -89,35 +423,133 @@ fn DrawingCanvas(config: AppConfig) -> impl IntoView {
         let pixel_x = (canvas_x / config.pixel_size).floor() as usize;
         let pixel_y = (canvas_y / config.pixel_size).floor() as usize;
         
-        if pixel_x < config.pixel_grid_size && pixel_y < config.pixel_grid_size {
+        if pixel_x < config.grid_width && pixel_y < config.grid_height {
             Some((pixel_x, pixel_y))
         } else {
             None
         }
     };
 
+    // Drawing after rewinding the timeline discards the rewound-away events
+    // and brings the Pico back in line with the grid shown on screen
+    let record_event = move |event: DrawEvent| {
+        if let Some(position) = rewound_to.get_untracked() {
+            history.update(|history| history.truncate(position));
+            set_rewound_to.set(None);
+            transport::send_grid(&pixel_grid.get_untracked());
+            post_to_tabs(TabMessage::State(share::encode_grid(&pixel_grid.get_untracked())));
+        }
+        history.update(|history| history.push(event, Author::Local));
+        recorder::record(Recorded::Draw(event, Author::Local));
+    };
+
     // Handle drawing on pixel - now with WebSocket
     let draw_pixel = move |x: usize, y: usize| {
-        // Update visual grid immediately for responsive UI
-        set_pixel_grid.update(|grid| {
-            grid[y][x] = true;
-        });
+        let brush_size = settings.with_untracked(|settings| settings.brush_size);
+        
+        let state = match (tool.get_untracked(), ink.get_untracked()) {
+            (Tool::Eraser, _) => PixelState::Clear,
+            (_, level) if level == GRAY_LEVELS - 1 => PixelState::Set,
+            (_, level) => PixelState::Gray(level),
+        };
+        
+        let mut changed = Vec::new();
+        for (x, y) in brush::footprint(x, y, brush_size, &config) {
+            // Moving within a cell that is already inked, or erased, changes nothing
+            if pixel_grid.with_untracked(|grid| state.apply(grid[y][x], x, y) == grid[y][x]) {
+                continue;
+            }
+            let event = DrawEvent::Pixel { x, y, state };
+            record_event(event);
+            
+            // Update visual grid immediately for responsive UI
+            set_pixel_grid.update(|grid| event.apply(grid));
+            
+            changed.push((x, y, state));
+            post_to_tabs(TabMessage::Event(event, Author::Local));
+            if pixel_grid.with_untracked(|grid| grid[y][x]) {
+                session_stats.update(|stats| stats.record_pixels(1));
+            }
+        }
+        
+        // Send the whole brush stamp via WebSocket (non-blocking)
+        transport::send_pixels(&changed);
+    };
+
+    // Draw straight onto the overlay instead of going through a signal
+    let preview_brush = move |cell: Option<(usize, usize)>| {
+        let Some(overlay) = overlay_ref.get_untracked() else {
+            return;
+        };
+        let Some(ctx) = overlay
+            .get_context("2d")
+            .ok()
+            .flatten()
+            .and_then(|ctx| ctx.dyn_into::<CanvasRenderingContext2d>().ok())
+        else {
+            return;
+        };
         
-        // Send pixel update via WebSocket (non-blocking)
-        send_pixel_via_websocket(x, y, true);
+        let brush_size = settings.with_untracked(|settings| settings.brush_size);
+        let cells = cell
+            .map(|(x, y)| brush::footprint(x, y, brush_size, &config))
+            .unwrap_or_default();
+        brush::draw_preview(&ctx, &cells, &config);
+    };
+
+    let preview_area = move |from: (usize, usize), to: (usize, usize)| {
+        erase_area.set_value(Some((from, to)));
+        let Some(ctx) = overlay_ref
+            .get_untracked()
+            .and_then(|overlay| overlay.get_context("2d").ok().flatten())
+            .and_then(|ctx| ctx.dyn_into::<CanvasRenderingContext2d>().ok())
+        else {
+            return;
+        };
+        brush::draw_preview(&ctx, &brush::area_cells(from, to), &config);
+    };
+
+    // Clear the dragged-out rectangle here and on the Pico, in one message
+    let erase = move |from: (usize, usize), to: (usize, usize)| {
+        let (x, y, width, height) = brush::area(from, to);
+        let event = DrawEvent::ClearRect { x, y, width, height };
+        record_event(event);
+        set_pixel_grid.update(|grid| event.apply(grid));
+        transport::send_clear_rect(x, y, width, height);
+        post_to_tabs(TabMessage::Event(event, Author::Local));
     };
 
     // Mouse event handlers
     let on_mouse_down = move |e: MouseEvent| {
+        if spectating.get_untracked() {
+            return;
+        }
         if let Some((x, y)) = mouse_to_pixel_coords(&e) {
+            if tool.get_untracked() == Tool::EraseArea {
+                preview_area((x, y), (x, y));
+                return;
+            }
             set_is_drawing.set(true);
+            session_stats.update(|stats| stats.record_stroke());
             draw_pixel(x, y);
         }
     };
 
     let on_mouse_move = move |e: MouseEvent| {
+        if spectating.get_untracked() {
+            return;
+        }
+        let cell = mouse_to_pixel_coords(&e);
+        if let Some((from, _)) = erase_area.get_value() {
+            if let Some(to) = cell {
+                preview_area(from, to);
+            }
+            return;
+        }
+        preview_brush(cell);
+        
         if is_drawing.get() {
-            if let Some((x, y)) = mouse_to_pixel_coords(&e) {
+            if let Some((x, y)) = cell {
                 draw_pixel(x, y);
             }
         }
@@ -125,40 +557,367 @@ fn DrawingCanvas(config: AppConfig) -> impl IntoView {
 
     let on_mouse_up = move |_: MouseEvent| {
         set_is_drawing.set(false);
+        if let Some((from, to)) = erase_area.get_value() {
+            erase_area.set_value(None);
+            preview_brush(None);
+            erase(from, to);
+        }
     };
 
     // Clear canvas function
-    let clear_canvas = move |_| {
-        set_pixel_grid.set(
-            vec![vec![false; config.pixel_grid_size]; config.pixel_grid_size]
-        );
+    let clear_canvas = move || {
+        record_event(DrawEvent::Clear);
+        set_pixel_grid.set(config.empty_grid());
+        session_stats.update(|stats| stats.record_clear());
         
         // Send clear command via WebSocket
-        send_clear_via_websocket();
+        transport::send_clear();
+        post_to_tabs(TabMessage::Event(DrawEvent::Clear, Author::Local));
+    };
+
+    let on_tab_message = move |message: TabMessage| match message {
+        TabMessage::Event(event, author) => {
+            apply_remote(event, author);
+            // The leader forwards followers' drawing to the Pico
+            if is_leader.get_untracked() && author == Author::Local {
+                match event {
+                    DrawEvent::Pixel { x, y, state } => transport::send_pixel(x, y, state),
+                    DrawEvent::Clear => transport::send_clear(),
+                    DrawEvent::ClearRect { x, y, width, height } => {
+                        transport::send_clear_rect(x, y, width, height)
+                    }
+                }
+            }
+        }
+        TabMessage::SyncRequest => {
+            if is_leader.get_untracked() {
+                post_to_tabs(TabMessage::State(share::encode_grid(&pixel_grid.get_untracked())));
+                post_to_tabs(TabMessage::Connection(connection.get_untracked()));
+            }
+        }
+        // Tabs on a different grid size can't take the canvas, decode_grid rejects it
+        TabMessage::State(payload) => {
+            if !is_leader.get_untracked() {
+                if let Some(grid) = share::decode_grid(&payload, config.grid_width, config.grid_height) {
+                    history.set(DrawHistory::new(grid.clone()));
+                    set_rewound_to.set(None);
+                    set_pixel_grid.set(grid);
+                }
+            }
+        }
+        TabMessage::Connection(state) => {
+            if !is_leader.get_untracked() {
+                set_connection.set(state);
+            }
+        }
+        TabMessage::Pico(text) => {
+            if !is_leader.get_untracked() {
+                on_message(Incoming::Text(text));
+            }
+        }
+        TabMessage::RoundTrip(sample) => {
+            if !is_leader.get_untracked() {
+                on_round_trip(sample);
+            }
+        }
+        TabMessage::Hello { client_id, width, height } => {
+            if !is_leader.get_untracked() {
+                on_hello(client_id, width, height);
+            }
+        }
+    };
+
+    tab_channel.set_value(TabChannel::open(on_tab_message));
+    if !is_leader.get_untracked() {
+        post_to_tabs(TabMessage::SyncRequest);
+    }
+    on_cleanup(move || {
+        tab_channel.with_value(|channel| {
+            if let Some(channel) = channel {
+                channel.close();
+            }
+        });
+    });
+
+    // Followers show the leader's connection
+    create_effect(move |_| {
+        let state = connection.get();
+        if is_leader.get_untracked() {
+            post_to_tabs(TabMessage::Connection(state));
+        }
+    });
+
+    // Take back everything one participant drew, then bring the Pico and the
+    // other tabs in line with the result
+    let undo_author = move |author: Author| {
+        if let Some(position) = rewound_to.get_untracked() {
+            history.update(|history| history.truncate(position));
+            set_rewound_to.set(None);
+        }
+        history.update(|history| history.remove_author(author));
+        let grid = history.with_untracked(|history| history.snapshot(history.len()));
+        transport::send_grid(&grid);
+        post_to_tabs(TabMessage::State(share::encode_grid(&grid)));
+        set_pixel_grid.set(grid);
+    };
+    // Session playback from the debug panel starts over from the recorded canvas
+    // and replays the recorded events as if they arrived now. The debug panel
+    // mirrors them to the Pico itself when asked to
+    let replay_reset = move |grid: Vec<Vec<bool>>| {
+        post_to_tabs(TabMessage::State(share::encode_grid(&grid)));
+        history.set(DrawHistory::new(grid.clone()));
+        set_rewound_to.set(None);
+        set_pixel_grid.set(grid);
+    };
+    let replay_event = move |(event, author): (DrawEvent, Author)| {
+        apply_remote(event, author);
+        post_to_tabs(TabMessage::Event(event, author));
+    };
+
+    let author_name = move |author: Author| match author {
+        Author::Local => translate(language.get(), Key::You).to_string(),
+        Author::Remote(0) => "Pico".to_string(),
+        Author::Remote(id) => translate(language.get(), Key::Participant).replace("{id}", &id.to_string()),
+    };
+
+    // Start watching from the frame the Pico holds, then follow its relayed drawing
+    let on_spectate = move |ev: ev::Event| {
+        let spectate = event_target_checked(&ev);
+        set_spectating.set(spectate);
+        set_is_drawing.set(false);
+        if is_leader.get_untracked() && connection.get_untracked() == ConnectionState::Connected {
+            transport::send_register(spectate);
+        }
+        if spectate {
+            preview_brush(None);
+            transport::send_get_frame();
+        }
+    };
+
+    // Put the rendered canvas on the clipboard, flashing the outcome for a moment
+    let copy_image = move |_| {
+        let Some(canvas) = canvas_ref.get_untracked() else {
+            return;
+        };
+        let canvas = canvas.unchecked_ref::<HtmlCanvasElement>().clone();
+
+        spawn_local(async move {
+            let status = match clipboard::copy_canvas(canvas).await {
+                Ok(()) => Key::ImageCopied,
+                Err(e) => {
+                    log::error!("Failed to copy canvas: {:?}", e);
+                    Key::CopyFailed
+                }
+            };
+            set_copy_status.set(Some(status));
+            set_timeout(move || set_copy_status.set(None), std::time::Duration::from_secs(2));
+        });
+    };
+
+    // Rewind or fast-forward the canvas through the drawing history
+    let on_scrub = move |ev: ev::Event| {
+        let Ok(position) = event_target_value(&ev).parse::<usize>() else {
+            return;
+        };
+        let len = history.with_untracked(|history| history.len());
+        set_rewound_to.set((position < len).then_some(position));
+        set_pixel_grid.set(history.with_untracked(|history| history.snapshot(position)));
+    };
+    let timeline_position = move || {
+        rewound_to.get().unwrap_or_else(|| history.with(|history| history.len()))
+    };
+
+    // Encode the grid into the URL fragment and show the resulting link
+    let share_canvas = move |_| {
+        let link = share::share_grid(&pixel_grid.get_untracked());
+        if link.is_none() {
+            log::error!("Failed to build share link");
+        }
+        set_share_failed.set(link.is_none());
+        set_share_link.set(link);
     };
 
     view! {
         <div class="drawing-container">
+            <div class="status-bar" class:tour-highlight=tour::highlighted(TourStep::Connection)>
+                <span
+                    class=move || connection.get().css_class()
+                    class:slow=is_slow
+                >
+                    {move || translate(language.get(), connection.get().label())}
+                </span>
+            </div>
+
             <div class="controls">
-                <button on:click=clear_canvas>"Clear"</button>
+                <button
+                    class:tour-highlight=tour::highlighted(TourStep::Clear)
+                    prop:disabled=move || spectating.get()
+                    on:click=move |_| clear_canvas()
+                >
+                    {t(Key::Clear)}
+                </button>
+                <button
+                    prop:disabled=move || spectating.get() || connection.get() != ConnectionState::Connected
+                    on:click=move |_| transport::send_grid(&pixel_grid.get_untracked())
+                >
+                    {t(Key::ResyncPico)}
+                </button>
+                <button on:click=share_canvas>{t(Key::Share)}</button>
+                <button on:click=copy_image>{t(Key::CopyImage)}</button>
+                <button on:click=move |_| set_show_qr.update(|show| *show = !*show)>{t(Key::Qr)}</button>
+                <label class="toggle">
+                    <input type="checkbox"
+                        prop:checked=move || inverted.get()
+                        on:change=move |ev| set_inverted.set(event_target_checked(&ev))
+                    />
+                    {t(Key::InvertColors)}
+                </label>
+                <label>
+                    {t(Key::Tool)} " "
+                    <select
+                        prop:disabled=move || spectating.get()
+                        on:change=move |ev| {
+                            if let Some(tool) = Tool::from_code(&event_target_value(&ev)) {
+                                set_tool.set(tool);
+                            }
+                        }
+                    >
+                        {Tool::ALL.into_iter().map(|choice| view! {
+                            <option value=choice.code() prop:selected=move || tool.get() == choice>
+                                {t(choice.label())}
+                            </option>
+                        }).collect_view()}
+                    </select>
+                </label>
+                <label>
+                    {t(Key::Ink)} " "
+                    <select
+                        prop:disabled=move || spectating.get() || tool.get() != Tool::Pen
+                        on:change=move |ev| {
+                            if let Ok(level) = event_target_value(&ev).parse() {
+                                set_ink.set(level);
+                            }
+                        }
+                    >
+                        {brush::INK_LEVELS.into_iter().map(|level| view! {
+                            <option value=level.to_string() prop:selected=move || ink.get() == level>
+                                {format!("{}%", level as u32 * 100 / (GRAY_LEVELS - 1) as u32)}
+                            </option>
+                        }).collect_view()}
+                    </select>
+                </label>
+                <label class="toggle">
+                    <input type="checkbox"
+                        prop:checked=move || spectating.get()
+                        on:change=on_spectate
+                    />
+                    {t(Key::Spectate)}
+                </label>
             </div>
+
+            <Show when=move || show_qr.get()>
+                <QrCodeView text=qr_text />
+            </Show>
+
+            {move || pico_grid.get()
+                .filter(|&(width, height)| width < config.grid_width || height < config.grid_height)
+                .map(|(width, height)| view! {
+                    <p class="error">{move || i18n::format_size(
+                        translate(language.get(), Key::GridCropped),
+                        width,
+                        height,
+                    )}</p>
+                })}
+
+            {move || copy_status.get().map(|status| view! {
+                <p class="copy-status">{t(status)}</p>
+            })}
+
+            <Show when=move || share_failed.get()>
+                <p class="error">{t(Key::ShareFailed)}</p>
+            </Show>
+
+            {move || share_link.get().map(|link| view! {
+                <input class="share-link" readonly=true value=link />
+            })}
             
-            <div class="canvas-container">
+            <div class="canvas-container" class:tour-highlight=tour::highlighted(TourStep::Canvas)>
                 <canvas
-                    class="drawing-canvas"
+                    class=move || if spectating.get() { "drawing-canvas spectating" } else { "drawing-canvas" }
                     _ref=canvas_ref
-                    width=config.canvas_size.to_string()
-                    height=config.canvas_size.to_string()
+                    width=config.canvas_width.to_string()
+                    height=config.canvas_height.to_string()
                     on:mousedown=on_mouse_down
                     on:mousemove=on_mouse_move
                     on:mouseup=on_mouse_up
-                    on:mouseleave=move |_| set_is_drawing.set(false)
+                    on:mouseleave=move |_| {
+                        set_is_drawing.set(false);
+                        erase_area.set_value(None);
+                        preview_brush(None);
+                    }
+                />
+                <canvas
+                    class="overlay-canvas"
+                    _ref=overlay_ref
+                    width=config.canvas_width.to_string()
+                    height=config.canvas_height.to_string()
+                />
+            </div>
+
+            <div class="timeline">
+                <label>{t(Key::Timeline)}</label>
+                <input type="range" min="0"
+                    prop:max=move || history.with(|history| history.len())
+                    prop:value=timeline_position
+                    prop:disabled=move || spectating.get() || history.with(|history| history.is_empty())
+                    on:input=on_scrub
                 />
+                <span>{move || format!(
+                    "{} / {}",
+                    timeline_position(),
+                    history.with(|history| history.len()),
+                )}</span>
             </div>
             
+            // Only worth showing once someone else has drawn
+            <Show when=move || history.with(|history| history.authors().iter().any(|author| *author != Author::Local))>
+                <fieldset class="participants">
+                    <legend>
+                        {t(Key::Participants)}
+                        {move || client_id.get().map(|id| format!(" (#{})", id))}
+                    </legend>
+                    {move || history.with(|history| history.authors()).into_iter().map(|author| view! {
+                        <div class="participant">
+                            <label>
+                                <input type="checkbox"
+                                    prop:checked=move || highlighted.get() == Some(author)
+                                    on:change=move |ev| set_highlighted.set(
+                                        event_target_checked(&ev).then_some(author)
+                                    )
+                                />
+                                {move || author_name(author)}
+                            </label>
+                            <button
+                                prop:disabled=move || spectating.get()
+                                on:click=move |_| undo_author(author)
+                            >
+                                {t(Key::UndoStrokes)}
+                            </button>
+                        </div>
+                    }).collect_view()}
+                </fieldset>
+            </Show>
+            
             <div class="info">
-                <p>"Resolution: " {config.pixel_grid_size} "x" {config.pixel_grid_size} " pixels"</p>
-                <p>"Pixels drawn: " {move || {
+                {move || round_trip_ms.get().map(|ms| view! {
+                    <p class:error=is_slow>{t(Key::RoundTrip)} {format!("{:.0} ms", ms)}</p>
+                })}
+                <p>{move || i18n::format_size(
+                    translate(language.get(), Key::Resolution),
+                    config.grid_width,
+                    config.grid_height,
+                )}</p>
+                <p>{t(Key::PixelsDrawn)} {move || {
                     let grid = pixel_grid.get();
                     let mut count = 0;
                     for row in grid.iter() {
@@ -169,122 +928,37 @@ fn DrawingCanvas(config: AppConfig) -> impl IntoView {
                     count
                 }}</p>
             </div>
+
+            <PicoStatusPanel status=pico_status rssi_history=rssi_history />
+            <StatsPanel />
+            <CollectPanel grid=pixel_grid on_saved=move |_| clear_canvas() />
+            <DebugPanel
+                grid=pixel_grid
+                pico_url=config.pico_url
+                corrupt_messages=corrupt_messages
+                latency=latency
+                on_replay_reset=replay_reset
+                on_replay_event=replay_event
+            />
         </div>
     }
 }
 
-// WebSocket setup and management functions
-fn setup_websocket(pico_url: &str) {
-    use wasm_bindgen::closure::Closure;
-    
-    // Close existing connection if any
-    WS_CONNECTION.with(|ws_conn| {
-        if let Some(ws) = ws_conn.borrow().as_ref() {
-            let _ = ws.close();
-        }
-        *ws_conn.borrow_mut() = None;
-    });
-    
-    log::info!("Connecting to WebSocket at ws://{}:80", pico_url);
-    
-    // Create WebSocket connection
-    let ws_url = format!("ws://{}:80/ws", pico_url);
-    let ws = match WebSocket::new(&ws_url) {
-        Ok(ws) => ws,
-        Err(e) => {
-            log::error!("Failed to create WebSocket: {:?}", e);
-            return;
-        }
-    };
-    
-    // Set binary type to arraybuffer for efficient binary messages
-    ws.set_binary_type(web_sys::BinaryType::Arraybuffer);
-    
-    // Setup onopen handler
-    let onopen = Closure::wrap(Box::new(move |_| {
-        log::info!("WebSocket connected!");
-    }) as Box<dyn FnMut(JsValue)>);
-    ws.set_onopen(Some(onopen.as_ref().unchecked_ref()));
-    onopen.forget();
-    
-    // Setup onclose handler
-    let onclose = Closure::wrap(Box::new(move |e: CloseEvent| {
-        log::warn!("WebSocket closed: code={}, reason={}", e.code(), e.reason());
-        
-        // Clear connection
-        WS_CONNECTION.with(|ws_conn| {
-            *ws_conn.borrow_mut() = None;
-        });
-    }) as Box<dyn FnMut(CloseEvent)>);
-    ws.set_onclose(Some(onclose.as_ref().unchecked_ref()));
-    onclose.forget();
-    
-    // Setup onerror handler
-    let onerror = Closure::wrap(Box::new(move |e: ErrorEvent| {
-        log::error!("WebSocket error: {:?}", e);
-    }) as Box<dyn FnMut(ErrorEvent)>);
-    ws.set_onerror(Some(onerror.as_ref().unchecked_ref()));
-    onerror.forget();
-    
-    // Setup onmessage handler (for potential server messages)
-    let onmessage = Closure::wrap(Box::new(move |e: MessageEvent| {
-        log::debug!("Received message from server: {:?}", e.data());
-    }) as Box<dyn FnMut(MessageEvent)>);
-    ws.set_onmessage(Some(onmessage.as_ref().unchecked_ref()));
-    onmessage.forget();
-    
-    // Store connection
-    WS_CONNECTION.with(|ws_conn| {
-        *ws_conn.borrow_mut() = Some(ws);
-    });
-}
-
-fn send_pixel_via_websocket(x: usize, y: usize, state: bool) {
-    WS_CONNECTION.with(|ws_conn| {
-        if let Some(ws) = ws_conn.borrow().as_ref() {
-            if ws.ready_state() == WebSocket::OPEN {
-                // Create binary message: [x, y, state]
-                let message = [x as u8, y as u8, if state { 1u8 } else { 0u8 }];
-                
-                match ws.send_with_u8_array(&message) {
-                    Ok(_) => {
-                        log::debug!("Sent pixel: ({}, {}) = {}", x, y, state);
-                    }
-                    Err(e) => {
-                        log::error!("Failed to send pixel: {:?}", e);
-                    }
-                }
-            } else {
-                log::warn!("WebSocket not open, cannot send pixel");
-            }
-        }
-    });
-}
+#[component]
+pub fn App(config: AppConfig) -> impl IntoView {
+    i18n::provide_i18n();
+    let language = use_language();
+    let settings = settings::provide_settings(&config);
+    stats::provide_stats();
+    tabs::provide_leadership();
+    tour::provide_tour();
 
-fn send_clear_via_websocket() {
-    WS_CONNECTION.with(|ws_conn| {
-        if let Some(ws) = ws_conn.borrow().as_ref() {
-            if ws.ready_state() == WebSocket::OPEN {
-                // Send clear command: [255, 255, 2]
-                let message = [255u8, 255u8, 2u8];
-                
-                match ws.send_with_u8_array(&message) {
-                    Ok(_) => {
-                        log::info!("Sent clear command");
-                    }
-                    Err(e) => {
-                        log::error!("Failed to send clear command: {:?}", e);
-                    }
-                }
-            } else {
-                log::warn!("WebSocket not open, cannot send clear command");
-            }
-        }
+    // Changing the grid preset remounts the canvas with a fresh grid and connection
+    let active_config = create_memo(move |_| {
+        let (width, height) = settings.get().grid.dimensions();
+        config.with_grid(width, height)
     });
-}
 
-#[component]
-pub fn App(config: AppConfig) -> impl IntoView {
     view! {
         <div class="app">
             <style>
@@ -322,10 +996,26 @@ pub fn App(config: AppConfig) -> impl IntoView {
                 
                 .canvas-container {
                     display: inline-block;
+                    position: relative;
                     border: 2px solid #333;
                     border-radius: 4px;
                 }
                 
+                .drawing-canvas {
+                    display: block;
+                }
+                
+                .drawing-canvas.spectating {
+                    cursor: default;
+                }
+                
+                .overlay-canvas {
+                    position: absolute;
+                    left: 0;
+                    top: 0;
+                    pointer-events: none;
+                }
+                
                 .info {
                     margin-top: 15px;
                     color: #666;
@@ -335,13 +1025,206 @@ pub fn App(config: AppConfig) -> impl IntoView {
                 .info p {
                     margin: 5px 0;
                 }
+                
+                .tour-highlight {
+                    outline: 3px solid #4285f4;
+                    outline-offset: 3px;
+                    border-radius: 4px;
+                }
+                
+                .tour-popup {
+                    position: fixed;
+                    left: 50%;
+                    bottom: 20px;
+                    transform: translateX(-50%);
+                    max-width: 360px;
+                    padding: 12px 16px;
+                    background: #fff;
+                    border: 1px solid #4285f4;
+                    border-radius: 6px;
+                    box-shadow: 0 2px 10px rgba(0, 0, 0, 0.2);
+                    z-index: 10;
+                }
+                
+                .tour-buttons {
+                    display: flex;
+                    justify-content: flex-end;
+                    gap: 8px;
+                }
+                
+                .status-bar {
+                    margin-bottom: 10px;
+                }
+                
+                .connection-badge {
+                    display: inline-block;
+                    padding: 2px 10px;
+                    border-radius: 10px;
+                    font-size: 12px;
+                    color: #fff;
+                }
+                
+                .connection-badge.connecting { background: #f0ad4e; }
+                .connection-badge.connected { background: #4CAF50; }
+                .connection-badge.disconnected { background: #d9534f; }
+                .connection-badge.offline { background: #777; }
+                .connection-badge.incompatible { background: #8e44ad; }
+                .connection-badge.unauthorized { background: #8e44ad; }
+                .connection-badge.slow { background: #d9534f; }
+                
+                .qr-code {
+                    margin-bottom: 10px;
+                }
+                
+                .qr-caption {
+                    font-size: 12px;
+                    color: #666;
+                    word-break: break-all;
+                }
+                
+                .settings-panel {
+                    margin-bottom: 15px;
+                    text-align: left;
+                    font-size: 13px;
+                }
+                
+                .settings-panel label {
+                    display: block;
+                    margin: 4px 0;
+                }
+                
+                .participants {
+                    margin-top: 15px;
+                    text-align: left;
+                    font-size: 13px;
+                }
+                
+                .participant {
+                    display: flex;
+                    justify-content: space-between;
+                    align-items: center;
+                    margin: 4px 0;
+                }
+                
+                .stats-panel {
+                    margin-top: 15px;
+                    text-align: left;
+                    font-size: 13px;
+                }
+                
+                .stats-panel p {
+                    margin: 2px 0;
+                }
+                
+                .stats-panel table {
+                    border-collapse: collapse;
+                    margin-top: 6px;
+                }
+                
+                .stats-panel th, .stats-panel td {
+                    padding: 2px 8px;
+                    border-bottom: 1px solid #eee;
+                }
+                
+                .status-panel {
+                    margin-top: 15px;
+                    text-align: left;
+                    font-size: 13px;
+                }
+                
+                .status-panel p {
+                    margin: 2px 0;
+                }
+                
+                .collect-panel {
+                    margin-top: 15px;
+                    text-align: left;
+                    font-size: 13px;
+                }
+                
+                .collect-prompt {
+                    font-size: 18px;
+                    font-weight: bold;
+                }
+                
+                .collect-panel th, .collect-panel td {
+                    padding: 2px 6px;
+                    text-align: center;
+                }
+                
+                .debug-panel {
+                    margin-top: 15px;
+                    text-align: left;
+                    font-size: 13px;
+                }
+                
+                .debug-section label {
+                    display: block;
+                    margin: 4px 0;
+                }
+                
+                .compression-table td, .compression-table th {
+                    padding: 2px 8px;
+                    text-align: right;
+                }
+                
+                .debug-section input {
+                    width: 80px;
+                }
+                
+                .language-picker {
+                    float: right;
+                    font-size: 13px;
+                }
+                
+                .copy-status {
+                    color: #4CAF50;
+                    font-size: 13px;
+                }
+                
+                .error {
+                    color: #d9534f;
+                }
+                
+                .controls .toggle {
+                    display: flex;
+                    align-items: center;
+                    gap: 4px;
+                    font-size: 14px;
+                }
+                
+                .timeline {
+                    display: flex;
+                    align-items: center;
+                    gap: 8px;
+                    margin-top: 10px;
+                    font-size: 13px;
+                    color: #666;
+                }
+                
+                .timeline input {
+                    flex: 1;
+                }
+                
+                .share-link {
+                    width: 100%;
+                    margin-bottom: 10px;
+                    font-size: 12px;
+                }
                 "
             </style>
             
-            <h1>"Doodle-RS"</h1>
-            <p>"Draw on the canvas below. Each square represents a pixel on your 48x48 OLED display."</p>
+            <LanguagePicker />
+            <h1>{t(Key::Title)}</h1>
+            <p>{move || i18n::format_size(
+                translate(language.get(), Key::Intro),
+                active_config.get().grid_width,
+                active_config.get().grid_height,
+            )}</p>
             
-            <DrawingCanvas config=config/>
+            <SettingsPanel />
+            <TourPopup />
+            {move || view! { <DrawingCanvas config=active_config.get()/> }}
         </div>
     }
 }
\ No newline at end of file